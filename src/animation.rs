@@ -0,0 +1,250 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! LED animations, played by a `Communicator` as `LED` frames while it ticks.
+//!
+//! An animation runs one of the built-in effects, or a keyframe timeline, over a period of
+//! `period_ms`, once or looping until stopped. Frames are sent no faster than `max_fps`, nor
+//! than the link allows: at most `LINK_SHARE` of the baud rate goes to frames and their `ACK`s,
+//! leaving the rest to heartbeats and other requests. Frames identical to the last one sent are
+//! skipped.
+
+use std::f32::consts::TAU;
+use std::fmt::Display;
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+
+/// Default upper bound on the frame rate.
+pub const MAX_FPS: f32 = 30.0;
+/// Share of the link bandwidth that frames may take up.
+pub const LINK_SHARE: f64 = 0.5;
+/// Bits on the wire per byte, i.e. 8N1 framing with start and stop bits.
+const BITS_PER_BYTE: u64 = 10;
+
+/// Colours of all LEDs at one point of a timeline.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub at_ms: u64,
+    /// One colour for all LEDs, or a pattern repeated across them.
+    #[serde(deserialize_with = "color::deserialize_colors")]
+    pub colors: Vec<u32>,
+}
+
+const fn _default_width() -> usize {
+    1
+}
+
+/// What an animation shows over each period.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "effect", rename_all = "lowercase")]
+pub enum Effect {
+    /// Colours interpolated linearly between keyframes, held before the first and after the last.
+    Keyframes { keyframes: Vec<Keyframe> },
+    /// All LEDs from `from` to `to`.
+    Fade {
+        #[serde(deserialize_with = "color::deserialize_color")]
+        from: u32,
+        #[serde(deserialize_with = "color::deserialize_color")]
+        to: u32,
+    },
+    /// All LEDs from off up to `color` and back, smoothly.
+    Pulse {
+        #[serde(deserialize_with = "color::deserialize_color")]
+        color: u32,
+    },
+    /// All hues spread across the LEDs, turning once.
+    Rainbow,
+    /// `width` LEDs of `color` running once along the others, of `background`.
+    Chase {
+        #[serde(deserialize_with = "color::deserialize_color")]
+        color: u32,
+        #[serde(default, deserialize_with = "color::deserialize_color")]
+        background: u32,
+        #[serde(default = "_default_width")]
+        width: usize,
+    },
+}
+
+/// An effect over `period_ms`, e.g. `{"effect":"pulse","color":"red","period_ms":1000,"loop":true}`
+/// as a `TypedRequest`, or `ANIMATE PULSE red OVER 1000 LOOP` in the text grammar.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Animation {
+    #[serde(flatten)]
+    pub effect: Effect,
+    pub period_ms: u64,
+    /// Whether to start over after each period until stopped, rather than stop after one.
+    #[serde(default, rename = "loop", skip_serializing_if = "std::ops::Not::not")]
+    pub looping: bool,
+    /// Number of LEDs. Defaults to that of the board mirror, or 1 if it has none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leds: Option<usize>,
+    /// Upper bound on the frame rate. Defaults to `MAX_FPS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fps: Option<f32>,
+}
+
+/// `a` to `b` by `f` within `[0, 1]`, channel by channel.
+fn _mix(a: u32, b: u32, f: f32) -> u32 {
+    let (a, b) = (color::to_rgb(a), color::to_rgb(b));
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let channel = |a: u8, b: u8| (f32::from(b) - f32::from(a)).mul_add(f, f32::from(a)).round().clamp(0.0, 255.0) as u8;
+    color::from_rgb(channel(a.0, b.0), channel(a.1, b.1), channel(a.2, b.2))
+}
+
+/// `colors` repeated across `leds` LEDs.
+fn _spread(colors: &[u32], leds: usize) -> Vec<u32> {
+    colors.iter().copied().cycle().take(if colors.is_empty() { 0 } else { leds }).collect()
+}
+
+impl Animation {
+    /// Runs `effect` once over `period`, on as many LEDs as the board has.
+    #[must_use]
+    pub fn new(effect: Effect, period: Duration) -> Self {
+        Self {
+            effect,
+            period_ms: u64::try_from(period.as_millis()).unwrap_or(u64::MAX),
+            looping: false,
+            leds: None,
+            max_fps: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_leds(mut self, leds: usize) -> Self {
+        self.leds = Some(leds);
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_fps(mut self, fps: f32) -> Self {
+        self.max_fps = Some(fps);
+        self
+    }
+
+    /// Checks that the animation can be played.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` if the period or a number of LEDs is 0, `max_fps` is not
+    /// positive, keyframes are empty, out of order, past the period or without colours, or a
+    /// chase has a width of 0.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        if self.period_ms == 0 {
+            return invalid("Expected positive period");
+        }
+        if matches!(self.leds, Some(0 | 256..)) {
+            return invalid("Expected between 1 and 255 LEDs");
+        }
+        if self.max_fps.is_some_and(|fps| !(fps.is_finite() && fps > 0.0)) {
+            return invalid("Expected positive frame rate");
+        }
+        match &self.effect {
+            Effect::Keyframes { keyframes } => {
+                if keyframes.is_empty() || keyframes.iter().any(|k| k.colors.is_empty()) {
+                    return invalid("Expected keyframes with colours");
+                }
+                if keyframes.windows(2).any(|k| k[0].at_ms > k[1].at_ms) {
+                    return invalid("Expected keyframes in order");
+                }
+                if keyframes.iter().any(|k| k.at_ms > self.period_ms) {
+                    return invalid("Expected keyframes within the period");
+                }
+            },
+            Effect::Chase { width: 0, .. } => return invalid("Expected positive chase width"),
+            _ => (),
+        }
+        return Ok(());
+    }
+
+    /// Whether the animation is over at `elapsed` from its start.
+    #[must_use]
+    pub fn is_done(&self, elapsed: Duration) -> bool {
+        !self.looping && elapsed.as_millis() >= u128::from(self.period_ms)
+    }
+
+    /// Colours of `leds` LEDs at `elapsed` from the start.
+    #[must_use]
+    pub fn frame_at(&self, elapsed: Duration, leds: usize) -> Vec<u32> {
+        let period = u128::from(self.period_ms.max(1));
+        let ms = if self.looping { elapsed.as_millis() % period } else { elapsed.as_millis().min(period) };
+        #[allow(clippy::cast_precision_loss)]
+        let phase = ms as f32 / period as f32;
+
+        match &self.effect {
+            Effect::Keyframes { keyframes } => {
+                let i = keyframes.partition_point(|k| u128::from(k.at_ms) <= ms);
+                let (Some(prev), Some(next)) = (keyframes.get(i.saturating_sub(1)), keyframes.get(i).or_else(|| keyframes.last())) else {
+                    return Vec::new();
+                };
+                let (a, b) = (_spread(&prev.colors, leds), _spread(&next.colors, leds));
+                let span = next.at_ms.saturating_sub(prev.at_ms);
+                #[allow(clippy::cast_precision_loss)]
+                let f = if i == 0 || span == 0 { 1.0 } else { (ms - u128::from(prev.at_ms)) as f32 / span as f32 };
+                a.iter().zip(&b).map(|(&a, &b)| _mix(a, b, f)).collect()
+            },
+            Effect::Fade { from, to } => vec![_mix(*from, *to, phase); leds],
+            Effect::Pulse { color } => vec![_mix(0, *color, (1.0 - (TAU * phase).cos()) / 2.0); leds],
+            Effect::Rainbow => (0..leds)
+                .map(|i| {
+                    #[allow(clippy::cast_precision_loss)]
+                    let offset = i as f32 / leds as f32;
+                    color::from_hsv(360.0 * (offset + phase).fract(), 1.0, 1.0)
+                })
+                .collect(),
+            Effect::Chase { color, background, width } => {
+                // => Head runs from the first LED to past the last, so that the tail leaves too
+                #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let head = (phase * (leds + width) as f32) as usize;
+                (0..leds).map(|i| if i < head && i + width >= head { *color } else { *background }).collect()
+            },
+        }
+    }
+
+    /// Interval between frames of `leds` LEDs, as per `max_fps` and, if known, the `baud` rate of
+    /// the link. Each frame takes `1 + 3 * leds` bytes, and 1 more for its `ACK`.
+    #[must_use]
+    pub fn frame_interval(&self, leds: usize, baud: Option<u32>) -> Duration {
+        let fps = Duration::from_secs_f64(1.0 / f64::from(self.max_fps.unwrap_or(MAX_FPS)));
+        let Some(baud) = baud.filter(|b| *b > 0) else { return fps; };
+        let bits = (2 + 3 * leds as u64) * BITS_PER_BYTE;
+        #[allow(clippy::cast_precision_loss)]
+        let link = Duration::from_secs_f64(bits as f64 / f64::from(baud) / LINK_SHARE);
+        fps.max(link)
+    }
+}
+
+impl Display for Animation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ANIMATE")?;
+        match &self.effect {
+            Effect::Keyframes { keyframes } => {
+                write!(f, " KEYFRAMES")?;
+                for k in keyframes {
+                    write!(f, " AT {}", k.at_ms)?;
+                    for c in &k.colors { write!(f, " #{c:06x}")?; }
+                }
+            },
+            Effect::Fade { from, to } => write!(f, " FADE #{from:06x} #{to:06x}")?,
+            Effect::Pulse { color } => write!(f, " PULSE #{color:06x}")?,
+            Effect::Rainbow => write!(f, " RAINBOW")?,
+            Effect::Chase { color, background, .. } => write!(f, " CHASE #{color:06x} #{background:06x}")?,
+        }
+        write!(f, " OVER {}", self.period_ms)?;
+        if self.looping { write!(f, " LOOP")?; }
+        if let Some(n) = self.leds { write!(f, " LEDS {n}")?; }
+        if let Some(fps) = self.max_fps { write!(f, " FPS {fps}")?; }
+        if let Effect::Chase { width, .. } = self.effect {
+            if width != 1 { write!(f, " WIDTH {width}")?; }
+        }
+        return Ok(());
+    }
+}
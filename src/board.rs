@@ -0,0 +1,126 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Host-side mirror of the state of the board, i.e. its magnet cells and LED colours.
+//!
+//! A `Communicator` applies each `MAGNET`, `LED` and `QUIT` to its `BoardState` once the Arduino
+//! acknowledges it, and resyncs from the firmware with `STATE` after reconnecting. The firmware
+//! replies to `STATE` with its opcode, the number of magnet cells as `u8` followed by the cells as
+//! encoded for `MAGNET`, then the number of LEDs as `u8` followed by the colours as encoded for
+//! `LED`.
+//!
+//! `MAGNET_DELTA` and `LED_DELTA` update single entries in place, each prefixed by its index as
+//! `u8`. Entries beyond the current number of cells or LEDs are ignored, as by the firmware.
+
+use serde::Serialize;
+
+use crate::{bindings, chunk, opcode};
+use crate::command::MagnetCell;
+
+/// Decodes `MAGNET` arguments into cells, ignoring any trailing partial cell.
+#[must_use]
+pub fn decode_cells(args: &[u8]) -> Vec<MagnetCell> {
+    args.chunks_exact(9)
+        .map(|c| MagnetCell {
+            x: f32::from_le_bytes([c[0], c[1], c[2], c[3]]),
+            y: f32::from_le_bytes([c[4], c[5], c[6], c[7]]),
+            on: c[8] != 0,
+        })
+        .collect()
+}
+
+/// Decodes `LED` arguments into `0xRRGGBB` colours, ignoring any trailing partial colour.
+#[must_use]
+pub fn decode_colors(args: &[u8]) -> Vec<u32> {
+    args.chunks_exact(3)
+        .map(|c| u32::from_be_bytes([0, c[0], c[1], c[2]]))
+        .collect()
+}
+
+/// Decodes `MAGNET_DELTA` arguments into indexed cells, ignoring any trailing partial entry.
+#[must_use]
+pub fn decode_magnet_delta(args: &[u8]) -> Vec<(u8, MagnetCell)> {
+    args.chunks_exact(10)
+        .map(|c| (c[0], decode_cells(&c[1..])[0]))
+        .collect()
+}
+
+/// Decodes `LED_DELTA` arguments into indexed colours, ignoring any trailing partial entry.
+#[must_use]
+pub fn decode_led_delta(args: &[u8]) -> Vec<(u8, u32)> {
+    args.chunks_exact(4)
+        .map(|c| (c[0], u32::from_be_bytes([0, c[1], c[2], c[3]])))
+        .collect()
+}
+
+/// Sets `entries[i] = v` for each `(i, v)` in `changes` within bounds.
+pub fn apply_delta<T: Copy>(entries: &mut [T], changes: &[(u8, T)]) {
+    for &(i, v) in changes {
+        if let Some(e) = entries.get_mut(usize::from(i)) { *e = v; }
+    }
+}
+
+/// Magnet cells and LED colours of the board, as last set.
+#[derive(Debug, Default, PartialEq, Clone, Serialize)]
+pub struct BoardState {
+    pub magnets: Vec<MagnetCell>,
+    pub leds: Vec<u32>,
+    /// Whether the state is known to match the firmware, i.e. it was read back with `STATE` or
+    /// reset with `QUIT`. Cleared on disconnect until resynced.
+    pub synced: bool,
+}
+
+impl BoardState {
+    /// Applies `instr` as acknowledged by the Arduino. Does nothing for instructions other than
+    /// `MAGNET`, `LED`, their deltas and `QUIT`.
+    pub fn apply(&mut self, instr: &[u8]) {
+        match instr.split_first() {
+            Some((&bindings::MAGNET, args)) => self.magnets = decode_cells(args),
+            Some((&bindings::LED, args))    => self.leds = decode_colors(args),
+            Some((&opcode::MAGNET_DELTA, args)) => apply_delta(&mut self.magnets, &decode_magnet_delta(args)),
+            Some((&opcode::LED_DELTA, args))    => apply_delta(&mut self.leds, &decode_led_delta(args)),
+            Some((&bindings::QUIT, _))      => *self = Self { synced: true, ..Self::default() },
+            _ => (),
+        }
+    }
+
+    /// Whether the Arduino acknowledges `instr` with `ACK`.
+    #[must_use]
+    pub fn is_acknowledged(instr: &[u8]) -> bool {
+        chunk::continued(instr).is_some() || matches!(
+            instr.first(),
+            Some(&(
+                bindings::MAGNET | bindings::LED | opcode::MAGNET_DELTA | opcode::LED_DELTA
+                    | opcode::STREAM | bindings::QUIT
+            ))
+        )
+    }
+
+    /// Encodes this state as the firmware replies to `STATE`.
+    #[must_use]
+    pub fn encode_reply(&self) -> Vec<u8> {
+        let mut reply = vec![opcode::STATE, u8::try_from(self.magnets.len()).unwrap_or(u8::MAX)];
+        for cell in self.magnets.iter().take(u8::MAX.into()) {
+            reply.extend_from_slice(&cell.x.to_le_bytes());
+            reply.extend_from_slice(&cell.y.to_le_bytes());
+            reply.push(cell.on.into());
+        }
+        reply.push(u8::try_from(self.leds.len()).unwrap_or(u8::MAX));
+        for rgb_int in self.leds.iter().take(u8::MAX.into()) {
+            reply.extend_from_slice(&rgb_int.to_be_bytes()[1..]);
+        }
+        return reply;
+    }
+
+    /// Decodes the payload of a `STATE` reply, i.e. without its opcode.
+    ///
+    /// ### Returns
+    /// The state, marked synced, or `None` if `payload` is malformed.
+    #[must_use]
+    pub fn decode_reply(payload: &[u8]) -> Option<Self> {
+        let (&n_magnets, rest) = payload.split_first()?;
+        let (cells, rest) = rest.split_at_checked(usize::from(n_magnets) * 9)?;
+        let (&n_leds, colors) = rest.split_first()?;
+        if colors.len() != usize::from(n_leds) * 3 { return None; }
+        Some(Self { magnets: decode_cells(cells), leds: decode_colors(colors), synced: true })
+    }
+}
@@ -0,0 +1,205 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Mapping of `MAGNET` coordinates from user units to the coordinates the firmware expects.
+//!
+//! A calibration is read from a JSON file of form:
+//! ```json
+//! {"unit": "cm", "transform": [[0.0, 1.0, 0.0], [-1.0, 0.0, 300.0]],
+//!  "grid": {"pitch": [25.0, 25.0], "origin": [12.5, 12.5]}, "snap": true}
+//! ```
+//! where every field is optional. Coordinates in `unit` are first converted to millimetres, with
+//! grid indices counting `pitch` millimetres from `origin`, then optionally snapped to the nearest
+//! magnet of the grid, and finally mapped to firmware coordinates by the affine `transform`, i.e.
+//! `[x', y'] = [[a, b], [c, d]] * [x, y] + [tx, ty]` for `[[a, b, tx], [c, d, ty]]`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{bindings, opcode};
+use crate::board::{decode_cells, decode_magnet_delta};
+use crate::command::{Command, MagnetCell};
+use crate::Instruction;
+
+/// Unit of user coordinates.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    #[default]
+    Mm,
+    Cm,
+    /// Index into the magnet grid, possibly fractional.
+    Grid,
+}
+
+/// 2D affine transform from millimetres to firmware coordinates, as `[[a, b, tx], [c, d, ty]]`.
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+pub struct Affine(pub [[f32; 3]; 2]);
+
+impl Default for Affine {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Affine {
+    pub const IDENTITY: Self = Self([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+
+    #[must_use]
+    pub const fn determinant(&self) -> f32 {
+        let [[a, b, _], [c, d, _]] = self.0;
+        a * d - b * c
+    }
+
+    #[must_use]
+    #[allow(clippy::many_single_char_names)]
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let [[a, b, tx], [c, d, ty]] = self.0;
+        (a.mul_add(x, b * y) + tx, c.mul_add(x, d * y) + ty)
+    }
+
+    /// Inverse of `Affine::apply`, assuming a non-zero determinant.
+    #[must_use]
+    #[allow(clippy::many_single_char_names)]
+    pub fn invert(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let [[a, b, tx], [c, d, ty]] = self.0;
+        let det = self.determinant();
+        let (x, y) = (x - tx, y - ty);
+        (d.mul_add(x, -b * y) / det, a.mul_add(y, -c * x) / det)
+    }
+}
+
+/// Physical magnet grid, in millimetres.
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+pub struct Grid {
+    /// Distance between neighbouring magnets along `x` and `y`.
+    pub pitch: (f32, f32),
+    /// Position of the magnet at grid index `(0, 0)`.
+    #[serde(default)]
+    pub origin: (f32, f32),
+}
+
+/// Coordinate frame of one rig.
+#[derive(Debug, PartialEq, Clone, Default, Deserialize)]
+pub struct Calibration {
+    #[serde(default)]
+    pub unit: Unit,
+    #[serde(default)]
+    pub transform: Affine,
+    /// Required by `Unit::Grid` and `snap`.
+    #[serde(default)]
+    pub grid: Option<Grid>,
+    /// Whether to move each cell to the nearest magnet of `grid`.
+    #[serde(default)]
+    pub snap: bool,
+}
+
+/// Maps the cells of `MAGNET` and `MAGNET_DELTA` instructions through `f`, passing others through.
+fn _map_cells(instr: &[u8], f: impl Fn(MagnetCell) -> MagnetCell) -> Instruction {
+    match instr.split_first() {
+        Some((&bindings::MAGNET, args)) => Command::Magnet {
+            cells: decode_cells(args).into_iter().map(f).collect(),
+        }.encode(),
+        Some((&opcode::MAGNET_DELTA, args)) => Command::MagnetDelta {
+            cells: decode_magnet_delta(args).into_iter().map(|(i, c)| (i, f(c))).collect(),
+        }.encode(),
+        _ => instr.to_vec(),
+    }
+}
+
+impl Calibration {
+    /// Reads a calibration from the JSON file at `path`.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::InvalidData` if the file is not valid JSON of the form above, the
+    ///   transform cannot be inverted, or the grid is missing or invalid when required.
+    /// - Any other `io::Error` from reading the file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let calibration: Self = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        calibration.validate()?;
+        return Ok(calibration);
+    }
+
+    /// Checks that the calibration can be used, as required by `Calibration::load`.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidData` if the transform cannot be inverted, or the grid is missing
+    /// or invalid when required.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        let det = self.transform.determinant();
+        if !det.is_normal() || self.transform.0.iter().flatten().any(|v| !v.is_finite()) {
+            return invalid("Expected finite, invertible transform");
+        }
+        let positive = |v: f32| v.is_finite() && v > 0.0;
+        match self.grid {
+            Some(g) if !positive(g.pitch.0) || !positive(g.pitch.1) => {
+                return invalid("Expected positive grid pitch");
+            },
+            None if self.unit == Unit::Grid || self.snap => {
+                return invalid("Expected grid for grid units or snapping");
+            },
+            _ => {},
+        }
+        return Ok(());
+    }
+
+    fn _to_mm(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        match (self.unit, self.grid) {
+            (Unit::Mm, _) | (Unit::Grid, None) => (x, y),
+            (Unit::Cm, _) => (x * 10.0, y * 10.0),
+            (Unit::Grid, Some(g)) => (x.mul_add(g.pitch.0, g.origin.0), y.mul_add(g.pitch.1, g.origin.1)),
+        }
+    }
+
+    fn _from_mm(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        match (self.unit, self.grid) {
+            (Unit::Mm, _) | (Unit::Grid, None) => (x, y),
+            (Unit::Cm, _) => (x / 10.0, y / 10.0),
+            (Unit::Grid, Some(g)) => ((x - g.origin.0) / g.pitch.0, (y - g.origin.1) / g.pitch.1),
+        }
+    }
+
+    /// Moves `cell` to the nearest magnet of the grid, if snapping.
+    #[must_use]
+    pub fn snap(&self, cell: MagnetCell) -> MagnetCell {
+        let Some(g) = self.grid.filter(|_| self.snap) else { return cell; };
+        let (x, y) = self._to_mm((cell.x, cell.y));
+        let x = ((x - g.origin.0) / g.pitch.0).round().mul_add(g.pitch.0, g.origin.0);
+        let y = ((y - g.origin.1) / g.pitch.1).round().mul_add(g.pitch.1, g.origin.1);
+        let (x, y) = self._from_mm((x, y));
+        MagnetCell { x, y, ..cell }
+    }
+
+    /// Maps `cell` from user units to firmware coordinates, without snapping.
+    #[must_use]
+    pub fn to_firmware(&self, cell: MagnetCell) -> MagnetCell {
+        let (x, y) = self.transform.apply(self._to_mm((cell.x, cell.y)));
+        MagnetCell { x, y, ..cell }
+    }
+
+    /// Maps `cell` from firmware coordinates back to user units.
+    #[must_use]
+    pub fn from_firmware(&self, cell: MagnetCell) -> MagnetCell {
+        let (x, y) = self._from_mm(self.transform.invert((cell.x, cell.y)));
+        MagnetCell { x, y, ..cell }
+    }
+
+    /// Snaps the cells of `MAGNET` and `MAGNET_DELTA` instructions, keeping them in user units.
+    #[must_use]
+    pub fn snap_instruction(&self, instr: &[u8]) -> Instruction {
+        if !self.snap { return instr.to_vec(); }
+        _map_cells(instr, |c| self.snap(c))
+    }
+
+    /// Encodes the cells of `MAGNET` and `MAGNET_DELTA` instructions in firmware coordinates.
+    /// Other instructions pass through unchanged.
+    #[must_use]
+    pub fn to_firmware_instruction(&self, instr: &[u8]) -> Instruction {
+        _map_cells(instr, |c| self.to_firmware(c))
+    }
+}
@@ -0,0 +1,86 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Splitting of instructions that exceed the receive buffer of the firmware into several frames
+//! of the same opcode, each of which is acknowledged on its own.
+//!
+//! `MAGNET_DELTA` and `LED_DELTA` entries are independent, so their chunks are simply sent in
+//! order. `MAGNET` and `LED` replace the whole board, so their chunks form one transfer: every
+//! chunk but the last has `opcode::CONTINUED` set on its opcode, telling the firmware to hold its
+//! cells or colours and append those of the next frame, and the last chunk is a plain `MAGNET`
+//! or `LED` frame that ends the transfer. The firmware must support `CONTINUED` for transfers of
+//! more than one frame, whereas instructions that fit one frame are sent unchanged.
+
+use std::io;
+
+use crate::{bindings, opcode};
+use crate::Instruction;
+
+/// Length of each argument entry of `opcode`, if its arguments can be split between entries.
+#[must_use]
+pub const fn entry_len(opcode: u8) -> Option<usize> {
+    match opcode {
+        bindings::MAGNET       => Some(9),
+        bindings::LED          => Some(3),
+        opcode::MAGNET_DELTA => Some(10),
+        opcode::LED_DELTA    => Some(4),
+        _ => None,
+    }
+}
+
+/// Length of the arguments of a full `opcode` frame of at most `max_len` bytes, i.e. up to the
+/// last whole entry. 0 if `opcode` cannot be split or not even one entry fits.
+#[must_use]
+pub const fn capacity(opcode: u8, max_len: usize) -> usize {
+    match entry_len(opcode) {
+        Some(n) => max_len.saturating_sub(1) / n * n,
+        None => 0,
+    }
+}
+
+/// Opcode of the `MAGNET` or `LED` transfer that `frame` is a chunk of, if it is to be continued
+/// by the next frame.
+#[must_use]
+pub const fn continued(frame: &[u8]) -> Option<u8> {
+    let Some(&opcode) = frame.first() else { return None; };
+    let transfer = opcode & !opcode::CONTINUED;
+    if opcode & opcode::CONTINUED == 0 || !matches!(transfer, bindings::MAGNET | bindings::LED) {
+        return None;
+    }
+    Some(transfer)
+}
+
+/// Splits `instr` into frames of at most `max_len` bytes each, as laid out above.
+///
+/// ### Returns
+/// The frames to write in order, i.e. `instr` alone if it is short enough.
+///
+/// # Errors
+/// `io::ErrorKind::InvalidInput` if `instr` exceeds `max_len` and cannot be split.
+pub fn split(instr: &[u8], max_len: usize) -> io::Result<Vec<Instruction>> {
+    let Some((&opcode, args)) = instr.split_first() else { return Ok(vec![instr.to_vec()]); };
+    let capacity = capacity(opcode, max_len);
+    if instr.len() <= max_len { return Ok(vec![instr.to_vec()]); }
+    if capacity == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Instruction of {} bytes exceeds maximum frame of {max_len} bytes", instr.len())
+        ));
+    }
+
+    let chunk_opcode = match opcode {
+        bindings::MAGNET | bindings::LED => opcode | opcode::CONTINUED,
+        _ => opcode,
+    };
+    let n_frames = args.len().div_ceil(capacity);
+    let frames = args
+        .chunks(capacity)
+        .enumerate()
+        .map(|(i, c)| {
+            let mut frame = Vec::with_capacity(c.len() + 1);
+            frame.push(if i + 1 < n_frames { chunk_opcode } else { opcode });
+            frame.extend_from_slice(c);
+            frame
+        })
+        .collect();
+    return Ok(frames);
+}
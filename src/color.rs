@@ -0,0 +1,375 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! LED colours, parsed from text and corrected for brightness and gamma before encoding.
+//!
+//! ## Accepted forms
+//! - `#RRGGBB` or `0xRRGGBB`, e.g. `#ff8000`.
+//! - CSS colour names, e.g. `orange` or `RebeccaPurple`.
+//! - `rgb(r, g, b)`, each channel within `[0, 255]` or a percentage, e.g. `rgb(255, 50%, 0)`.
+//! - `hsv(h, s, v)`, with hue in degrees within `[0, 360]` and saturation and value within
+//!   `[0, 1]` or as percentages, e.g. `hsv(30, 100%, 1)`.
+//! - Decimal `0xRRGGBB` integers, e.g. `16744448`.
+//!
+//! Values out of range are rejected rather than truncated.
+
+use std::fmt::Display;
+use std::io;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{bindings, opcode, Instruction};
+
+/// Largest colour, i.e. white.
+pub const MAX: u32 = 0x00FF_FFFF;
+
+/// CSS colour names with their `0xRRGGBB` values.
+#[allow(clippy::unreadable_literal)]
+pub const NAMES: [(&str, u32); 148] = [
+    ("aliceblue", 0xF0F8FF), ("antiquewhite", 0xFAEBD7), ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4), ("azure", 0xF0FFFF), ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4), ("black", 0x000000), ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF), ("blueviolet", 0x8A2BE2), ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887), ("cadetblue", 0x5F9EA0), ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E), ("coral", 0xFF7F50), ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC), ("crimson", 0xDC143C), ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B), ("darkcyan", 0x008B8B), ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9), ("darkgreen", 0x006400), ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B), ("darkmagenta", 0x8B008B), ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00), ("darkorchid", 0x9932CC), ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A), ("darkseagreen", 0x8FBC8F), ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F), ("darkslategrey", 0x2F4F4F), ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3), ("deeppink", 0xFF1493), ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969), ("dimgrey", 0x696969), ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222), ("floralwhite", 0xFFFAF0), ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF), ("gainsboro", 0xDCDCDC), ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700), ("goldenrod", 0xDAA520), ("gray", 0x808080),
+    ("green", 0x008000), ("greenyellow", 0xADFF2F), ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0), ("hotpink", 0xFF69B4), ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082), ("ivory", 0xFFFFF0), ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA), ("lavenderblush", 0xFFF0F5), ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD), ("lightblue", 0xADD8E6), ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF), ("lightgoldenrodyellow", 0xFAFAD2), ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90), ("lightgrey", 0xD3D3D3), ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A), ("lightseagreen", 0x20B2AA), ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899), ("lightslategrey", 0x778899), ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0), ("lime", 0x00FF00), ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6), ("magenta", 0xFF00FF), ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA), ("mediumblue", 0x0000CD), ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB), ("mediumseagreen", 0x3CB371), ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A), ("mediumturquoise", 0x48D1CC), ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xF5FFFA), ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5), ("navajowhite", 0xFFDEAD), ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6), ("olive", 0x808000), ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500), ("orangered", 0xFF4500), ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA), ("palegreen", 0x98FB98), ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093), ("papayawhip", 0xFFEFD5), ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F), ("pink", 0xFFC0CB), ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000), ("rosybrown", 0xBC8F8F), ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513), ("salmon", 0xFA8072), ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57), ("seashell", 0xFFF5EE), ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0), ("skyblue", 0x87CEEB), ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F), ("steelblue", 0x4682B4), ("tan", 0xD2B48C),
+    ("teal", 0x008080), ("thistle", 0xD8BFD8), ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0), ("violet", 0xEE82EE), ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF), ("whitesmoke", 0xF5F5F5), ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+/// Error from parsing a colour, with the offending text.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ColorError {
+    pub input: String,
+    pub reason: String,
+}
+
+impl Display for ColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid colour {:?}: {}", self.input, self.reason)
+    }
+}
+
+impl std::error::Error for ColorError {}
+
+/// Joins `r`, `g` and `b` into `0xRRGGBB`.
+#[must_use]
+pub fn from_rgb(r: u8, g: u8, b: u8) -> u32 {
+    u32::from_be_bytes([0, r, g, b])
+}
+
+/// Splits `rgb` into its `r`, `g` and `b` channels, ignoring its top byte.
+#[must_use]
+pub const fn to_rgb(rgb: u32) -> (u8, u8, u8) {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    (r, g, b)
+}
+
+/// Converts hue `h` in degrees within `[0, 360]`, saturation `s` and value `v` within `[0, 1]`
+/// into `0xRRGGBB`.
+#[must_use]
+#[allow(clippy::many_single_char_names)]
+pub fn from_hsv(h: f32, s: f32, v: f32) -> u32 {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h {
+        h if h < 1.0 => (c, x, 0.0),
+        h if h < 2.0 => (x, c, 0.0),
+        h if h < 3.0 => (0.0, c, x),
+        h if h < 4.0 => (0.0, x, c),
+        h if h < 5.0 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let channel = |f: f32| ((f + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    from_rgb(channel(r), channel(g), channel(b))
+}
+
+/// Parses the `(a, b, c)` arguments of `rgb` and `hsv`.
+fn _arguments(args: &str) -> Option<[&str; 3]> {
+    let args = args.strip_prefix('(')?.strip_suffix(')')?;
+    let mut split = args.split(',').map(str::trim);
+    let parsed = [split.next()?, split.next()?, split.next()?];
+    if split.next().is_some() { return None; }
+    return Some(parsed);
+}
+
+/// Parses `arg` as a number within `[0, max]`, or as a percentage of `max`.
+fn _fraction_of(arg: &str, max: f32) -> Result<f32, String> {
+    let (number, scale) = match arg.strip_suffix('%') {
+        Some(pct) => (pct.trim_end(), max / 100.0),
+        None => (arg, 1.0),
+    };
+    let value = number.parse::<f32>().map_err(|_| format!("Expected number, got {arg:?}"))? * scale;
+    if !(0.0..=max).contains(&value) {
+        return Err(format!("Expected {arg} within [0, {max}] or [0%, 100%]"));
+    }
+    return Ok(value);
+}
+
+fn _hex(digits: &str) -> Result<u32, String> {
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(String::from("Expected 6 hexadecimal digits"));
+    }
+    return u32::from_str_radix(digits, 16).map_err(|e| e.to_string());
+}
+
+/// Parses `s` as a colour in any of the accepted forms.
+///
+/// # Errors
+/// `ColorError` if `s` is in none of the accepted forms, or has a value out of range.
+pub fn parse(s: &str) -> Result<u32, ColorError> {
+    let s = s.trim();
+    let lower = s.to_ascii_lowercase();
+    let res = if let Some(digits) = lower.strip_prefix('#').or_else(|| lower.strip_prefix("0x")) {
+        _hex(digits)
+    } else if let Some(args) = lower.strip_prefix("rgb") {
+        _arguments(args)
+            .ok_or_else(|| String::from("Expected rgb(r, g, b)"))
+            .and_then(|args| {
+                let mut channels = [0; 3];
+                for (c, arg) in channels.iter_mut().zip(args) {
+                    let value = _fraction_of(arg, 255.0)?;
+                    if arg.ends_with('%') || value.fract() == 0.0 {
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                        { *c = value.round() as u8; }
+                    } else {
+                        return Err(format!("Expected integer channel, got {arg}"));
+                    }
+                }
+                Ok(from_rgb(channels[0], channels[1], channels[2]))
+            })
+    } else if let Some(args) = lower.strip_prefix("hsv") {
+        _arguments(args)
+            .ok_or_else(|| String::from("Expected hsv(h, s, v)"))
+            .and_then(|[h, s, v]| {
+                let h = h.parse::<f32>().map_err(|_| format!("Expected hue in degrees, got {h:?}"))?;
+                if !(0.0..=360.0).contains(&h) { return Err(format!("Expected hue {h} within [0, 360]")); }
+                Ok(from_hsv(h, _fraction_of(s, 1.0)?, _fraction_of(v, 1.0)?))
+            })
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        match lower.parse::<u32>() {
+            Ok(rgb) if rgb <= MAX => Ok(rgb),
+            Ok(rgb) => Err(format!("Expected at most {MAX} (0xFFFFFF), got {rgb}")),
+            Err(e) => Err(e.to_string()),
+        }
+    } else {
+        NAMES.iter()
+            .find(|(name, _)| *name == lower)
+            .map(|(_, rgb)| *rgb)
+            .ok_or_else(|| String::from("Unknown colour name"))
+    };
+    return res.map_err(|reason| ColorError { input: s.to_owned(), reason });
+}
+
+/// Colour as given in JSON, i.e. either `16744448` or `"#ff8000"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorRepr {
+    Int(u64),
+    Text(String),
+}
+
+impl ColorRepr {
+    fn _into_rgb<E: serde::de::Error>(self) -> Result<u32, E> {
+        match self {
+            Self::Int(rgb) => u32::try_from(rgb)
+                .ok()
+                .filter(|rgb| *rgb <= MAX)
+                .ok_or_else(|| E::custom(format!("Expected colour at most 0xFFFFFF, got {rgb}"))),
+            Self::Text(s) => parse(&s).map_err(E::custom),
+        }
+    }
+}
+
+/// Deserializes a colour given as `0xRRGGBB` integer or as string in any form of `parse`.
+pub(crate) fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    ColorRepr::deserialize(deserializer)?._into_rgb()
+}
+
+/// Same as `deserialize_color`, for a list of colours.
+pub(crate) fn deserialize_colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    Vec::<ColorRepr>::deserialize(deserializer)?
+        .into_iter()
+        .map(ColorRepr::_into_rgb)
+        .collect()
+}
+
+/// Same as `deserialize_color`, for a list of colours prefixed by their indices.
+pub(crate) fn deserialize_indexed_colors<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Vec<(u8, u32)>, D::Error> {
+    Vec::<(u8, ColorRepr)>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(i, c)| c._into_rgb().map(|rgb| (i, rgb)))
+        .collect()
+}
+
+/// Joins `words` split at whitespace within parentheses, e.g. `rgb(255,`, `0,` and `0)` into
+/// `rgb(255, 0, 0)`, leaving others as they are.
+pub fn join_words<'a>(words: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut joined: Vec<String> = Vec::new();
+    let mut open = false;
+    for word in words {
+        match joined.last_mut() {
+            Some(last) if open => {
+                last.push(' ');
+                last.push_str(word);
+            },
+            _ => joined.push(word.to_owned()),
+        }
+        let last = joined.last().map(String::as_str).unwrap_or_default();
+        open = last.matches('(').count() > last.matches(')').count();
+    }
+    return joined;
+}
+
+/// Global brightness and gamma correction of LED colours, applied by a `Communicator` right
+/// before encoding.
+///
+/// Each channel `c` is sent as `255 * brightness * (c / 255) ^ gamma`, rounded. A gamma of about
+/// 2.2 makes the steps between colours look even on typical LEDs.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ColorCorrection {
+    brightness: f32,
+    gamma: f32,
+    /// Corrected value of each channel value.
+    table: [u8; 256],
+}
+
+impl Default for ColorCorrection {
+    fn default() -> Self {
+        Self::new(1.0, 1.0).expect("Identity correction is valid")
+    }
+}
+
+impl ColorCorrection {
+    /// Correction scaling colours by `brightness` after raising them to the power of `gamma`.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` if `brightness` is not within `[0, 1]` or `gamma` is not
+    /// positive.
+    pub fn new(brightness: f32, gamma: f32) -> io::Result<Self> {
+        if !(0.0..=1.0).contains(&brightness) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Expected brightness within [0, 1], got {brightness}")
+            ));
+        }
+        if !(gamma.is_finite() && gamma > 0.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Expected positive gamma, got {gamma}")
+            ));
+        }
+        let mut table = [0; 256];
+        for (c, corrected) in table.iter_mut().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let f = (c as f32 / 255.0).powf(gamma) * brightness;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            { *corrected = (f * 255.0).round().clamp(0.0, 255.0) as u8; }
+        }
+        return Ok(Self { brightness, gamma, table });
+    }
+
+    #[must_use]
+    pub const fn brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    #[must_use]
+    pub const fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    /// Whether the correction leaves all colours as they are.
+    #[must_use]
+    pub fn is_identity(&self) -> bool {
+        self.table.iter().enumerate().all(|(c, &corrected)| usize::from(corrected) == c)
+    }
+
+    /// Corrected `rgb`, as sent to the firmware.
+    #[must_use]
+    pub fn apply(&self, rgb: u32) -> u32 {
+        let (r, g, b) = to_rgb(rgb);
+        from_rgb(self.table[usize::from(r)], self.table[usize::from(g)], self.table[usize::from(b)])
+    }
+
+    /// Colour that `rgb`, as read back from the firmware, was most likely corrected from. Exact
+    /// up to rounding, and black if the brightness is 0.
+    #[must_use]
+    pub fn invert(&self, rgb: u32) -> u32 {
+        let channel = |corrected: u8| -> u8 {
+            // => Smallest value corrected to at least `corrected`, or the brightest there is
+            let c = self.table.partition_point(|&t| t < corrected).min(255);
+            u8::try_from(c).unwrap_or(u8::MAX)
+        };
+        let (r, g, b) = to_rgb(rgb);
+        from_rgb(channel(r), channel(g), channel(b))
+    }
+
+    /// Corrects the colours of `LED` and `LED_DELTA` instructions.
+    ///
+    /// ### Returns
+    /// `instr` with its colours corrected, or as it is if it is neither.
+    #[must_use]
+    pub fn apply_instruction(&self, instr: &[u8]) -> Instruction {
+        let mut corrected = instr.to_vec();
+        let Some((&opcode, args)) = instr.split_first() else { return corrected; };
+        // => Colours as big-endian RGB triples, each prefixed by an index byte in deltas
+        let (stride, offset) = match opcode {
+            bindings::LED       => (3, 0),
+            opcode::LED_DELTA => (4, 1),
+            _ => return corrected,
+        };
+        for (i, _) in args.chunks_exact(stride).enumerate() {
+            let start = 1 + i * stride + offset;
+            for c in &mut corrected[start..start + 3] {
+                *c = self.table[usize::from(*c)];
+            }
+        }
+        return corrected;
+    }
+}
@@ -0,0 +1,195 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+use crate::{bindings, opcode, Instruction, ReadMode, ReadSpec, Request, RequestConversionError};
+use crate::animation::Animation;
+use crate::trajectory::Trajectory;
+
+/// A single magnet cell as understood by the `MAGNET` operation.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct MagnetCell {
+    pub x: f32,
+    pub y: f32,
+    pub on: bool,
+}
+
+impl MagnetCell {
+    /// Whether both coordinates are finite, i.e. neither infinite nor `NaN`.
+    #[must_use]
+    pub const fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite()
+    }
+}
+
+/// Typed counterpart to the `WRITE <OP> <ARGS...>` text grammar parsed by `Request::try_from`.
+///
+/// Serialized with the Arduino op name in the `cmd` field, e.g.:
+/// `{"cmd":"MAGNET","cells":[{"x":1.0,"y":2.0,"on":true}]}`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "UPPERCASE")]
+pub enum Command {
+    Sensor,
+    Magnet { cells: Vec<MagnetCell> },
+    /// Colours as `0xRRGGBB` integers, or as strings in any form of `color::parse`.
+    Led {
+        #[serde(deserialize_with = "color::deserialize_colors")]
+        colors: Vec<u32>,
+    },
+    /// Update of the magnet cells at the given indices, leaving others as they are.
+    #[serde(rename = "MAGNET_DELTA")]
+    MagnetDelta { cells: Vec<(u8, MagnetCell)> },
+    /// Update of the LED colours at the given indices, leaving others as they are.
+    #[serde(rename = "LED_DELTA")]
+    LedDelta {
+        #[serde(deserialize_with = "color::deserialize_indexed_colors")]
+        colors: Vec<(u8, u32)>,
+    },
+    Heartbeat,
+    /// Start pushing `SENSOR` readings every `interval_ms`, or stop if 0.
+    Stream { interval_ms: u16 },
+    Ack,
+    Quit,
+}
+
+fn _encode_cell_into(instr_buf: &mut Instruction, cell: &MagnetCell) {
+    instr_buf.extend_from_slice(&cell.x.to_le_bytes());
+    instr_buf.extend_from_slice(&cell.y.to_le_bytes());
+    instr_buf.push(cell.on.into());
+}
+
+impl Command {
+    /// Encodes this command into the byte sequence sent to the Arduino.
+    ///
+    /// The encoding is identical to that of the text grammar, i.e. `x` and `y` as little-endian
+    /// `f32`s followed by `is_on` as `u8` for `MAGNET`, and the lower 3 bytes (big-endian) of each
+    /// colour for `LED`. Each entry of `MAGNET_DELTA` and `LED_DELTA` is prefixed by its index as
+    /// `u8`. `STREAM` takes its interval as a little-endian `u16`.
+    #[must_use]
+    pub fn encode(&self) -> Instruction {
+        let mut instr_buf: Instruction = Vec::with_capacity(512);
+        match self {
+            Self::Sensor => instr_buf.push(bindings::SENSOR),
+            Self::Magnet { cells } => {
+                instr_buf.push(bindings::MAGNET);
+                for cell in cells {
+                    _encode_cell_into(&mut instr_buf, cell);
+                }
+            },
+            Self::Led { colors } => {
+                instr_buf.push(bindings::LED);
+                for rgb_int in colors {
+                    instr_buf.extend_from_slice(&rgb_int.to_be_bytes()[1..]);
+                }
+            },
+            Self::MagnetDelta { cells } => {
+                instr_buf.push(opcode::MAGNET_DELTA);
+                for (i, cell) in cells {
+                    instr_buf.push(*i);
+                    _encode_cell_into(&mut instr_buf, cell);
+                }
+            },
+            Self::LedDelta { colors } => {
+                instr_buf.push(opcode::LED_DELTA);
+                for (i, rgb_int) in colors {
+                    instr_buf.push(*i);
+                    instr_buf.extend_from_slice(&rgb_int.to_be_bytes()[1..]);
+                }
+            },
+            Self::Heartbeat => instr_buf.push(opcode::HEARTBEAT),
+            Self::Stream { interval_ms } => {
+                instr_buf.push(opcode::STREAM);
+                instr_buf.extend_from_slice(&interval_ms.to_le_bytes());
+            },
+            Self::Ack  => instr_buf.push(bindings::ACK),
+            Self::Quit => instr_buf.push(bindings::QUIT),
+        }
+        return instr_buf;
+    }
+}
+
+impl From<&Command> for Request {
+    fn from(cmd: &Command) -> Self {
+        Self::Write(cmd.encode())
+    }
+}
+
+/// Typed counterpart to `Request`, serialized with the serial-communicator op in the `op` field,
+/// e.g. `{"op":"READ"}`, `{"op":"READ","len":4,"timeout_ms":500}` or
+/// `{"op":"WRITE","cmd":"SENSOR"}`, or `{"op":"STATE"}` to query the host-side board state.
+///
+/// `{"op":"MOVE",...}` takes the fields of a `Trajectory`, and `{"op":"ANIMATE",...}` those of an
+/// `Animation`. `{"op":"STOP"}` stops any animation.
+///
+/// At most one of `len`, `until` and `frame` may be given for `READ`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "UPPERCASE")]
+pub enum TypedRequest {
+    Read {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        len: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        until: Option<u8>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        frame: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+    Write(Command),
+    State,
+    Move(Trajectory),
+    Animate(Animation),
+    Stop,
+}
+
+impl TypedRequest {
+    /// `READ` with default `ReadSpec`.
+    pub const READ: Self = Self::Read { len: None, until: None, frame: false, timeout_ms: None };
+}
+
+impl TryFrom<&TypedRequest> for Request {
+    type Error = RequestConversionError;
+
+    fn try_from(req: &TypedRequest) -> Result<Self, Self::Error> {
+        const _FN_NAME: &str = "[Request as TryFrom<&TypedRequest>::try_from]";
+
+        match req {
+            TypedRequest::Read { len, until, frame, timeout_ms } => {
+                let mode = match (len, until, frame) {
+                    (None, None, false)    => ReadMode::Available,
+                    (Some(n), None, false) => ReadMode::Exact(*n),
+                    (None, Some(b), false) => ReadMode::Until(*b),
+                    (None, None, true)     => ReadMode::Frame,
+                    _ => return Err(RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Expected at most one of `len`, `until` and `frame`")
+                    )),
+                };
+                Ok(Self::Read(ReadSpec { mode, timeout: timeout_ms.map(Duration::from_millis) }))
+            },
+            TypedRequest::Write(c) => {
+                let finite = match c {
+                    Command::Magnet { cells } => cells.iter().all(MagnetCell::is_finite),
+                    Command::MagnetDelta { cells } => cells.iter().all(|(_, cell)| cell.is_finite()),
+                    _ => true,
+                };
+                if !finite {
+                    return Err(RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Expected finite magnet coordinates")
+                    ));
+                }
+                Ok(c.into())
+            },
+            TypedRequest::State    => Ok(Self::State),
+            TypedRequest::Move(t)  => t.validate()
+                .map(|()| Self::Move(t.clone()))
+                .map_err(|e| RequestConversionError::MalformedOpSequence(format!("{_FN_NAME} {e}"))),
+            TypedRequest::Animate(a) => a.validate()
+                .map(|()| Self::Animate(a.clone()))
+                .map_err(|e| RequestConversionError::MalformedOpSequence(format!("{_FN_NAME} {e}"))),
+            TypedRequest::Stop => Ok(Self::Stop),
+        }
+    }
+}
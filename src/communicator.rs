@@ -0,0 +1,882 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+use std::collections::VecDeque;
+use std::io;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::{error, info, warn};
+use serde::Serialize;
+use serialport::{ClearBuffer, SerialPort};
+
+use crate::animation::Animation;
+use crate::{bindings, opcode};
+use crate::board::BoardState;
+use crate::calibration::Calibration;
+use crate::color::ColorCorrection;
+use crate::chunk;
+use crate::command::Command;
+use crate::response::{timestamp_now, to_hex, Decoded, Response};
+use crate::safety::SafetyGuard;
+use crate::shutdown::SafeState;
+use crate::trajectory::Trajectory;
+use crate::util::serial_helper::{
+    read_all_bytes_after, read_exact_into, read_frame_into, read_until_byte_into, write_all_bytes,
+};
+use crate::{ReadMode, ReadSpec, Request};
+
+/// Shortest sleep of `Communicator::idle_for` while an animation frame is overdue, e.g. deferred
+/// until the client reads pending replies.
+const ANIMATION_MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// Outcome of running a single `Request` against an Arduino.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Outcome {
+    Written(Vec<u8>),
+    Received(Response),
+    /// Host-side mirror of the board, as of `Request::State`.
+    State(BoardState),
+}
+
+/// Change in connection state of a `Communicator`, to be reported to its client.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum LinkEvent {
+    Connected {
+        device: String,
+        timestamp: u64,
+    },
+    Disconnected {
+        device: String,
+        timestamp: u64,
+        reason: String,
+    },
+    /// No heartbeat reply for longer than `HeartbeatPolicy::stale_after`.
+    Stale {
+        device: String,
+        timestamp: u64,
+        silent_ms: u64,
+    },
+    /// Heartbeat replies resumed after `Stale`.
+    Alive {
+        device: String,
+        timestamp: u64,
+    },
+}
+
+/// What to do with the request in flight when the connection is lost.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum PendingPolicy {
+    /// Hold the request until reconnected, then run it.
+    #[default]
+    Queue,
+    /// Fail the request at once. Reconnection is attempted on the next request.
+    Fail,
+}
+
+/// How a `Communicator` recovers from a lost connection.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub pending: PendingPolicy,
+    /// How long to keep trying to reconnect before giving up on a request.
+    pub give_up_after: Duration,
+    /// Interval between reconnection attempts.
+    pub retry_interval: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            pending: PendingPolicy::default(),
+            give_up_after: Duration::from_secs(30),
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// How a `Communicator` keeps the firmware watchdog fed and detects a stale link.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct HeartbeatPolicy {
+    /// Interval between `HEARTBEAT`s while no other instruction is written.
+    pub interval: Duration,
+    /// How long to wait for each `HEARTBEAT` reply.
+    pub timeout: Duration,
+    /// Silence after which the link is reported `LinkEvent::Stale`.
+    pub stale_after: Duration,
+}
+
+impl Default for HeartbeatPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(200),
+            timeout: Duration::from_millis(100),
+            stale_after: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Heartbeat bookkeeping of a `Communicator`.
+struct Heartbeat {
+    policy: HeartbeatPolicy,
+    last_write: Instant,
+    last_alive: Instant,
+    stale: bool,
+}
+
+/// Animation being played by a `Communicator`.
+struct Player {
+    animation: Animation,
+    leds: usize,
+    interval: Duration,
+    started: Instant,
+    next_frame: Instant,
+    /// Colours of the last frame, not sent again until they change.
+    last: Option<Vec<u32>>,
+}
+
+/// Opens a new port to the same Arduino, e.g. by re-running discovery for its serial number.
+pub type Connector = Box<dyn FnMut() -> io::Result<Box<dyn SerialPort>> + Send>;
+
+/// Whether `e` means the link itself failed, rather than the Arduino replying late or
+/// unexpectedly, or the request being rejected before it was written.
+fn _is_link_failure(e: &io::Error) -> bool {
+    !matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData)
+}
+
+fn _not_connected(device: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, format!("{device} is not connected"))
+}
+
+/// Splits a reply in `mode` into its first bytes taken from `held` and the rest to read, if any.
+fn _take_held(held: &[u8], mode: ReadMode) -> (usize, Option<ReadMode>) {
+    if held.is_empty() { return (0, Some(mode)); }
+    match mode {
+        ReadMode::Available => (held.len(), None),
+        ReadMode::Exact(n) => {
+            let taken = n.min(held.len());
+            (taken, (taken < n).then_some(ReadMode::Exact(n - taken)))
+        },
+        ReadMode::Until(b) => match held.iter().position(|&h| h == b) {
+            Some(i) => (i + 1, None),
+            None => (held.len(), Some(mode)),
+        },
+        ReadMode::Frame => {
+            let len = 1 + usize::from(held[0]);
+            let taken = len.min(held.len());
+            (taken, (taken < len).then_some(ReadMode::Exact(len - taken)))
+        },
+    }
+}
+
+/// Owns the serial port to one Arduino and runs `Request`s against it.
+pub struct Communicator {
+    /// `None` while disconnected.
+    port: Option<Box<dyn SerialPort>>,
+    device: String,
+    read_buffer: Vec<u8>,
+    reconnect: Option<(Connector, ReconnectPolicy)>,
+    events: Vec<LinkEvent>,
+    /// Run on drop unless already run.
+    safe_state: Option<SafeState>,
+    heartbeat: Option<Heartbeat>,
+    board: BoardState,
+    /// What to apply to `board` on each `ACK` still due, oldest first.
+    unacked: VecDeque<Vec<u8>>,
+    /// Bytes received by `tick` on behalf of the client, read first by `Communicator::read`.
+    held: Vec<u8>,
+    /// Number of leading `ACK`s in `held` already applied to `board`.
+    held_acks: usize,
+    max_frame_len: Option<usize>,
+    safety: Option<SafetyGuard>,
+    calibration: Option<Calibration>,
+    color_correction: Option<ColorCorrection>,
+    animation: Option<Player>,
+}
+
+impl Communicator {
+    #[must_use]
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        let device = port.name().unwrap_or_else(|| String::from("<unnamed>"));
+        Self::with_device_name(port, &device)
+    }
+
+    /// Same as `Communicator::new`, but reports the device as `device` instead of the port name.
+    #[must_use]
+    pub fn with_device_name(port: Box<dyn SerialPort>, device: &str) -> Self {
+        Self {
+            port: Some(port),
+            device: device.to_owned(),
+            read_buffer: Vec::with_capacity(512),
+            reconnect: None,
+            events: Vec::new(),
+            safe_state: None,
+            heartbeat: None,
+            board: BoardState::default(),
+            unacked: VecDeque::new(),
+            held: Vec::new(),
+            held_acks: 0,
+            max_frame_len: None,
+            safety: None,
+            calibration: None,
+            color_correction: None,
+            animation: None,
+        }
+    }
+
+    /// Enables automatic reconnection through `connector` on I/O errors other than time-outs.
+    #[must_use]
+    pub fn with_reconnect(mut self, connector: Connector, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some((connector, policy));
+        self
+    }
+
+    /// Puts the Arduino into `safe_state` when dropped, e.g. on return or panic.
+    #[must_use]
+    pub fn with_safe_state(mut self, safe_state: SafeState) -> Self {
+        self.safe_state = Some(safe_state);
+        self
+    }
+
+    /// Sends `HEARTBEAT`s as per `policy` on `Communicator::tick`.
+    #[must_use]
+    pub fn with_heartbeat(mut self, policy: HeartbeatPolicy) -> Self {
+        let now = Instant::now();
+        self.heartbeat = Some(Heartbeat { policy, last_write: now, last_alive: now, stale: false });
+        self
+    }
+
+    /// Splits instructions longer than `len` bytes, the size of the firmware receive buffer, into
+    /// several frames as per `chunk::split`. All but the last frame are acknowledged within
+    /// `Communicator::write`.
+    #[must_use]
+    pub const fn with_max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = Some(len);
+        self
+    }
+
+    /// Checks `MAGNET` and `MAGNET_DELTA` instructions against `guard` before writing them,
+    /// rejecting or clamping them as per its limits.
+    #[must_use]
+    pub fn with_safety(mut self, guard: SafetyGuard) -> Self {
+        self.safety = Some(guard);
+        self
+    }
+
+    /// Takes `MAGNET` and `MAGNET_DELTA` coordinates in the user units of `calibration`, encoding
+    /// them in firmware coordinates on write. Safety limits and the board mirror stay in user
+    /// units.
+    #[must_use]
+    pub const fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Corrects the colours of `LED` and `LED_DELTA` instructions with `correction` on write. The
+    /// board mirror keeps the colours as requested.
+    #[must_use]
+    pub fn with_color_correction(mut self, correction: ColorCorrection) -> Self {
+        self.color_correction = Some(correction);
+        self
+    }
+
+    /// Name of the underlying `tty` device.
+    #[must_use]
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    #[must_use]
+    pub const fn is_connected(&self) -> bool {
+        self.port.is_some()
+    }
+
+    /// Underlying port, or `None` while disconnected.
+    pub fn port_mut(&mut self) -> Option<&mut dyn SerialPort> {
+        self.port.as_mut().map(|p| &mut **p as &mut dyn SerialPort)
+    }
+
+    /// Mirror of the board, as of the last acknowledged `MAGNET`, `LED` and `QUIT` or the last
+    /// `Communicator::sync_state`.
+    #[must_use]
+    pub const fn board_state(&self) -> &BoardState {
+        &self.board
+    }
+
+    /// Same as `Communicator::board_state`, after applying the `ACK`s received so far without
+    /// blocking. They are still returned by the next `Communicator::read`.
+    ///
+    /// # Errors
+    /// Any `io::Error` from reading the port.
+    pub fn poll_board_state(&mut self) -> io::Result<&BoardState> {
+        self._drain_acks()?;
+        return Ok(&self.board);
+    }
+
+    /// Discards bytes in `buffer` of the port, if connected. `ACK`s received so far are applied to
+    /// the board mirror first. Discarding any other input also forgets instructions awaiting
+    /// `ACK`, as their replies may be among it.
+    ///
+    /// # Errors
+    /// Any `serialport::Error` from querying or clearing the port.
+    pub fn clear(&mut self, buffer: ClearBuffer) -> serialport::Result<()> {
+        // => ACKs already received still apply to the mirror
+        if buffer != ClearBuffer::Output { self._drain_acks()?; }
+        let Some(port) = self.port.as_deref_mut() else { return Ok(()); };
+        let discards_input = buffer != ClearBuffer::Output && port.bytes_to_read()? > 0;
+        port.clear(buffer)?;
+        if discards_input || (buffer != ClearBuffer::Output && !self.held.is_empty()) {
+            self.unacked.clear();
+            self.held.clear();
+            self.held_acks = 0;
+        }
+        return Ok(());
+    }
+
+    /// Takes connection state changes since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<LinkEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Writes `frame` to the Arduino and flushes the port, applying `applies` to the board
+    /// mirror once acknowledged.
+    fn _write_frame(&mut self, frame: &[u8], applies: &[u8]) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::write_frame]";
+
+        let Some(port) = self.port.as_deref_mut() else {
+            return Err(_not_connected(&self.device));
+        };
+        write_all_bytes(port, frame)?;
+        port.flush()?;
+        if let Some(hb) = self.heartbeat.as_mut() { hb.last_write = Instant::now(); }
+        if BoardState::is_acknowledged(frame) { self.unacked.push_back(applies.to_vec()); }
+        info!("{_FN_NAME} Written {frame:x?} to {}", self.device);
+        return Ok(());
+    }
+
+    /// Reads `ACK`s until all instructions written so far are acknowledged.
+    fn _await_acks(&mut self) -> io::Result<()> {
+        let spec = ReadSpec { mode: ReadMode::Until(bindings::ACK), timeout: None };
+        while !self.unacked.is_empty() {
+            // => Bytes held for the client came before, and are not these ACKs
+            let res = self._read(&spec, false)?;
+            if res.decoded != Decoded::Ack {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected ACK from {}, got {}", self.device, to_hex(&res.raw))
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Writes `instr` to the Arduino and flushes the port. `MAGNET` and `MAGNET_DELTA` are snapped,
+    /// checked against the safety limits and encoded in firmware coordinates first, if configured.
+    /// Instructions exceeding the maximum frame length, if any, are split into several frames, all
+    /// but the last of which are awaited to be acknowledged first.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::NotConnected` if disconnected.
+    /// - `io::ErrorKind::InvalidInput` if `instr` exceeds the maximum frame length and cannot be
+    ///   split.
+    /// - `io::ErrorKind::InvalidData` if a frame was replied to with anything but `ACK`.
+    /// - `io::ErrorKind::InvalidInput` if `instr` violates the safety limits, if any, and is not
+    ///   clamped, with the `safety::Violation` as inner error.
+    /// - Any `io::Error` from writing to or flushing the port, or from reading `ACK`s.
+    pub fn write(&mut self, instr: &[u8]) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::write]";
+
+        let snapped = self.calibration
+            .as_ref()
+            .map_or_else(|| instr.to_vec(), |c| c.snap_instruction(instr));
+        let guarded = match self.safety.as_mut() {
+            Some(guard) => {
+                let (guarded, clamped) = guard
+                    .guard(&snapped, &self.board)
+                    .map_err(|v| io::Error::new(io::ErrorKind::InvalidInput, v))?;
+                for v in clamped { warn!("{_FN_NAME} Clamped: {v}"); }
+                guarded
+            },
+            None => snapped,
+        };
+        let encoded = self.calibration
+            .as_ref()
+            .map_or_else(|| guarded.clone(), |c| c.to_firmware_instruction(&guarded));
+        let encoded = match self.color_correction.as_ref() {
+            Some(correction) => correction.apply_instruction(&encoded),
+            None => encoded,
+        };
+        let frames = match self.max_frame_len {
+            Some(max_len) => chunk::split(&encoded, max_len)?,
+            None => vec![encoded],
+        };
+        let Some((last, chunks)) = frames.split_last() else { return Ok(()); };
+        for frame in chunks {
+            // => Applied to the mirror all at once with the last frame
+            let res = self._write_frame(frame, &[]).and_then(|()| self._await_acks());
+            if res.is_err() { self.unacked.clear(); }
+            res?;
+        }
+        return self._write_frame(last, &guarded);
+    }
+
+    /// Writes the `MAGNET` frames of `trajectory` on schedule, each awaiting its `ACK` before the
+    /// next. Frames already overdue once their successor is due are skipped, except the last.
+    ///
+    /// ### Returns
+    /// The last frame written, which leaves the magnet at the end of the trajectory.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::InvalidInput` if `trajectory` is invalid as per `Trajectory::validate`.
+    /// - Same as `Communicator::write`, and `io::ErrorKind::InvalidData` if a frame was replied
+    ///   to with anything but `ACK`.
+    pub fn run_trajectory(&mut self, trajectory: &Trajectory) -> io::Result<Vec<u8>> {
+        const _FN_NAME: &str = "[Communicator::run_trajectory]";
+
+        trajectory.validate()?;
+        let frames = trajectory.frames();
+        let start = Instant::now();
+        let mut skipped = 0;
+        for (i, (offset, frame)) in frames.iter().enumerate() {
+            if frames.get(i + 1).is_some_and(|(next, _)| *next <= start.elapsed()) {
+                skipped += 1;
+                continue;
+            }
+            if let Some(wait) = offset.checked_sub(start.elapsed()) { sleep(wait); }
+            let res = self.write(frame).and_then(|()| self._await_acks());
+            if res.is_err() { self.unacked.clear(); }
+            res?;
+        }
+        if skipped > 0 { warn!("{_FN_NAME} Skipped {skipped} of {} overdue frames", frames.len()); }
+        return Ok(frames.last().map(|(_, f)| f.clone()).unwrap_or_default());
+    }
+
+    /// Reads a reply from the Arduino as specified by `spec`, within `spec.timeout` or the port
+    /// timeout if unspecified. Replies already received by `Communicator::tick` are read first.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::TimedOut` if the reply did not arrive in full within the timeout.
+    /// - `io::ErrorKind::NotConnected` if disconnected.
+    /// - Any other `io::Error` from reading the port.
+    pub fn read(&mut self, spec: &ReadSpec) -> io::Result<Response> {
+        self._read(spec, true)
+    }
+
+    /// Same as `Communicator::read`, but only reads bytes held by `Communicator::tick` if `held`.
+    fn _read(&mut self, spec: &ReadSpec, held: bool) -> io::Result<Response> {
+        const _FN_NAME: &str = "[Communicator::read]";
+
+        let Some(port) = self.port.as_deref_mut() else {
+            return Err(_not_connected(&self.device));
+        };
+        let timeout = spec.timeout.unwrap_or_else(|| port.timeout());
+        let (taken, rest) = if held { _take_held(&self.held, spec.mode) } else { (0, Some(spec.mode)) };
+        let buf = &mut self.read_buffer;
+        match rest {
+            None => buf.clear(),
+            Some(ReadMode::Available) => { read_all_bytes_after(port, buf, timeout)?; },
+            Some(ReadMode::Exact(n))  => { read_exact_into(port, buf, n, timeout)?; },
+            Some(ReadMode::Until(b))  => { read_until_byte_into(port, buf, b, timeout)?; },
+            Some(ReadMode::Frame)     => { read_frame_into(port, buf, timeout)?; },
+        }
+        let applied = taken.min(self.held_acks);
+        if taken > 0 {
+            // => Without the length prefix of frames, as read from the port
+            let skip = usize::from(spec.mode == ReadMode::Frame);
+            let held: Vec<u8> = self.held.drain(..taken).skip(skip).collect();
+            self.read_buffer.splice(0..0, held);
+            self.held_acks -= applied;
+        }
+        info!("{_FN_NAME} Received {:x?} from {}", self.read_buffer, self.device);
+        if rest.is_some() { self._mark_alive(); }
+        let res = Response::new(&self.device, self.read_buffer.clone());
+        self.read_buffer.clear();
+        if res.decoded == Decoded::Ack {
+            // => As many leading ACKs as acknowledged instructions, less those applied when held
+            for _ in res.raw.iter().skip(applied).take_while(|&&b| b == bindings::ACK) {
+                let Some(instr) = self.unacked.pop_front() else { break; };
+                self.board.apply(&instr);
+            }
+        }
+        return Ok(res);
+    }
+
+    /// Reads the `ACK`s received so far to instructions awaiting them, applying them to the board
+    /// mirror and holding them for `Communicator::read`. Stops at any other byte, held as well.
+    ///
+    /// ### Returns
+    /// Whether replies other than those `ACK`s are left for the client.
+    fn _drain_acks(&mut self) -> io::Result<bool> {
+        let Some(port) = self.port.as_deref_mut() else { return Ok(false); };
+        let mut byte = [0_u8; 1];
+        let mut received = false;
+        while self.held.len() == self.held_acks && !self.unacked.is_empty() && port.bytes_to_read()? > 0 {
+            port.read_exact(&mut byte)?;
+            received = true;
+            self.held.push(byte[0]);
+            if byte[0] != bindings::ACK { break; }
+            self.held_acks += 1;
+            if let Some(instr) = self.unacked.pop_front() { self.board.apply(&instr); }
+        }
+        let pending = self.held.len() > self.held_acks || port.bytes_to_read()? > 0;
+        // => Replies show the link is alive as much as a HEARTBEAT would
+        if received || port.bytes_to_read()? > 0 { self._mark_alive(); }
+        return Ok(pending);
+    }
+
+    /// Sends `HANDSHAKE` and waits for the Arduino to reply in kind within the port timeout.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::InvalidData` if the Arduino replied with anything else.
+    /// - Same as `Communicator::write` and `Communicator::read` otherwise.
+    pub fn handshake(&mut self) -> io::Result<()> {
+        self.write(&[bindings::HANDSHAKE])?;
+        let res = self.read(&ReadSpec::default())?;
+        if res.decoded == Decoded::Handshake { return Ok(()); }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected HANDSHAKE reply from {}, got {:x?}", self.device, res.raw)
+        ));
+    }
+
+    /// Replaces the board mirror with the state reported by the firmware in reply to `STATE`.
+    /// Pending replies are discarded first. Magnets and colours are mapped back through any
+    /// calibration and colour correction, the latter only up to rounding.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::InvalidData` if the Arduino replied with anything but a `STATE` reply.
+    /// - Same as `Communicator::write` and `Communicator::read` otherwise.
+    pub fn sync_state(&mut self) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::sync_state]";
+
+        self.clear(ClearBuffer::Input)?;
+        self.write(&[opcode::STATE])?;
+        let res = self.read(&ReadSpec::default())?;
+        let Decoded::State { magnets, leds } = res.decoded else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected STATE reply from {}, got {:x?}", self.device, res.raw)
+            ));
+        };
+        let magnets = match self.calibration.as_ref() {
+            Some(calibration) => magnets.into_iter().map(|c| calibration.from_firmware(c)).collect(),
+            None => magnets,
+        };
+        let leds = match self.color_correction.as_ref() {
+            Some(correction) => leds.into_iter().map(|rgb| correction.invert(rgb)).collect(),
+            None => leds,
+        };
+        self.board = BoardState { magnets, leds, synced: true };
+        info!("{_FN_NAME} Synced state of {}: {:?}", self.device, self.board);
+        return Ok(());
+    }
+
+    /// Starts playing `animation` on the next `Communicator::tick`, replacing any other. Frames are
+    /// sent as per `Animation::frame_interval` at the baud rate of the port.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` if `animation` is invalid as per `Animation::validate`.
+    pub fn start_animation(&mut self, animation: Animation) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::start_animation]";
+
+        animation.validate()?;
+        let leds = animation.leds.unwrap_or_else(|| self.board.leds.len().clamp(1, u8::MAX.into()));
+        let baud = self.port.as_deref().and_then(|p| p.baud_rate().ok());
+        let interval = animation.frame_interval(leds, baud);
+        info!("{_FN_NAME} Playing {animation} on {leds} LEDs of {} every {interval:?}", self.device);
+        let now = Instant::now();
+        self.animation = Some(Player { animation, leds, interval, started: now, next_frame: now, last: None });
+        return Ok(());
+    }
+
+    /// Stops any animation, leaving the LEDs as of its last frame.
+    ///
+    /// ### Returns
+    /// The animation stopped, if any.
+    pub fn stop_animation(&mut self) -> Option<Animation> {
+        self.animation.take().map(|p| p.animation)
+    }
+
+    /// Animation being played, if any. Animations that do not loop stop after their last frame.
+    #[must_use]
+    pub fn animation(&self) -> Option<&Animation> {
+        self.animation.as_ref().map(|p| &p.animation)
+    }
+
+    /// Time left until the next animation frame is due, zero if overdue, or `None` if there is no
+    /// animation. Lets idle loops wake up in time for `Communicator::tick`.
+    #[must_use]
+    pub fn next_frame_in(&self) -> Option<Duration> {
+        self.animation.as_ref().map(|p| p.next_frame.saturating_duration_since(Instant::now()))
+    }
+
+    /// Sends the animation frame due, if any, and awaits its `ACK`.
+    ///
+    /// ### Returns
+    /// The frame sent, if any.
+    fn _animate(&mut self) -> io::Result<Option<Vec<u8>>> {
+        const _FN_NAME: &str = "[Communicator::animate]";
+
+        let Some(next_frame) = self.animation.as_ref().map(|p| p.next_frame) else { return Ok(None); };
+        let now = Instant::now();
+        if now < next_frame || !self.is_connected() { return Ok(None); }
+        // Leave unread replies to the client, other than ACKs held for it
+        if self._drain_acks()? { return Ok(None); }
+        let Some(player) = self.animation.as_mut() else { return Ok(None); };
+
+        let elapsed = now - player.started;
+        let colors = player.animation.frame_at(elapsed, player.leds);
+        // => Frames already missed are skipped
+        player.next_frame = (player.next_frame + player.interval).max(now);
+        let unchanged = player.last.as_ref() == Some(&colors);
+        player.last = Some(colors.clone());
+        if player.animation.is_done(elapsed) {
+            info!("{_FN_NAME} Animation on {} done", self.device);
+            self.animation = None;
+        }
+        if unchanged { return Ok(None); }
+
+        let frame = Command::Led { colors }.encode();
+        match self.write(&frame).and_then(|()| self._await_acks()) {
+            Ok(()) => Ok(Some(frame)),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                warn!("{_FN_NAME} No ACK to animation frame from {}", self.device);
+                self.unacked.clear();
+                Ok(Some(frame))
+            },
+            Err(e) => {
+                if _is_link_failure(&e) { self._disconnect(&e); }
+                Err(e)
+            },
+        }
+    }
+
+    /// Keeps the link alive if heartbeats are enabled, i.e. sends `HEARTBEAT` if nothing was written
+    /// for `HeartbeatPolicy::interval` and reports `LinkEvent::Stale` if the Arduino has been silent
+    /// for `HeartbeatPolicy::stale_after`. Sends the frame due of any animation first, which counts
+    /// as written.
+    ///
+    /// Meant to be called from the idle loop of the client rather than from a thread of its own, so
+    /// that heartbeats stop, and the firmware watchdog trips, if the client stalls.
+    ///
+    /// # Errors
+    /// Any `io::Error` other than time-outs from writing `HEARTBEAT` or an animation frame, or from
+    /// reading their replies, after which the `Communicator` is disconnected.
+    pub fn tick(&mut self) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::tick]";
+
+        self._animate()?;
+        let Some(hb) = self.heartbeat.as_ref() else { return Ok(()); };
+        let (policy, due) = (hb.policy, hb.last_write.elapsed() >= hb.policy.interval);
+        if !self.is_connected() { return Ok(()); }
+        // Leave unread replies to the client, other than ACKs held for it
+        if due && !self._drain_acks()? {
+            let res = self.write(&[opcode::HEARTBEAT]).and_then(|()| self._read(&ReadSpec {
+                mode: ReadMode::Until(opcode::HEARTBEAT), timeout: Some(policy.timeout),
+            }, false));
+            match res {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut =>
+                    warn!("{_FN_NAME} No HEARTBEAT reply from {}", self.device),
+                Err(e) => {
+                    if _is_link_failure(&e) { self._disconnect(&e); }
+                    return Err(e);
+                },
+            }
+        }
+
+        let Some(hb) = self.heartbeat.as_mut() else { return Ok(()); };
+        let silent = hb.last_alive.elapsed();
+        if !hb.stale && silent > policy.stale_after {
+            hb.stale = true;
+            warn!("{_FN_NAME} Link to {} is stale, silent for {silent:?}", self.device);
+            self.events.push(LinkEvent::Stale {
+                device: self.device.clone(),
+                timestamp: timestamp_now(),
+                silent_ms: u64::try_from(silent.as_millis()).unwrap_or(u64::MAX),
+            });
+        }
+        return Ok(());
+    }
+
+    /// Sleeps for `duration`, calling `Communicator::tick` as often as heartbeats and animation
+    /// frames are due.
+    ///
+    /// # Errors
+    /// Same as `Communicator::tick`.
+    pub fn idle_for(&mut self, duration: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + duration;
+        let step = self.heartbeat.as_ref().map_or(duration, |hb| hb.policy.interval);
+        loop {
+            self.tick()?;
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() { return Ok(()); }
+            // => Woken up for animation frames too, without spinning while they are deferred
+            let frame = self.next_frame_in().map_or(step, |d| d.max(ANIMATION_MIN_SLEEP));
+            sleep(left.min(step).min(frame));
+        }
+    }
+
+    fn _mark_alive(&mut self) {
+        const _FN_NAME: &str = "[Communicator::mark_alive]";
+
+        let Some(hb) = self.heartbeat.as_mut() else { return; };
+        hb.last_alive = Instant::now();
+        if hb.stale {
+            hb.stale = false;
+            info!("{_FN_NAME} Link to {} is alive again", self.device);
+            self.events.push(LinkEvent::Alive { device: self.device.clone(), timestamp: timestamp_now() });
+        }
+    }
+
+    fn _disconnect(&mut self, reason: &io::Error) {
+        const _FN_NAME: &str = "[Communicator::disconnect]";
+
+        if self.port.take().is_none() { return; }
+        self.unacked.clear();
+        self.held.clear();
+        self.held_acks = 0;
+        self.board.synced = false;
+        warn!("{_FN_NAME} Lost connection to {}: {reason}", self.device);
+        self.events.push(LinkEvent::Disconnected {
+            device: self.device.clone(), timestamp: timestamp_now(), reason: reason.to_string(),
+        });
+    }
+
+    /// Tries to reconnect until the policy gives up.
+    fn _reconnect(&mut self) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::reconnect]";
+
+        let Some((_, policy)) = self.reconnect.as_ref() else {
+            return Err(_not_connected(&self.device));
+        };
+        let policy = *policy;
+        let deadline = Instant::now() + policy.give_up_after;
+        loop {
+            let (connector, _) = self.reconnect.as_mut().unwrap();
+            match connector() {
+                Ok(port) => {
+                    if let Some(name) = port.name() { self.device = name; }
+                    self.port = Some(port);
+                    match self.handshake() {
+                        Ok(()) => {
+                            info!("{_FN_NAME} Reconnected to {}", self.device);
+                            if let Err(e) = self.sync_state() {
+                                warn!("{_FN_NAME} Cannot sync state of {}: {e}", self.device);
+                            }
+                            self.events.push(LinkEvent::Connected {
+                                device: self.device.clone(), timestamp: timestamp_now(),
+                            });
+                            return Ok(());
+                        },
+                        Err(e) => {
+                            // Release port, else it cannot be reopened
+                            warn!("{_FN_NAME} Handshake with {} failed: {e}", self.device);
+                            self.port = None;
+                        },
+                    }
+                },
+                Err(e) => info!("{_FN_NAME} Cannot reconnect to {} yet: {e}", self.device),
+            }
+            if Instant::now() + policy.retry_interval > deadline {
+                error!("{_FN_NAME} Giving up reconnecting to {}", self.device);
+                return Err(_not_connected(&self.device));
+            }
+            sleep(policy.retry_interval);
+        }
+    }
+
+    fn _execute_once(&mut self, req: &Request) -> io::Result<Outcome> {
+        match req {
+            Request::Read(spec) => Ok(Outcome::Received(self.read(spec)?)),
+            Request::Write(v)   => {
+                self.write(v)?;
+                Ok(Outcome::Written(v.clone()))
+            },
+            Request::State => Ok(Outcome::State(self.poll_board_state()?.clone())),
+            Request::Move(t) => Ok(Outcome::Written(self.run_trajectory(t)?)),
+            Request::Animate(a) => {
+                self.start_animation(a.clone())?;
+                Ok(Outcome::Written(self._animate()?.unwrap_or_default()))
+            },
+            Request::Stop => {
+                self.stop_animation();
+                Ok(Outcome::State(self.board.clone()))
+            },
+        }
+    }
+
+    /// Runs `req` against the Arduino, reconnecting as per policy if enabled. `Request::State` is
+    /// answered from the board mirror, without I/O or reconnecting. `Request::Move` runs the
+    /// whole trajectory, reporting its last frame as written. `Request::Animate` starts the
+    /// animation, reporting its first frame as written, and `Request::Stop` stops it, reporting the
+    /// board mirror, without I/O either.
+    ///
+    /// Only transport errors count as a lost link. Time-outs, requests rejected before writing
+    /// (`io::ErrorKind::InvalidInput`) and unexpected replies (`io::ErrorKind::InvalidData`) are
+    /// returned as they are, keeping the port open.
+    ///
+    /// # Errors
+    /// - Same as `Communicator::write` or `Communicator::read`, depending on `req`.
+    /// - `io::ErrorKind::NotConnected` if reconnection gave up.
+    pub fn execute(&mut self, req: &Request) -> io::Result<Outcome> {
+        if matches!(req, Request::State | Request::Stop) { return self._execute_once(req); }
+        let Some(pending) = self.reconnect.as_ref().map(|(_, p)| p.pending) else {
+            return self._execute_once(req);
+        };
+        if !self.is_connected() { self._reconnect()?; }
+
+        match self._execute_once(req) {
+            Err(e) if _is_link_failure(&e) => {
+                self._disconnect(&e);
+                match pending {
+                    PendingPolicy::Fail  => Err(e),
+                    PendingPolicy::Queue => {
+                        self._reconnect()?;
+                        self._execute_once(req)
+                    },
+                }
+            },
+            res => res,
+        }
+    }
+
+    /// Writes the safe-state sequence given by `Communicator::with_safe_state`, waiting for each
+    /// instruction to be acknowledged. Does nothing if there is none or it was already run.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::InvalidData` if the Arduino replied with anything but `ACK`.
+    /// - Same as `Communicator::write` and `Communicator::read` otherwise, including
+    ///   `io::ErrorKind::TimedOut` if an `ACK` did not arrive in time.
+    pub fn enter_safe_state(&mut self) -> io::Result<()> {
+        const _FN_NAME: &str = "[Communicator::enter_safe_state]";
+
+        let Some(safe_state) = self.safe_state.take() else { return Ok(()); };
+        // => Returns as soon as the ACK arrives, the timeout being a deadline
+        let spec = ReadSpec { mode: ReadMode::Until(bindings::ACK), timeout: Some(safe_state.ack_timeout) };
+        for instr in &safe_state.sequence {
+            // Do not mistake stale replies for ACK
+            self.clear(ClearBuffer::Input)?;
+            self.write(instr)?;
+            let res = self.read(&spec)?;
+            if res.decoded != Decoded::Ack {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected ACK to {} from {}, got {}", to_hex(instr), self.device, to_hex(&res.raw))
+                ));
+            }
+        }
+        info!("{_FN_NAME} {} is in safe state", self.device);
+        return Ok(());
+    }
+}
+
+impl Drop for Communicator {
+    fn drop(&mut self) {
+        const _FN_NAME: &str = "[Communicator::drop]";
+
+        if let Err(e) = self.enter_safe_state() {
+            error!("{_FN_NAME} Cannot put {} into safe state: {e}", self.device);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Fixed-rate control loop, e.g. for Cosmos' ctrl loop.
+//!
+//! On each tick, the loop writes the commands queued since the previous tick, then `SENSOR`, and
+//! publishes the reading. Commands superseded within a tick are coalesced, i.e. only the latest
+//! command per opcode is written. Ticks are scheduled on a fixed grid from the start of the loop;
+//! a tick that starts after its successor was due counts as a deadline miss, and the skipped
+//! ticks are dropped rather than run in a burst.
+//!
+//! With `ControlLoop::with_delta`, `MAGNET` and `LED` only send what changed since the last
+//! acknowledged state, as per `delta::DeltaEncoder`.
+
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::bindings;
+use crate::command::Command;
+use crate::communicator::Communicator;
+use crate::datalog::SensorLog;
+use crate::delta::DeltaEncoder;
+use crate::format::{Format, Status};
+use crate::response::Response;
+use crate::shutdown::Interrupt;
+use crate::{Instruction, ReadMode, ReadSpec, Request};
+
+/// Default interval between statistics reports.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Timing statistics of a `ControlLoop`, in microseconds where applicable.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize)]
+pub struct ControlStats {
+    pub ticks: u64,
+    /// Ticks that started after the next one was due, i.e. were skipped.
+    pub deadline_misses: u64,
+    /// Commands dropped because a later command with the same opcode superseded them.
+    pub coalesced: u64,
+    /// Ticks that failed to write a command or read `SENSOR`.
+    pub errors: u64,
+    /// Delay of tick start from schedule.
+    pub jitter_min_us: u64,
+    pub jitter_mean_us: u64,
+    pub jitter_max_us: u64,
+    /// Time spent within a tick.
+    pub work_max_us: u64,
+    #[serde(skip)]
+    jitter_sum_us: u128,
+}
+
+fn _as_us(d: Duration) -> u64 {
+    u64::try_from(d.as_micros()).unwrap_or(u64::MAX)
+}
+
+impl ControlStats {
+    fn _record(&mut self, jitter: Duration, work: Duration) {
+        let jitter = _as_us(jitter);
+        self.jitter_min_us = if self.ticks == 0 { jitter } else { self.jitter_min_us.min(jitter) };
+        self.jitter_max_us = self.jitter_max_us.max(jitter);
+        self.jitter_sum_us += u128::from(jitter);
+        self.ticks += 1;
+        self.jitter_mean_us = u64::try_from(self.jitter_sum_us / u128::from(self.ticks)).unwrap_or(u64::MAX);
+        self.work_max_us = self.work_max_us.max(_as_us(work));
+    }
+}
+
+/// Commands queued for the next tick, at most one per opcode.
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    pending: Vec<Instruction>,
+}
+
+impl CommandQueue {
+    /// Queues `instr`, replacing any queued command with the same opcode in place.
+    ///
+    /// ### Returns
+    /// Whether a queued command was superseded.
+    pub fn push(&mut self, instr: Instruction) -> bool {
+        let Some(&opcode) = instr.first() else { return false; };
+        match self.pending.iter_mut().find(|p| p.first() == Some(&opcode)) {
+            Some(p) => {
+                *p = instr;
+                true
+            },
+            None => {
+                self.pending.push(instr);
+                false
+            },
+        }
+    }
+
+    /// Takes the queued commands, in order of first arrival.
+    pub fn drain(&mut self) -> Vec<Instruction> {
+        std::mem::take(&mut self.pending)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Runs a `Communicator` at a fixed rate.
+pub struct ControlLoop<'a> {
+    comm: &'a mut Communicator,
+    period: Duration,
+    sensor_reply: ReadSpec,
+    stats_interval: Duration,
+    queue: CommandQueue,
+    stats: ControlStats,
+    log: Option<&'a mut SensorLog>,
+    delta: Option<DeltaEncoder>,
+}
+
+impl<'a> ControlLoop<'a> {
+    /// Creates a loop ticking every `period`.
+    ///
+    /// Until `ControlLoop::with_sensor_reply` is given, `SENSOR` replies are read as whatever
+    /// arrived within half a period.
+    pub fn new(comm: &'a mut Communicator, period: Duration) -> Self {
+        Self {
+            comm,
+            period,
+            sensor_reply: ReadSpec { mode: ReadMode::Available, timeout: Some(period / 2) },
+            stats_interval: STATS_INTERVAL,
+            queue: CommandQueue::default(),
+            stats: ControlStats::default(),
+            log: None,
+            delta: None,
+        }
+    }
+
+    /// Reads `SENSOR` replies as per `spec`, e.g. `ReadMode::Exact` to return as soon as the
+    /// reply is complete.
+    #[must_use]
+    pub const fn with_sensor_reply(mut self, spec: ReadSpec) -> Self {
+        self.sensor_reply = spec;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_stats_interval(mut self, interval: Duration) -> Self {
+        self.stats_interval = interval;
+        self
+    }
+
+    /// Logs each `SENSOR` reading to `log`, along with the commands written before it.
+    #[must_use]
+    pub fn with_log(mut self, log: &'a mut SensorLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Delta-encodes `MAGNET` and `LED` against the board state mirrored by the `Communicator`
+    /// through `encoder`, also writing its due full refreshes on each tick. Deltas are only sent
+    /// once the mirror is synced, see `Communicator::sync_state`.
+    #[must_use]
+    pub fn with_delta(mut self, encoder: DeltaEncoder) -> Self {
+        self.delta = Some(encoder);
+        self
+    }
+
+    #[must_use]
+    pub const fn stats(&self) -> &ControlStats {
+        &self.stats
+    }
+
+    /// Queues `instr` for the next tick, coalescing it with any command it supersedes.
+    pub fn push(&mut self, instr: Instruction) {
+        if self.queue.push(instr) { self.stats.coalesced += 1; }
+    }
+
+    /// Writes `frames` in order, each awaiting its `ACK`. Makes the next delta-encoded commands
+    /// go out in full on failure, as the mirrored state may be off.
+    fn _write_acked(&mut self, frames: Vec<Instruction>) -> io::Result<()> {
+        let ack = ReadSpec { mode: ReadMode::Until(bindings::ACK), timeout: Some(self.period) };
+        for frame in frames {
+            let res = self.comm.execute(&Request::Write(frame)).and_then(|_| self.comm.read(&ack));
+            if let (Err(_), Some(delta)) = (&res, self.delta.as_mut()) { delta.force_full_refresh(); }
+            res?;
+        }
+        return Ok(());
+    }
+
+    /// Runs one tick, i.e. writes the queued commands, each awaiting its `ACK`, any full refreshes
+    /// due if delta-encoding, then `SENSOR`.
+    ///
+    /// ### Returns
+    /// The `SENSOR` reply.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing commands or reading their replies. Commands not yet written
+    /// are dropped. Errors from logging are only logged to stderr.
+    pub fn tick(&mut self) -> io::Result<Response> {
+        const _FN_NAME: &str = "[ControlLoop::tick]";
+
+        for instr in self.queue.drain() {
+            let frames = match self.delta.as_mut() {
+                Some(delta) => delta.encode(self.comm.board_state(), &instr),
+                None => vec![instr.clone()],
+            };
+            self._write_acked(frames)?;
+            if let Some(log) = self.log.as_mut() { log.record_command(&instr); }
+        }
+        if let Some(refreshes) = self.delta.as_mut().map(DeltaEncoder::take_due_refreshes) {
+            self._write_acked(refreshes)?;
+        }
+        self.comm.execute(&Request::from(&Command::Sensor))?;
+        let res = self.comm.read(&self.sensor_reply)?;
+        if let Some(Err(e)) = self.log.as_mut().map(|log| log.record_response(&res)) {
+            error!("{_FN_NAME} Cannot log reading: {e}");
+        }
+        return Ok(res);
+    }
+
+    /// Moves commands arriving on `commands` into the queue until `until`.
+    ///
+    /// ### Returns
+    /// `false` once `commands` has disconnected and is drained.
+    fn _receive_until(&mut self, commands: &Receiver<Instruction>, until: Instant) -> bool {
+        loop {
+            let left = until.saturating_duration_since(Instant::now());
+            let received = if left.is_zero() {
+                commands.try_recv().map_err(|e| match e {
+                    TryRecvError::Empty        => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                })
+            } else {
+                commands.recv_timeout(left)
+            };
+            match received {
+                Ok(instr) => self.push(instr),
+                Err(RecvTimeoutError::Timeout) => if left.is_zero() { return true; },
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+        }
+    }
+
+    /// Runs the loop until `commands` disconnects or `interrupt` is set, writing each `SENSOR`
+    /// reading, any link events and, every stats interval, the statistics to `out` in `format`.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to `out`. Errors from the Arduino are reported to `out` and
+    /// counted in the statistics instead.
+    pub fn run(
+        &mut self,
+        commands: &Receiver<Instruction>,
+        format: Format,
+        out: &mut dyn Write,
+        interrupt: &Interrupt,
+    ) -> io::Result<()> {
+        const _FN_NAME: &str = "[ControlLoop::run]";
+
+        if self.delta.is_some() && !self.comm.board_state().synced {
+            if let Err(e) = self.comm.sync_state() {
+                warn!("{_FN_NAME} Cannot sync state, sending commands in full: {e}");
+            }
+        }
+        let start = Instant::now();
+        let mut next_stats = start + self.stats_interval;
+        let mut scheduled = start;
+        let mut open = true;
+        while open && !interrupt.is_set() {
+            /* 1. Wait for tick, queueing commands meanwhile */
+            open = self._receive_until(commands, scheduled);
+
+            /* 2. Tick */
+            let began = Instant::now();
+            let res = self.tick();
+            self.stats._record(began - scheduled, began.elapsed());
+            match res {
+                Ok(r)  => format.write_response(out, &r)?,
+                Err(e) => {
+                    self.stats.errors += 1;
+                    warn!("{_FN_NAME} Tick failed: {e}");
+                    format.write_error(out, Status::from(&e), self.comm.device(), &e.to_string())?;
+                },
+            }
+            for event in self.comm.take_events() {
+                info!("{_FN_NAME} {event:?}");
+                format.write_event(out, &event)?;
+            }
+
+            /* 3. Schedule next tick, skipping those already missed */
+            scheduled += self.period;
+            let now = Instant::now();
+            if now > scheduled {
+                let missed = (now - scheduled).as_nanos() / self.period.as_nanos().max(1) + 1;
+                self.stats.deadline_misses += u64::try_from(missed).unwrap_or(u64::MAX);
+                scheduled += self.period * u32::try_from(missed).unwrap_or(u32::MAX);
+            }
+
+            if now >= next_stats {
+                next_stats += self.stats_interval;
+                info!("{_FN_NAME} {:?}", self.stats);
+                format.write_stats(out, self.comm.device(), &self.stats)?;
+            }
+        }
+        info!("{_FN_NAME} Stopped after {:?}: {:?}", start.elapsed(), self.stats);
+        return format.write_stats(out, self.comm.device(), &self.stats);
+    }
+}
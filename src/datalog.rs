@@ -0,0 +1,362 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Logging of decoded `SENSOR` readings for offline analysis.
+//!
+//! Each reading is logged as one row of its timestamp, device and readings, followed by the
+//! `MAGNET` and `LED` commands in effect at the time, in the argument syntax of the text grammar,
+//! e.g. `1 2 true 3 4 false` for `WRITE MAGNET 1 2 true 3 4 false`. Rows go to CSV, or to Parquet
+//! with the `parquet` feature.
+//!
+//! The log starts at the given path and rotates to numbered files next to it, e.g. `run.csv`,
+//! `run.1.csv`, `run.2.csv`, once a file holds enough rows or is old enough, or when the number of
+//! readings per row changes.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::info;
+
+use crate::bindings;
+use crate::response::{to_hex, Decoded, Response};
+
+/// Commands whose latest instance is logged with each reading, with their column names.
+const COMMAND_COLUMNS: [(&str, u8); 2] = [("magnet", bindings::MAGNET), ("led", bindings::LED)];
+
+/// File format of a `SensorLog`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Csv,
+    /// Requires the `parquet` feature.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl LogFormat {
+    const fn _extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+/// When a `SensorLog` moves on to a new file. Never if neither is set.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Rotation {
+    pub max_rows: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+/// One logged reading.
+#[derive(Debug, PartialEq, Clone)]
+struct Row {
+    timestamp: u64,
+    device: String,
+    readings: Vec<f32>,
+    commands: [String; COMMAND_COLUMNS.len()],
+}
+
+/// Formats the arguments of `instr` in the syntax of the text grammar, or as hex if the opcode
+/// takes none.
+fn _describe(instr: &[u8]) -> String {
+    let mut s = String::new();
+    match instr.split_first() {
+        Some((&bindings::MAGNET, args)) => for c in args.chunks_exact(9) {
+            let x = f32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+            let y = f32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+            let _ = write!(s, "{x} {y} {} ", c[8] != 0);
+        },
+        Some((&bindings::LED, args)) => for c in args.chunks_exact(3) {
+            let _ = write!(s, "{} ", u32::from_be_bytes([0, c[0], c[1], c[2]]));
+        },
+        _ => s = to_hex(instr),
+    }
+    s.truncate(s.trim_end().len());
+    return s;
+}
+
+/// Quotes `field` for CSV if needed.
+fn _csv_field(field: &str) -> String {
+    if !field.contains([',', '"', '\n', '\r']) { return field.to_owned(); }
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// File currently written to by a `SensorLog`.
+enum Sink {
+    Csv(BufWriter<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_sink::ParquetSink),
+}
+
+impl Sink {
+    fn create(path: &Path, format: LogFormat, readings: usize) -> io::Result<Self> {
+        let file = File::create(path)?;
+        match format {
+            LogFormat::Csv => {
+                let mut out = BufWriter::new(file);
+                let mut header = String::from("timestamp,device");
+                for i in 0..readings { let _ = write!(header, ",reading_{i}"); }
+                for (name, _) in COMMAND_COLUMNS { let _ = write!(header, ",{name}"); }
+                writeln!(out, "{header}")?;
+                Ok(Self::Csv(out))
+            },
+            #[cfg(feature = "parquet")]
+            LogFormat::Parquet => Ok(Self::Parquet(parquet_sink::ParquetSink::new(file, readings)?)),
+        }
+    }
+
+    fn write_row(&mut self, row: Row) -> io::Result<()> {
+        match self {
+            Self::Csv(out) => {
+                let mut line = format!("{},{}", row.timestamp, _csv_field(&row.device));
+                for r in &row.readings { let _ = write!(line, ",{r}"); }
+                for c in &row.commands { let _ = write!(line, ",{}", _csv_field(c)); }
+                writeln!(out, "{line}")
+            },
+            #[cfg(feature = "parquet")]
+            Self::Parquet(sink) => sink.write_row(row),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Csv(out) => out.flush(),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(sink) => sink.flush(),
+        }
+    }
+
+    fn close(self) -> io::Result<()> {
+        match self {
+            Self::Csv(mut out) => out.flush(),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(sink) => sink.close(),
+        }
+    }
+}
+
+/// Current file of a `SensorLog`, with what decides its rotation.
+struct Current {
+    sink: Sink,
+    readings: usize,
+    rows: u64,
+    opened: Instant,
+}
+
+/// Logs decoded `SENSOR` readings, along with the commands in effect, to rotating files.
+///
+/// Call `SensorLog::close` when done, as Parquet files are incomplete until closed.
+pub struct SensorLog {
+    path: PathBuf,
+    format: LogFormat,
+    rotation: Rotation,
+    current: Option<Current>,
+    /// Number of files opened so far.
+    files: u32,
+    commands: [Option<Vec<u8>>; COMMAND_COLUMNS.len()],
+}
+
+impl SensorLog {
+    /// Creates a log starting at `path` in `format`. Files are only created on the first reading.
+    #[must_use]
+    pub fn new(path: &Path, format: LogFormat) -> Self {
+        Self {
+            path: path.to_owned(),
+            format,
+            rotation: Rotation::default(),
+            current: None,
+            files: 0,
+            commands: Default::default(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Path of the `n`-th file of the log, counting from 0, i.e. `path` itself for the first and
+    /// `path` with `n` inserted before its extension otherwise.
+    #[must_use]
+    pub fn file_path(&self, n: u32) -> PathBuf {
+        if n == 0 { return self.path.clone(); }
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = self.path.extension().map_or_else(
+            || self.format._extension().into(),
+            |e| e.to_string_lossy(),
+        );
+        self.path.with_file_name(format!("{stem}.{n}.{ext}"))
+    }
+
+    /// Records `instr` as written to the Arduino, so that it is logged with later readings if it
+    /// is one of `MAGNET` or `LED`. `QUIT` clears both.
+    pub fn record_command(&mut self, instr: &[u8]) {
+        match instr.first() {
+            Some(&bindings::QUIT) => self.commands = Default::default(),
+            Some(opcode) => {
+                let column = COMMAND_COLUMNS.iter().position(|(_, op)| op == opcode);
+                if let Some(i) = column { self.commands[i] = Some(instr.to_owned()); }
+            },
+            None => (),
+        }
+    }
+
+    /// Logs `res` if it is a `SENSOR` reply, rotating files as needed.
+    ///
+    /// # Errors
+    /// Any `io::Error` from creating, writing to or closing log files.
+    pub fn record_response(&mut self, res: &Response) -> io::Result<()> {
+        const _FN_NAME: &str = "[SensorLog::record_response]";
+
+        let Decoded::Sensor { readings } = &res.decoded else { return Ok(()); };
+        let rotate = self.current.as_ref().is_some_and(|c| {
+            c.readings != readings.len()
+                || self.rotation.max_rows.is_some_and(|n| c.rows >= n)
+                || self.rotation.max_age.is_some_and(|age| c.opened.elapsed() >= age)
+        });
+        if rotate { self._close_current()?; }
+        let current = match self.current.take() {
+            Some(c) => c,
+            None => {
+                let path = self.file_path(self.files);
+                info!("{_FN_NAME} Logging readings to {}", path.display());
+                let sink = Sink::create(&path, self.format, readings.len())?;
+                self.files += 1;
+                Current { sink, readings: readings.len(), rows: 0, opened: Instant::now() }
+            },
+        };
+        let current = self.current.insert(current);
+
+        let row = Row {
+            timestamp: res.timestamp,
+            device: res.device.clone(),
+            readings: readings.clone(),
+            commands: self.commands.each_ref().map(|c| c.as_deref().map(_describe).unwrap_or_default()),
+        };
+        current.sink.write_row(row)?;
+        current.rows += 1;
+        return Ok(());
+    }
+
+    /// Flushes rows logged so far, e.g. before the process may be killed.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to the log file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.current.as_mut().map_or(Ok(()), |c| c.sink.flush())
+    }
+
+    fn _close_current(&mut self) -> io::Result<()> {
+        self.current.take().map_or(Ok(()), |c| c.sink.close())
+    }
+
+    /// Flushes and closes the current file.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to or closing the log file.
+    pub fn close(mut self) -> io::Result<()> {
+        self._close_current()
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::fs::File;
+    use std::io;
+    use std::sync::Arc;
+
+    use parquet::data_type::{ByteArray, ByteArrayType, FloatType, Int64Type};
+    use parquet::errors::ParquetError;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    use super::{Row, COMMAND_COLUMNS};
+
+    /// Rows buffered before being written out as a row group.
+    const ROW_GROUP_ROWS: usize = 1024;
+
+    fn _io_error(e: ParquetError) -> io::Error {
+        io::Error::other(e)
+    }
+
+    /// Parquet file of `Row`s, written a row group at a time.
+    pub struct ParquetSink {
+        writer: SerializedFileWriter<File>,
+        rows: Vec<Row>,
+    }
+
+    impl ParquetSink {
+        pub fn new(file: File, readings: usize) -> io::Result<Self> {
+            let mut schema = String::from(
+                "message sensor_log { REQUIRED INT64 timestamp (TIMESTAMP(MILLIS,true)); REQUIRED BYTE_ARRAY device (UTF8); "
+            );
+            for i in 0..readings { schema.push_str(&format!("REQUIRED FLOAT reading_{i}; ")); }
+            for (name, _) in COMMAND_COLUMNS { schema.push_str(&format!("REQUIRED BYTE_ARRAY {name} (UTF8); ")); }
+            schema.push('}');
+            let schema = Arc::new(parse_message_type(&schema).map_err(_io_error)?);
+            let props = Arc::new(WriterProperties::builder().build());
+            Ok(Self {
+                writer: SerializedFileWriter::new(file, schema, props).map_err(_io_error)?,
+                rows: Vec::with_capacity(ROW_GROUP_ROWS),
+            })
+        }
+
+        pub fn write_row(&mut self, row: Row) -> io::Result<()> {
+            self.rows.push(row);
+            if self.rows.len() >= ROW_GROUP_ROWS { return self.flush(); }
+            Ok(())
+        }
+
+        /// Writes buffered rows out as one row group.
+        pub fn flush(&mut self) -> io::Result<()> {
+            if self.rows.is_empty() { return Ok(()); }
+            let rows = std::mem::take(&mut self.rows);
+            let mut group = self.writer.next_row_group().map_err(_io_error)?;
+            let mut idx = 0;
+            while let Some(mut column) = group.next_column().map_err(_io_error)? {
+                let readings = rows[0].readings.len();
+                let res = match idx {
+                    0 => {
+                        #[allow(clippy::cast_possible_wrap)]
+                        let values: Vec<i64> = rows.iter().map(|r| r.timestamp as i64).collect();
+                        column.typed::<Int64Type>().write_batch(&values, None, None)
+                    },
+                    1 => {
+                        let values: Vec<ByteArray> = rows.iter().map(|r| r.device.as_str().into()).collect();
+                        column.typed::<ByteArrayType>().write_batch(&values, None, None)
+                    },
+                    i if i < 2 + readings => {
+                        let values: Vec<f32> = rows.iter().map(|r| r.readings[i - 2]).collect();
+                        column.typed::<FloatType>().write_batch(&values, None, None)
+                    },
+                    i => {
+                        let values: Vec<ByteArray> = rows
+                            .iter()
+                            .map(|r| r.commands[i - 2 - readings].as_str().into())
+                            .collect();
+                        column.typed::<ByteArrayType>().write_batch(&values, None, None)
+                    },
+                };
+                res.map_err(_io_error)?;
+                column.close().map_err(_io_error)?;
+                idx += 1;
+            }
+            group.close().map_err(_io_error)?;
+            Ok(())
+        }
+
+        pub fn close(mut self) -> io::Result<()> {
+            self.flush()?;
+            self.writer.close().map_err(_io_error)?;
+            Ok(())
+        }
+    }
+}
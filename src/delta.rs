@@ -0,0 +1,159 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Delta encoding of `MAGNET` and `LED` commands, for links too slow to resend the whole board.
+//!
+//! A `DeltaEncoder` diffs each desired state against the last acknowledged one, as mirrored by the
+//! `Communicator`, and only sends the changed entries as `MAGNET_DELTA` or `LED_DELTA`, split into
+//! frames of at most `max_frame_len` bytes. The full command is sent instead while the mirror is
+//! not synced, when the number of cells or LEDs changes, when an index does not fit in `u8`, and
+//! every `full_refresh` interval to recover from drift.
+
+use std::time::{Duration, Instant};
+
+use crate::bindings;
+use crate::board::{decode_cells, decode_colors, BoardState};
+use crate::command::Command;
+use crate::Instruction;
+
+/// Default interval between full refreshes.
+pub const FULL_REFRESH: Duration = Duration::from_secs(10);
+/// Default maximum frame length, i.e. the size of the Arduino serial receive buffer.
+pub const MAX_FRAME_LEN: usize = 64;
+
+/// Desired state of one of `MAGNET` and `LED`.
+#[derive(Debug, Default)]
+struct Track {
+    /// Last full command, as requested.
+    desired: Option<Instruction>,
+    last_full: Option<Instant>,
+}
+
+impl Track {
+    fn _is_refresh_due(&self, interval: Duration) -> bool {
+        self.last_full.is_none_or(|t| t.elapsed() >= interval)
+    }
+}
+
+/// Turns full `MAGNET` and `LED` commands into delta frames.
+#[derive(Debug)]
+pub struct DeltaEncoder {
+    full_refresh: Duration,
+    max_frame_len: usize,
+    magnets: Track,
+    leds: Track,
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Number of entries of `entry_len` bytes that fit in a frame of `max_len` bytes after the
+/// opcode, yet at least one.
+const fn _entries_per_frame(entry_len: usize, max_len: usize) -> usize {
+    let n = max_len.saturating_sub(1) / entry_len;
+    if n == 0 { 1 } else { n }
+}
+
+/// Indexed entries of `desired` that differ from `acked`.
+///
+/// ### Returns
+/// `None` if lengths differ or an index does not fit in `u8`.
+fn _diff<T: PartialEq + Copy>(acked: &[T], desired: &[T]) -> Option<Vec<(u8, T)>> {
+    if acked.len() != desired.len() { return None; }
+    acked
+        .iter()
+        .zip(desired)
+        .enumerate()
+        .filter(|(_, (a, d))| a != d)
+        .map(|(i, (_, d))| u8::try_from(i).ok().map(|i| (i, *d)))
+        .collect()
+}
+
+impl DeltaEncoder {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            full_refresh: FULL_REFRESH,
+            max_frame_len: MAX_FRAME_LEN,
+            magnets: Track { desired: None, last_full: None },
+            leds: Track { desired: None, last_full: None },
+        }
+    }
+
+    #[must_use]
+    pub const fn with_full_refresh(mut self, interval: Duration) -> Self {
+        self.full_refresh = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Encodes `instr` into the frames that bring the board from `acked` to the state `instr`
+    /// requests. Instructions other than `MAGNET` and `LED` pass through unchanged; `QUIT` also
+    /// forgets the desired state.
+    ///
+    /// ### Returns
+    /// Frames to write in order, each awaiting its `ACK`. Empty if nothing changed.
+    pub fn encode(&mut self, acked: &BoardState, instr: &[u8]) -> Vec<Instruction> {
+        let Some((&opcode, args)) = instr.split_first() else { return Vec::new(); };
+        let max_len = self.max_frame_len;
+        let (track, frames) = match opcode {
+            bindings::MAGNET => (
+                &mut self.magnets,
+                _diff(&acked.magnets, &decode_cells(args)).map(|cells| cells
+                    .chunks(_entries_per_frame(10, max_len))
+                    .map(|c| Command::MagnetDelta { cells: c.to_vec() }.encode())
+                    .collect::<Vec<_>>()),
+            ),
+            bindings::LED => (
+                &mut self.leds,
+                _diff(&acked.leds, &decode_colors(args)).map(|colors| colors
+                    .chunks(_entries_per_frame(4, max_len))
+                    .map(|c| Command::LedDelta { colors: c.to_vec() }.encode())
+                    .collect::<Vec<_>>()),
+            ),
+            bindings::QUIT => {
+                self.magnets = Track::default();
+                self.leds = Track::default();
+                return vec![instr.to_vec()];
+            },
+            _ => return vec![instr.to_vec()],
+        };
+        track.desired = Some(instr.to_vec());
+        match frames {
+            Some(frames) if acked.synced && !track._is_refresh_due(self.full_refresh) => frames,
+            _ => {
+                track.last_full = Some(Instant::now());
+                vec![instr.to_vec()]
+            },
+        }
+    }
+
+    /// Takes the full commands whose refresh is due, marking them refreshed.
+    ///
+    /// ### Returns
+    /// The last desired `MAGNET` and `LED` commands not sent in full for `full_refresh`.
+    pub fn take_due_refreshes(&mut self) -> Vec<Instruction> {
+        let mut due = Vec::new();
+        for track in [&mut self.magnets, &mut self.leds] {
+            let Some(desired) = track.desired.as_ref() else { continue; };
+            if track._is_refresh_due(self.full_refresh) {
+                due.push(desired.clone());
+                track.last_full = Some(Instant::now());
+            }
+        }
+        return due;
+    }
+
+    /// Makes the next `MAGNET` and `LED` go out in full, e.g. after a failed write.
+    pub const fn force_full_refresh(&mut self) {
+        self.magnets.last_full = None;
+        self.leds.last_full = None;
+    }
+}
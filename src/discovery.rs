@@ -0,0 +1,198 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+extern crate serialport;
+
+use std::io;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::{info, warn};
+use serialport::{SerialPort, SerialPortType};
+
+use crate::bindings;
+use crate::util::serial_helper::{read_all_bytes_after, write_all_bytes};
+
+pub const BAUD_RATE_OPTIONS: [u32; 2] = [115_200, 9_600];
+
+/// Interval between `HANDSHAKE`s sent while waiting for an Arduino to become ready.
+const HANDSHAKE_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+/// How long DTR is held low to reset an Arduino.
+const RESET_PULSE: Duration = Duration::from_millis(100);
+
+/// How DTR is driven on open. Most Arduinos reset on a falling edge of DTR.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum DtrMode {
+    /// Assert DTR on open as the OS does, which resets the board if it was deasserted before.
+    #[default]
+    Assert,
+    /// Pulse DTR low after open, forcing a clean reset.
+    Reset,
+    /// Keep DTR deasserted on open, so that the board is not reset.
+    NoReset,
+}
+
+/// How to open and wait for an Arduino.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpenOptions {
+    pub dtr: DtrMode,
+    /// How long to wait for the Arduino to become ready after open.
+    pub ready_timeout: Duration,
+    /// Banner printed by the firmware on boot, if any. Its arrival also counts as ready.
+    pub banner: Option<Vec<u8>>,
+    /// Whether an Arduino not ready within `ready_timeout` at any baud rate is skipped, rather
+    /// than used at the first baud rate in `BAUD_RATE_OPTIONS` with a warning.
+    pub strict_ready: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self { dtr: DtrMode::default(), ready_timeout: Duration::from_secs(5), banner: None, strict_ready: false }
+    }
+}
+
+/// A connected Arduino `tty` device.
+pub struct DiscoveredPort {
+    pub port: Box<dyn SerialPort>,
+    /// USB serial number of the device, if reported.
+    pub serial_number: Option<String>,
+}
+
+/// Waits until the Arduino on `port` replies to `HANDSHAKE` or sends `banner`.
+///
+/// `HANDSHAKE` is resent periodically, as a resetting Arduino drops whatever it receives while in
+/// the bootloader. Its reply counts only as the last byte received so far, so that a `HANDSHAKE`
+/// byte amid boot noise is not mistaken for it.
+///
+/// ### Returns
+/// - `Ok(())` once the Arduino is ready.
+/// - `Err(io::Error)` of kind `io::ErrorKind::TimedOut` if it was not ready within `timeout`.
+/// - `Err(io::Error)` if cannot read from or write to `port`.
+///
+/// # Errors
+/// See above.
+pub fn wait_until_ready(
+    port: &mut dyn SerialPort,
+    banner: Option<&[u8]>,
+    timeout: Duration,
+) -> io::Result<()> {
+    const _FN_NAME: &str = "[discovery::wait_until_ready]";
+
+    let deadline = Instant::now() + timeout;
+    let mut received: Vec<u8> = Vec::new();
+    let mut chunk: Vec<u8> = Vec::new();
+    while Instant::now() < deadline {
+        write_all_bytes(port, &[bindings::HANDSHAKE])?;
+        port.flush()?;
+        let wait = HANDSHAKE_RETRY_INTERVAL.min(deadline.saturating_duration_since(Instant::now()));
+        match read_all_bytes_after(port, &mut chunk, wait) {
+            Ok(_) => received.extend_from_slice(&chunk),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        }
+
+        if received.last() == Some(&bindings::HANDSHAKE) {
+            info!("{_FN_NAME} Handshake reply received after {received:x?}");
+            return Ok(());
+        }
+        if let Some(b) = banner {
+            if !b.is_empty() && received.windows(b.len()).any(|w| w == b) {
+                info!("{_FN_NAME} Boot banner received");
+                // Discard handshake replies still in flight
+                sleep(HANDSHAKE_RETRY_INTERVAL);
+                port.clear(serialport::ClearBuffer::Input)?;
+                return Ok(());
+            }
+        }
+    }
+    return Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("{_FN_NAME} Arduino not ready within {timeout:?}, received {received:x?}")
+    ));
+}
+
+/// Opens `port_name` at `baud_rate` and drives DTR as per `opts`.
+fn _open(port_name: &str, baud_rate: u32, opts: &OpenOptions) -> io::Result<Box<dyn SerialPort>> {
+    let mut port = serialport::new(port_name, baud_rate)
+        .timeout(Duration::from_secs(1))
+        .flow_control(serialport::FlowControl::None)
+        .dtr_on_open(opts.dtr != DtrMode::NoReset)
+        .open()?;
+    if opts.dtr == DtrMode::Reset {
+        port.write_data_terminal_ready(false)?;
+        sleep(RESET_PULSE);
+        port.write_data_terminal_ready(true)?;
+    }
+    port.clear(serialport::ClearBuffer::All)?;
+    return Ok(port);
+}
+
+/// Same as `_open`, then waits for the Arduino to be ready.
+fn _open_ready(port_name: &str, baud_rate: u32, opts: &OpenOptions) -> io::Result<Box<dyn SerialPort>> {
+    let mut port = _open(port_name, baud_rate, opts)?;
+    wait_until_ready(port.as_mut(), opts.banner.as_deref(), opts.ready_timeout)?;
+    return Ok(port);
+}
+
+/// Tries to connect to relevant Arduino tty devices (i.e., all Arduinos connected to host).
+/// If `serial_number` is given, only devices reporting that USB serial number are connected to.
+///
+/// Each device is opened at the first baud rate in `BAUD_RATE_OPTIONS` at which it becomes ready
+/// as per `wait_until_ready`. If it is ready at none, it is opened at the first baud rate anyway
+/// with a warning, unless `opts.strict_ready`.
+///
+/// ### Returns
+/// - `Ok(ports)` which encapsulates `Vec` of `DiscoveredPort`s.
+/// - `Err(io::Error)` which is of kind `io::ErrorKind::NotFound`, indicating that no suitable `tty`
+///   devices could be found.
+///
+/// # Errors
+/// See above.
+pub fn find_arduino_serialports(
+    serial_number: Option<&str>,
+    opts: &OpenOptions,
+) -> io::Result<Vec<DiscoveredPort>> {
+    const _FN_NAME: &str = "[serial-communicator::find_arduino_serialport]";
+
+    let mut port_buf: Vec<DiscoveredPort> = Vec::with_capacity(2);
+    let available_ports = serialport::available_ports()?;
+    for info in &available_ports {
+        if let SerialPortType::UsbPort(t) = &info.port_type {
+            // Do not check for metadata, which enables 3rd party boards to be used
+            info!("{:#?}", t);
+            if t.vid != 0x3343 || t.pid != 0x0042 { continue; } // Not an Arduino
+            if serial_number.is_some() && t.serial_number.as_deref() != serial_number {
+                continue; // Not the Arduino asked for
+            }
+
+            let ready = BAUD_RATE_OPTIONS.iter().find_map(|&baud_rate| {
+                _open_ready(&info.port_name, baud_rate, opts)
+                    .inspect_err(|e| warn!("{_FN_NAME} Cannot use {} at {baud_rate} baud: {e}", info.port_name))
+                    .ok()
+            });
+            let port = match ready {
+                Some(port) => Some(port),
+                None if opts.strict_ready => None,
+                None => {
+                    let baud_rate = BAUD_RATE_OPTIONS[0];
+                    warn!("{_FN_NAME} {} not ready, proceeding at {baud_rate} baud", info.port_name);
+                    _open(&info.port_name, baud_rate, opts)
+                        .inspect_err(|e| warn!("{_FN_NAME} Cannot open {}: {e}", info.port_name))
+                        .ok()
+                },
+            };
+            if let Some(port) = port {
+                port_buf.push(DiscoveredPort { port, serial_number: t.serial_number.clone() });
+            }
+        }
+    }
+
+    if port_buf.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{_FN_NAME} No Arduino `tty` device connected to host!")
+        ));
+    } else {
+        return Ok(port_buf);
+    }
+}
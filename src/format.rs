@@ -0,0 +1,264 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::board::BoardState;
+use crate::command::TypedRequest;
+use crate::communicator::LinkEvent;
+use crate::control::ControlStats;
+use crate::response::{timestamp_now, Response};
+use crate::{Request, RequestConversionError};
+
+/// Wire format of the stdin/stdout loop.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+    /// Whitespace-separated text requests on stdin, raw response bytes on stdout. A failed READ
+    /// produces a line of form `<STATUS>: <message>` instead, e.g. `TIMEOUT: ...`.
+    #[default]
+    Text,
+    /// One JSON `TypedRequest` per stdin line, one JSON object per response or error on stdout.
+    Jsonl,
+    /// Text requests on stdin, one length-prefixed binary frame per READ result on stdout.
+    ///
+    /// Each frame is laid out as:
+    /// - `u32` (LE): Payload length.
+    /// - `u8`: `Status` code.
+    /// - `u8`: Device name length.
+    /// - `u64` (LE): Timestamp, in milliseconds since UNIX epoch.
+    /// - Device name, in UTF-8.
+    /// - Payload: Response bytes if `Status::Ok`, otherwise the error message in UTF-8.
+    ///
+    /// Invalid requests and WRITE errors produce no frame and are only logged.
+    /// `STATE` requests produce a frame with `Status::State`.
+    /// Link events produce unsolicited frames with `Status::Connected`, `Status::Disconnected`,
+    /// `Status::Stale` or `Status::Alive`, and control loop statistics with `Status::Stats`.
+    Framed,
+}
+
+/// Status of a READ result or link event, as reported in-band by `Jsonl` and `Framed` formats.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+#[repr(u8)]
+pub enum Status {
+    Ok      = 0,
+    Timeout = 1,
+    Error   = 2,
+    Invalid = 3,
+    /// Unsolicited: Connection to device lost. Payload is the reason.
+    Disconnected = 4,
+    /// Unsolicited: Connection to device (re-)established. Payload is empty.
+    Connected = 5,
+    /// Unsolicited: No heartbeat reply from device for a while. Payload is the silence in
+    /// milliseconds, as `u64` (LE).
+    Stale = 6,
+    /// Unsolicited: Heartbeat replies resumed after `Stale`. Payload is empty.
+    Alive = 7,
+    /// Unsolicited: Control loop statistics. Payload is the JSON-serialized `ControlStats`.
+    Stats = 8,
+    /// Reply to `STATE`. Payload is the JSON-serialized `BoardState`.
+    State = 9,
+}
+
+impl From<&io::Error> for Status {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut => Self::Timeout,
+            _                       => Self::Error,
+        }
+    }
+}
+
+fn _write_frame(
+    out: &mut dyn Write,
+    status: Status,
+    device: &str,
+    timestamp: u64,
+    payload: &[u8]
+) -> io::Result<()> {
+    let payload_len = u32::try_from(payload.len())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // Truncate device names that do not fit in length byte
+    let device = &device.as_bytes()[..device.len().min(u8::MAX as usize)];
+
+    out.write_all(&payload_len.to_le_bytes())?;
+    out.write_all(&[status as u8])?;
+    #[allow(clippy::cast_possible_truncation)]
+    out.write_all(&[device.len() as u8])?;
+    out.write_all(&timestamp.to_le_bytes())?;
+    out.write_all(device)?;
+    out.write_all(payload)
+}
+
+impl Format {
+    /// Parses one line of stdin into a `Request`.
+    ///
+    /// # Errors
+    /// - `RequestConversionError` from `Request::try_from` in `Text` format.
+    /// - `RequestConversionError::MalformedOpSequence` if the line is not a valid `TypedRequest`
+    ///   in `Jsonl` format.
+    pub fn parse_request(self, line: &str) -> Result<Request, RequestConversionError> {
+        const _FN_NAME: &str = "[Format::parse_request]";
+
+        match self {
+            Self::Text | Self::Framed => Request::try_from(line),
+            Self::Jsonl => {
+                if line.trim().is_empty() {
+                    return Err(RequestConversionError::EmptyOpSequence(
+                        format!("{_FN_NAME} Empty sequence as input")
+                    ));
+                }
+                serde_json::from_str::<TypedRequest>(line)
+                    .map_err(|e| RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Invalid JSON request: {e}")
+                    ))
+                    .and_then(|r| Request::try_from(&r))
+            },
+        }
+    }
+
+    /// Writes `res` to `out` and flushes it.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to or flushing `out`.
+    pub fn write_response(self, out: &mut dyn Write, res: &Response) -> io::Result<()> {
+        match self {
+            Self::Text   => out.write_all(&res.raw)?,
+            Self::Jsonl  => {
+                serde_json::to_writer(&mut *out, res)?;
+                out.write_all(b"\n")?;
+            },
+            Self::Framed => _write_frame(out, Status::Ok, &res.device, res.timestamp, &res.raw)?,
+        }
+        out.flush()
+    }
+
+    /// Whether errors not tied to a READ, e.g. invalid requests, are reported in-band.
+    #[must_use]
+    pub const fn reports_request_errors(self) -> bool {
+        matches!(self, Self::Jsonl)
+    }
+
+    /// Reports an error on `device` to `out`, i.e.:
+    /// - As a line of form `<STATUS>: <message>` for `Text`.
+    /// - As a JSON object of form `{"status":...,"error":...,"device":...,"timestamp":...}` for
+    ///   `Jsonl`.
+    /// - As a frame with `status` and `message` as payload for `Framed`.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to or flushing `out`.
+    pub fn write_error(
+        self,
+        out: &mut dyn Write,
+        status: Status,
+        device: &str,
+        message: &str
+    ) -> io::Result<()> {
+        let timestamp = timestamp_now();
+        match self {
+            Self::Text   => writeln!(out, "{}: {message}", format!("{status:?}").to_uppercase())?,
+            Self::Jsonl  => {
+                serde_json::to_writer(
+                    &mut *out,
+                    &serde_json::json!({
+                        "status": status, "error": message, "device": device, "timestamp": timestamp
+                    }),
+                )?;
+                out.write_all(b"\n")?;
+            },
+            Self::Framed => _write_frame(out, status, device, timestamp, message.as_bytes())?,
+        }
+        out.flush()
+    }
+
+    /// Reports link event `event` to `out` if errors are in-band for this format, i.e.:
+    /// - As the JSON-serialized `LinkEvent` for `Jsonl`.
+    /// - As a frame with the matching unsolicited `Status` for `Framed`.
+    ///
+    /// Does nothing for `Text`, whose events are only logged.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to or flushing `out`.
+    pub fn write_event(self, out: &mut dyn Write, event: &LinkEvent) -> io::Result<()> {
+        match (self, event) {
+            (Self::Text, _)   => return Ok(()),
+            (Self::Jsonl, _)  => {
+                serde_json::to_writer(&mut *out, event)?;
+                out.write_all(b"\n")?;
+            },
+            (Self::Framed, LinkEvent::Connected { device, timestamp }) =>
+                _write_frame(out, Status::Connected, device, *timestamp, &[])?,
+            (Self::Framed, LinkEvent::Disconnected { device, timestamp, reason }) =>
+                _write_frame(out, Status::Disconnected, device, *timestamp, reason.as_bytes())?,
+            (Self::Framed, LinkEvent::Stale { device, timestamp, silent_ms }) =>
+                _write_frame(out, Status::Stale, device, *timestamp, &silent_ms.to_le_bytes())?,
+            (Self::Framed, LinkEvent::Alive { device, timestamp }) =>
+                _write_frame(out, Status::Alive, device, *timestamp, &[])?,
+        }
+        out.flush()
+    }
+
+    /// Reports control loop statistics `stats` on `device` to `out` if errors are in-band for this
+    /// format, i.e.:
+    /// - As a JSON object of form `{"event":"stats","device":...,"timestamp":...}` with the fields
+    ///   of `stats` for `Jsonl`.
+    /// - As a frame with `Status::Stats` and the JSON-serialized `stats` as payload for `Framed`.
+    ///
+    /// Does nothing for `Text`, whose statistics are only logged.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to or flushing `out`.
+    pub fn write_stats(
+        self,
+        out: &mut dyn Write,
+        device: &str,
+        stats: &ControlStats
+    ) -> io::Result<()> {
+        match self {
+            Self::Text   => return Ok(()),
+            Self::Jsonl  => {
+                let mut obj = serde_json::to_value(stats)?;
+                obj["event"] = serde_json::Value::from("stats");
+                obj["device"] = serde_json::Value::from(device);
+                obj["timestamp"] = serde_json::Value::from(timestamp_now());
+                serde_json::to_writer(&mut *out, &obj)?;
+                out.write_all(b"\n")?;
+            },
+            Self::Framed => {
+                let payload = serde_json::to_vec(stats)?;
+                _write_frame(out, Status::Stats, device, timestamp_now(), &payload)?;
+            },
+        }
+        out.flush()
+    }
+
+    /// Writes board state `state` of `device` to `out` and flushes it, i.e.:
+    /// - As the JSON-serialized `state`, on a line of its own, for `Text`.
+    /// - As a JSON object of form `{"state":...,"device":...,"timestamp":...}` for `Jsonl`.
+    /// - As a frame with `Status::State` and the JSON-serialized `state` as payload for `Framed`.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to or flushing `out`.
+    pub fn write_state(self, out: &mut dyn Write, device: &str, state: &BoardState) -> io::Result<()> {
+        match self {
+            Self::Text   => {
+                serde_json::to_writer(&mut *out, state)?;
+                out.write_all(b"\n")?;
+            },
+            Self::Jsonl  => {
+                serde_json::to_writer(
+                    &mut *out,
+                    &serde_json::json!({ "state": state, "device": device, "timestamp": timestamp_now() }),
+                )?;
+                out.write_all(b"\n")?;
+            },
+            Self::Framed => {
+                let payload = serde_json::to_vec(state)?;
+                _write_frame(out, Status::State, device, timestamp_now(), &payload)?;
+            },
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,316 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! G-code programs driving the magnet table, e.g. paths exported from CAM tools.
+//!
+//! ## Supported subset
+//! One block per line. `;` and `( ... )` comments, line numbers `N<n>` and checksums `*<n>` are
+//! ignored, and letters are case-insensitive. Coordinates are millimetres once converted from
+//! inches, which `MAGNET` is assumed to take, e.g. through a `calibration::Calibration` in mm.
+//! - `G0`/`G1 [X<x>] [Y<y>] [F<feed>]`: Rapid or linear move, at `GcodeOptions::rapid_feed` or at
+//!   the last feed rate in units per minute. Becomes a `MOVE` while the magnet is on, and only
+//!   moves the position, without taking any time, while it is off. `X` and `Y` alone repeat the
+//!   last of `G0` and `G1`.
+//! - `G4 P<ms>` / `G4 S<s>`: Dwell.
+//! - `G20`/`G21`: Inches or millimetres. `G90`/`G91`: Absolute or relative coordinates.
+//! - `M3`/`M4` and `M5`: Magnet on and off at the current position, as `MAGNET` of one cell.
+//! - `M150 [R<r>] [U<g>] [B<b>] [I<i>]`: Colour of LED `i` as `LED_DELTA`, or of all
+//!   `GcodeOptions::leds` LEDs as `LED`. Channels are within `[0, 255]`, and 0 if missing.
+//! - `M2`/`M30`: End of program, ignoring any later blocks.
+
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::bindings;
+use crate::command::{Command, MagnetCell, TypedRequest};
+use crate::communicator::Communicator;
+use crate::shutdown::Interrupt;
+use crate::trajectory::Trajectory;
+use crate::{ReadMode, ReadSpec, Request};
+
+/// Default feed rate of `G0`, in millimetres per minute.
+pub const RAPID_FEED: f32 = 3000.0;
+/// Default feed rate of `G1` until set by `F`, in millimetres per minute.
+pub const FEED: f32 = 1000.0;
+
+/// Error from parsing or running a program, pointing at the offending line.
+#[derive(Debug)]
+pub struct GcodeError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for GcodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for GcodeError {}
+
+const fn _error(line: usize, message: String) -> GcodeError {
+    GcodeError { line, message }
+}
+
+/// Settings the program itself does not specify.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GcodeOptions {
+    pub rapid_feed: f32,
+    /// Feed rate until set by `F`.
+    pub feed: f32,
+    /// Number of LEDs set by `M150` without `I`.
+    pub leds: usize,
+}
+
+impl Default for GcodeOptions {
+    fn default() -> Self {
+        Self { rapid_feed: RAPID_FEED, feed: FEED, leds: 1 }
+    }
+}
+
+/// One request of a program, due `at_ms` milliseconds after its start.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Step {
+    pub at_ms: u64,
+    /// 1-based line number of the block it comes from.
+    pub line: usize,
+    #[serde(flatten)]
+    pub request: TypedRequest,
+}
+
+/// A parsed program, i.e. a timeline of `MAGNET`, `LED` and `MOVE` requests.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Program {
+    pub steps: Vec<Step>,
+    /// Time at which the last move or dwell ends.
+    pub duration: Duration,
+}
+
+/// Modal state of the interpreter.
+struct Machine {
+    options: GcodeOptions,
+    /// Millimetres per unit.
+    scale: f32,
+    relative: bool,
+    /// Whether `X` and `Y` alone move at the rapid feed rate.
+    rapid: bool,
+    feed: f32,
+    position: (f32, f32),
+    on: bool,
+    time: Duration,
+}
+
+/// Splits `block` into its words, e.g. `G1X10 Y-2.5` into `G1`, `X10`, `Y-2.5`.
+fn _words(block: &str) -> Result<Vec<(char, f32)>, String> {
+    let mut words = Vec::new();
+    let mut chars = block.chars().filter(|c| !c.is_whitespace()).peekable();
+    while let Some(letter) = chars.next() {
+        if !letter.is_ascii_alphabetic() { return Err(format!("Expected letter, got {letter}")); }
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) {
+            number.push(c);
+        }
+        let value = number.parse::<f32>().map_err(|_| format!("Invalid number after {letter}: {number:?}"))?;
+        if !value.is_finite() { return Err(format!("Invalid number after {letter}: {number}")); }
+        words.push((letter.to_ascii_uppercase(), value));
+    }
+    return Ok(words);
+}
+
+/// Strips comments, line number and checksum off `line`.
+fn _strip(line: &str) -> Result<String, String> {
+    let line = line.split(';').next().unwrap_or_default();
+    let mut block = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find('(') {
+        block.push_str(&rest[..open]);
+        let close = rest[open..].find(')').ok_or_else(|| String::from("Unclosed `(` comment"))?;
+        rest = &rest[open + close + 1..];
+    }
+    block.push_str(rest);
+    let block = block.split('*').next().unwrap_or_default().trim_start();
+    return Ok(match block.strip_prefix(['N', 'n']) {
+        Some(tail) => tail.trim_start_matches(|c: char| c.is_ascii_digit()).to_owned(),
+        None => block.to_owned(),
+    });
+}
+
+/// Channel of `M150` within `[0, 255]`.
+fn _channel(value: Option<f32>, letter: char) -> Result<u32, String> {
+    match value {
+        None => Ok(0),
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Some(v) if (0.0..=255.0).contains(&v) && v.fract() == 0.0 => Ok(v as u32),
+        Some(v) => Err(format!("Expected {letter} within [0, 255], got {v}")),
+    }
+}
+
+impl Machine {
+    fn _ms(&self) -> u64 {
+        u64::try_from(self.time.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    fn _magnet(&self) -> TypedRequest {
+        let (x, y) = self.position;
+        TypedRequest::Write(Command::Magnet { cells: vec![MagnetCell { x, y, on: self.on }] })
+    }
+
+    /// Runs `block`, pushing its requests to `steps`.
+    ///
+    /// ### Returns
+    /// Whether the program goes on.
+    fn _run(&mut self, line: usize, block: &str, steps: &mut Vec<Step>) -> Result<bool, String> {
+        let words = _words(block)?;
+        let param = |letter: char| words.iter().rev().find(|(l, _)| *l == letter).map(|(_, v)| *v);
+        let mut push = |at_ms: u64, request: TypedRequest| steps.push(Step { at_ms, line, request });
+
+        if let Some(f) = param('F') {
+            if f <= 0.0 { return Err(format!("Expected positive feed rate, got {f}")); }
+            self.feed = f;
+        }
+        let mut motion = None;
+        for &(letter, value) in &words {
+            // => Tenths, to tell e.g. `G1` from `G1.1`
+            #[allow(clippy::cast_possible_truncation)]
+            match (letter, (value * 10.0).round() as i32) {
+                ('G', 0) => motion = Some(true),
+                ('G', 10) => motion = Some(false),
+                ('G', 40) => {
+                    let dwell = match (param('P'), param('S')) {
+                        (Some(ms), _) if ms >= 0.0 => Duration::from_secs_f32(ms / 1000.0),
+                        (None, Some(s)) if s >= 0.0 => Duration::from_secs_f32(s),
+                        _ => return Err(String::from("Expected `G4 P<ms>` or `G4 S<s>`")),
+                    };
+                    self.time += dwell;
+                },
+                ('G', 200) => self.scale = 25.4,
+                ('G', 210) => self.scale = 1.0,
+                ('G', 900) => self.relative = false,
+                ('G', 910) => self.relative = true,
+                // => Only plane and feed mode there are
+                ('G', 170 | 940) => {},
+                ('M', 30 | 40) => {
+                    self.on = true;
+                    push(self._ms(), self._magnet());
+                },
+                ('M', 50) => {
+                    self.on = false;
+                    push(self._ms(), self._magnet());
+                },
+                ('M', 1500) => {
+                    let rgb = (_channel(param('R'), 'R')? << 16)
+                        | (_channel(param('U').or_else(|| param('G')), 'U')? << 8)
+                        | _channel(param('B'), 'B')?;
+                    let command = match param('I') {
+                        None => Command::Led { colors: vec![rgb; self.options.leds] },
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                        Some(i) if (0.0..=255.0).contains(&i) && i.fract() == 0.0 =>
+                            Command::LedDelta { colors: vec![(i as u8, rgb)] },
+                        Some(i) => return Err(format!("Expected I within [0, 255], got {i}")),
+                    };
+                    push(self._ms(), TypedRequest::Write(command));
+                },
+                ('M', 20 | 300) => return Ok(false),
+                ('G' | 'M', _) => return Err(format!("Unsupported {letter}{value}")),
+                _ => {},
+            }
+        }
+
+        let (x, y) = (param('X'), param('Y'));
+        if x.is_none() && y.is_none() {
+            if let Some(rapid) = motion { self.rapid = rapid; }
+            return Ok(true);
+        }
+        let rapid = motion.unwrap_or(self.rapid);
+        self.rapid = rapid;
+        let target = |axis: Option<f32>, from: f32| match axis {
+            Some(v) if self.relative => v.mul_add(self.scale, from),
+            Some(v) => v * self.scale,
+            None => from,
+        };
+        let from = self.position;
+        let to = (target(x, from.0), target(y, from.1));
+        self.position = to;
+        if !self.on || from == to { return Ok(true); }
+
+        let feed_mm_per_s = if rapid { self.options.rapid_feed } else { self.feed * self.scale } / 60.0;
+        let length = (to.0 - from.0).hypot(to.1 - from.1);
+        let trajectory = Trajectory::new(vec![from, to], Duration::from_secs_f32(length / feed_mm_per_s));
+        push(self._ms(), TypedRequest::Move(trajectory.clone()));
+        self.time += trajectory.duration();
+        return Ok(true);
+    }
+}
+
+impl Program {
+    /// Parses `src` into a `Program`.
+    ///
+    /// # Errors
+    /// `GcodeError` at the first malformed or unsupported block.
+    pub fn parse(src: &str, options: GcodeOptions) -> Result<Self, GcodeError> {
+        let mut machine = Machine {
+            options,
+            scale: 1.0,
+            relative: false,
+            rapid: true,
+            feed: options.feed,
+            position: (0.0, 0.0),
+            on: false,
+            time: Duration::ZERO,
+        };
+        let mut steps = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let block = _strip(line).map_err(|e| _error(i + 1, e))?;
+            if block.trim().is_empty() { continue; }
+            if !machine._run(i + 1, &block, &mut steps).map_err(|e| _error(i + 1, e))? { break; }
+        }
+        return Ok(Self { steps, duration: machine.time });
+    }
+
+    /// Writes the requests of the program to `out`, one JSON object per line with its due time
+    /// and line, e.g. `{"at_ms":0,"line":3,"op":"WRITE","cmd":"MAGNET","cells":[...]}`.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to `out`.
+    pub fn write_preview(&self, out: &mut dyn Write) -> io::Result<()> {
+        for step in &self.steps {
+            serde_json::to_writer(&mut *out, step)?;
+            writeln!(out)?;
+        }
+        return out.flush();
+    }
+
+    /// Runs the program against `comm`, each request on schedule and awaiting its `ACK`. Requests
+    /// running late delay the rest of the program.
+    ///
+    /// # Errors
+    /// `GcodeError` at the line whose request failed, or at which `interrupt` was set.
+    pub fn run(&self, comm: &mut Communicator, interrupt: &Interrupt) -> Result<(), GcodeError> {
+        let ack = ReadSpec { mode: ReadMode::Until(bindings::ACK), timeout: None };
+        let mut start = Instant::now();
+        for step in &self.steps {
+            let line = step.line;
+            if let Some(sig) = interrupt.signal() {
+                return Err(_error(line, format!("Interrupted by signal {sig}")));
+            }
+            let due = start + Duration::from_millis(step.at_ms);
+            comm.idle_for(due.saturating_duration_since(Instant::now()))
+                .map_err(|e| _error(line, format!("Heartbeat failed: {e}")))?;
+
+            let req = Request::try_from(&step.request).map_err(|e| _error(line, e.to_string()))?;
+            let res = match req {
+                Request::Write(_) => comm.execute(&req).and_then(|_| comm.read(&ack)).map(|_| ()),
+                _ => comm.execute(&req).map(|_| ()),
+            };
+            res.map_err(|e| _error(line, format!("Cannot run {req}: {e}")))?;
+            // => Shift the rest of the program by any delay
+            start += Instant::now().saturating_duration_since(due).saturating_sub(
+                if let TypedRequest::Move(t) = &step.request { t.duration() } else { Duration::ZERO }
+            );
+        }
+        return Ok(());
+    }
+}
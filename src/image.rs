@@ -0,0 +1,360 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! LED frames from image files, e.g. logos and test patterns for an LED panel.
+//!
+//! Images are resampled to the size of an `LedLayout` by averaging the pixels under each LED, and
+//! mapped onto its LEDs as one `LED` request per frame. Formats are told apart by their signature:
+//! - BMP and PNG: The image, or the first frame of animated PNGs.
+//! - GIF: Each frame of animated GIFs, with its delay, or the only frame of still ones.
+//!
+//! Transparent pixels are shown as black, i.e. off.
+
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ::image::codecs::gif::GifDecoder;
+use ::image::{AnimationDecoder, ImageDecoder, ImageFormat, ImageReader, Limits, RgbaImage};
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use serde::{Deserialize, Serialize};
+
+use crate::bindings;
+use crate::color;
+use crate::command::{Command, TypedRequest};
+use crate::communicator::Communicator;
+use crate::shutdown::Interrupt;
+use crate::{ReadMode, ReadSpec, Request};
+
+/// Largest image decoded, in pixels over all frames.
+pub const MAX_PIXELS: usize = 1 << 24;
+/// Delay of GIF frames asking for less than `MIN_GIF_DELAY`, as browsers do.
+pub const DEFAULT_GIF_DELAY: Duration = Duration::from_millis(100);
+const MIN_GIF_DELAY: Duration = Duration::from_millis(20);
+
+/// Order in which LEDs are chained along the rows of a panel.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// Every row in the same direction.
+    #[default]
+    RowMajor,
+    /// Every other row in the opposite direction, zigzagging down the panel.
+    Serpentine,
+}
+
+/// Corner of a panel at which its first LED is.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Layout of a panel of `width` by `height` LEDs, e.g.
+/// `{"width":16,"height":8,"order":"serpentine","origin":"bottom_left"}`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct LedLayout {
+    pub width: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub order: Order,
+    #[serde(default)]
+    pub origin: Origin,
+}
+
+impl LedLayout {
+    /// Row-major layout of `width` by `height` LEDs starting at the top left.
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, order: Order::default(), origin: Origin::default() }
+    }
+
+    #[must_use]
+    pub const fn with_order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_origin(mut self, origin: Origin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Reads a layout from the JSON file at `path`.
+    ///
+    /// # Errors
+    /// Any error from reading `path`, or `io::ErrorKind::InvalidData` if it does not hold a valid
+    /// layout.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let layout: Self = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        layout.validate()?;
+        return Ok(layout);
+    }
+
+    /// Checks that the layout can be used, as required by `LedLayout::load`.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidData` unless the panel has between 1 and 255 LEDs.
+    pub fn validate(&self) -> io::Result<()> {
+        if !(1..=u8::MAX.into()).contains(&self.width.saturating_mul(self.height)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected between 1 and 255 LEDs"));
+        }
+        return Ok(());
+    }
+
+    /// Number of LEDs on the panel.
+    #[must_use]
+    pub const fn leds(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Index along the chain of the LED at column `x` and row `y`, counted from the top left.
+    #[must_use]
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        let x = if matches!(self.origin, Origin::TopRight | Origin::BottomRight) { self.width - 1 - x } else { x };
+        let y = if matches!(self.origin, Origin::BottomLeft | Origin::BottomRight) { self.height - 1 - y } else { y };
+        let x = if self.order == Order::Serpentine && y % 2 == 1 { self.width - 1 - x } else { x };
+        y * self.width + x
+    }
+
+    /// Colours of the LEDs along the chain, from `image` once resampled to the size of the panel.
+    #[must_use]
+    pub fn map(&self, image: &Image) -> Vec<u32> {
+        let image = image.resample(self.width, self.height);
+        let mut leds = vec![0; self.leds()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                leds[self.index(x, y)] = image.rgb(x, y);
+            }
+        }
+        return leds;
+    }
+}
+
+/// Pixels of an image as RGBA, row by row from the top left.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+/// Range of the `i`-th of `to` pixels within `from`, never empty.
+const fn _span(i: usize, to: usize, from: usize) -> (usize, usize) {
+    let start = i * from / to;
+    let end = (i + 1) * from / to;
+    (start, if end > start { end } else { start + 1 })
+}
+
+impl Image {
+    /// Colour of the pixel at column `x` and row `y`, over black.
+    #[must_use]
+    pub fn rgb(&self, x: usize, y: usize) -> u32 {
+        let [red, green, blue, alpha] = self.pixels[y * self.width + x];
+        #[allow(clippy::cast_possible_truncation)]
+        let over_black = |c: u8| ((u16::from(c) * u16::from(alpha) + 127) / 255) as u8;
+        color::from_rgb(over_black(red), over_black(green), over_black(blue))
+    }
+
+    /// This image at `width` by `height`, each pixel the average of those it covers, over black.
+    #[must_use]
+    pub fn resample(&self, width: usize, height: usize) -> Self {
+        let mut pixels = Vec::with_capacity(width * height);
+        for ty in 0..height {
+            let (y0, y1) = _span(ty, height, self.height);
+            for tx in 0..width {
+                let (x0, x1) = _span(tx, width, self.width);
+                let mut sum = [0_u64; 3];
+                for y in y0..y1 {
+                    for &[r, g, b, a] in &self.pixels[y * self.width + x0..y * self.width + x1] {
+                        for (s, c) in sum.iter_mut().zip([r, g, b]) { *s += u64::from(c) * u64::from(a); }
+                    }
+                }
+                let n = ((y1 - y0) * (x1 - x0)) as u64 * 255;
+                #[allow(clippy::cast_possible_truncation)]
+                let avg = |s: u64| ((s + n / 2) / n) as u8;
+                pixels.push([avg(sum[0]), avg(sum[1]), avg(sum[2]), u8::MAX]);
+            }
+        }
+        return Self { width, height, pixels };
+    }
+}
+
+/// A frame of an image file, shown for `delay` before the next.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Frame {
+    pub image: Image,
+    pub delay: Duration,
+}
+
+fn _invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Limits on decoding, so that small files cannot take up memory without bound.
+fn _limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(4 * MAX_PIXELS as u64);
+    return limits;
+}
+
+fn _to_image(rgba: &RgbaImage) -> Image {
+    Image {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        pixels: rgba.pixels().map(|p| p.0).collect(),
+    }
+}
+
+/// Decodes the frames of a BMP, PNG or GIF image, told apart by its signature.
+///
+/// # Errors
+/// `io::ErrorKind::InvalidData` if `bytes` are not a supported image, or all its frames together
+/// exceed `MAX_PIXELS`.
+pub fn decode(bytes: &[u8]) -> io::Result<Vec<Frame>> {
+    let format = ::image::guess_format(bytes).map_err(_invalid)?;
+    if format != ImageFormat::Gif {
+        if !matches!(format, ImageFormat::Bmp | ImageFormat::Png) {
+            return Err(_invalid("Expected BMP, PNG or GIF image"));
+        }
+        let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+        reader.limits(_limits());
+        let image = _to_image(&reader.decode().map_err(_invalid)?.to_rgba8());
+        return Ok(vec![Frame { image, delay: Duration::ZERO }]);
+    }
+
+    let mut decoder = GifDecoder::new(Cursor::new(bytes)).map_err(_invalid)?;
+    decoder.set_limits(_limits()).map_err(_invalid)?;
+    let mut frames = Vec::new();
+    let mut pixels = 0_usize;
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(_invalid)?;
+        let image = _to_image(frame.buffer());
+        pixels += image.pixels.len();
+        if pixels > MAX_PIXELS {
+            return Err(_invalid("Expected at most MAX_PIXELS pixels in all frames"));
+        }
+        let delay = Duration::from(frame.delay());
+        frames.push(Frame { image, delay: if delay < MIN_GIF_DELAY { DEFAULT_GIF_DELAY } else { delay } });
+    }
+    match frames.as_mut_slice() {
+        [] => return Err(_invalid("Expected GIF with at least one image")),
+        // => A still GIF shows as long as any other still image
+        [frame] => frame.delay = Duration::ZERO,
+        _ => {},
+    }
+    return Ok(frames);
+}
+
+/// Reads and decodes the image at `path`, as per `decode`.
+///
+/// # Errors
+/// Any error from reading `path`, or `io::ErrorKind::InvalidData` if it is not a supported image.
+pub fn load(path: &Path) -> io::Result<Vec<Frame>> {
+    decode(&fs::read(path)?)
+}
+
+/// A request at `at_ms` from the start of a sequence, as printed by `Sequence::write_preview`.
+#[derive(Debug, Serialize)]
+pub struct Step {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub request: TypedRequest,
+}
+
+/// Frames of an image file mapped onto the LEDs of a panel.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Sequence {
+    pub layout: LedLayout,
+    /// Colours of the LEDs along the chain, and how long to show them for.
+    pub frames: Vec<(Vec<u32>, Duration)>,
+}
+
+impl Sequence {
+    /// Maps each of `frames` onto the LEDs of `layout`.
+    #[must_use]
+    pub fn new(frames: &[Frame], layout: LedLayout) -> Self {
+        Self {
+            layout,
+            frames: frames.iter().map(|f| (layout.map(&f.image), f.delay)).collect(),
+        }
+    }
+
+    /// `LED` request of each frame.
+    #[must_use]
+    pub fn requests(&self) -> Vec<Request> {
+        self.frames.iter().map(|(colors, _)| Request::from(&Command::Led { colors: colors.clone() })).collect()
+    }
+
+    /// Requests of the sequence played once, as timed by the frame delays.
+    #[must_use]
+    pub fn steps(&self) -> Vec<Step> {
+        let mut at = Duration::ZERO;
+        self.frames.iter().map(|(colors, delay)| {
+            let step = Step {
+                at_ms: u64::try_from(at.as_millis()).unwrap_or(u64::MAX),
+                request: TypedRequest::Write(Command::Led { colors: colors.clone() }),
+            };
+            at += *delay;
+            step
+        }).collect()
+    }
+
+    /// Writes the requests as JSON lines to `out`, one `Step` each.
+    ///
+    /// # Errors
+    /// Any error from writing to `out`.
+    pub fn write_preview(&self, out: &mut dyn Write) -> io::Result<()> {
+        for step in self.steps() {
+            serde_json::to_writer(&mut *out, &step)?;
+            writeln!(out)?;
+        }
+        return out.flush();
+    }
+
+    /// Draws each frame to `out` as the panel would show it, as coloured blocks on a terminal
+    /// with true colour, after a line giving its number and delay.
+    ///
+    /// # Errors
+    /// Any error from writing to `out`.
+    pub fn render(&self, out: &mut dyn Write) -> io::Result<()> {
+        let layout = &self.layout;
+        for (i, (colors, delay)) in self.frames.iter().enumerate() {
+            writeln!(out, "Frame {}/{}, {} ms", i + 1, self.frames.len(), delay.as_millis())?;
+            for y in 0..layout.height {
+                for x in 0..layout.width {
+                    let (r, g, b) = color::to_rgb(colors[layout.index(x, y)]);
+                    write!(out, "{}\u{2588}\u{2588}", SetForegroundColor(Color::Rgb { r, g, b }))?;
+                }
+                writeln!(out, "{ResetColor}")?;
+            }
+        }
+        return out.flush();
+    }
+
+    /// Shows the frames on the panel through `comm`, each awaiting its `ACK` and for its delay,
+    /// once or, if `looping` and there is more than one, until `interrupt` is set.
+    ///
+    /// # Errors
+    /// Any error from writing a frame, reading its `ACK` or sending heartbeats in between.
+    pub fn run(&self, comm: &mut Communicator, interrupt: &Interrupt, looping: bool) -> io::Result<()> {
+        let ack = ReadSpec { mode: ReadMode::Until(bindings::ACK), timeout: None };
+        let requests = self.requests();
+        loop {
+            for (req, (_, delay)) in requests.iter().zip(&self.frames) {
+                if interrupt.is_set() { return Ok(()); }
+                let due = Instant::now() + *delay;
+                comm.execute(req)?;
+                comm.read(&ack)?;
+                comm.idle_for(due.saturating_duration_since(Instant::now()))?;
+            }
+            if !looping || self.frames.len() < 2 { return Ok(()); }
+        }
+    }
+}
@@ -2,31 +2,228 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
 use std::fmt::Display;
+use std::time::Duration;
 
 use itertools::Itertools;
 
+use crate::animation::{Animation, Effect, Keyframe};
+use crate::trajectory::{Interpolation, Trajectory};
+
 pub mod util; 
+pub mod animation; 
+pub mod board; 
+pub mod calibration; 
+pub mod chunk; 
+pub mod color; 
+pub mod command; 
+pub mod communicator; 
+pub mod control; 
+pub mod datalog; 
+pub mod delta; 
+pub mod discovery; 
+pub mod format; 
+pub mod gcode; 
+pub mod image; 
+pub mod repl; 
+pub mod response; 
+pub mod safety; 
+pub mod script; 
+#[cfg(feature = "rhai")]
+pub mod scripting; 
+#[cfg(feature = "http")]
+pub mod server; 
+pub mod shutdown; 
+pub mod stream; 
+#[cfg(unix)]
+pub mod simulator; 
+pub mod term; 
+pub mod trajectory; 
 mod bindings;
+mod opcode;
 
 pub type Instruction = Vec<u8>; 
 
-#[derive(PartialEq, Eq, Clone)]
+/// How much a READ request should read before returning. 
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ReadMode {
+    /// Everything sent within the timeout. 
+    #[default]
+    Available, 
+    /// Exactly this many bytes. 
+    Exact(usize), 
+    /// Up to and including this byte. 
+    Until(u8), 
+    /// One `u8` length-prefixed frame. 
+    Frame, 
+}
+
+/// Parameters of a READ request. 
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ReadSpec {
+    pub mode: ReadMode, 
+    /// Deadline for the read to complete. Defaults to the port timeout if `None`. 
+    pub timeout: Option<Duration>, 
+}
+
+#[derive(PartialEq, Clone)]
 pub enum Request {
-    Read, 
-    Write(Instruction)
+    Read(ReadSpec), 
+    Write(Instruction), 
+    /// Query of the host-side `board::BoardState` mirror, without I/O. 
+    State, 
+    /// Movement of one magnet, expanded into timed `MAGNET` frames. 
+    Move(Trajectory), 
+    /// LED animation, played in the background as `LED` frames while the `Communicator` ticks. 
+    Animate(Animation), 
+    /// Stop of any LED animation, leaving the LEDs as of its last frame. 
+    Stop, 
 }
 
 impl Request {
-    fn _try_parse_opcode(opword: &str) -> Result<u8, ()> {
-        match opword {
-            "SENSOR" => Ok(bindings::SENSOR), 
-            "MAGNET" => Ok(bindings::MAGNET), 
-            "LED"    => Ok(bindings::LED), 
-            // "HANDSHAKE" => bindings::HANDSHAKE, 
-            "ACK"    => Ok(bindings::ACK), 
-            "QUIT"   => Ok(bindings::QUIT), 
-            _        => Err(()), 
+    fn _try_parse_byte(word: &str) -> Result<u8, ()> {
+        let res = match word.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16), 
+            None      => word.parse::<u8>(), 
+        }; 
+        res.map_err(|_| ())
+    }
+
+    /// Parses a magnet coordinate, rejecting `inf` and `NaN` as no firmware can act on them. 
+    fn _try_parse_coord(word: &str) -> Result<f32, ()> {
+        word.parse::<f32>().ok().filter(|v| v.is_finite()).ok_or(())
+    }
+
+    fn _try_parse_read_spec(words: &mut dyn Iterator<Item = &str>) -> Result<ReadSpec, ()> {
+        let mut spec = ReadSpec::default(); 
+        let mut words = words.peekable(); 
+        match words.peek().copied() {
+            Some("UNTIL") => {
+                words.next(); 
+                spec.mode = ReadMode::Until(Request::_try_parse_byte(words.next().ok_or(())?)?); 
+            }, 
+            Some("FRAME") => {
+                words.next(); 
+                spec.mode = ReadMode::Frame; 
+            }, 
+            Some("TIMEOUT") | None => (), 
+            Some(n) => {
+                words.next(); 
+                spec.mode = ReadMode::Exact(n.parse::<usize>().map_err(|_| ())?); 
+            }, 
+        }
+        match (words.next(), words.next(), words.next()) {
+            (Some("TIMEOUT"), Some(ms), None) => 
+                spec.timeout = Some(Duration::from_millis(ms.parse::<u64>().map_err(|_| ())?)), 
+            (None, _, _) => (), 
+            _ => return Err(()), 
+        }
+        return Ok(spec); 
+    }
+
+    fn _try_parse_trajectory(words: &mut dyn Iterator<Item = &str>) -> Result<Trajectory, ()> {
+        let mut coords: Vec<f32> = Vec::new(); 
+        loop {
+            match words.next().ok_or(())? {
+                "OVER" => break, 
+                w => coords.push(w.parse::<f32>().map_err(|_| ())?), 
+            }
+        }
+        if coords.len() % 2 != 0 { return Err(()); }
+        let waypoints = coords.chunks(2).map(|c| (c[0], c[1])).collect(); 
+        let ms = words.next().ok_or(())?.parse::<u64>().map_err(|_| ())?; 
+
+        let mut trajectory = Trajectory::new(waypoints, Duration::from_millis(ms)); 
+        while let Some(word) = words.next() {
+            let mut value = || words.next().ok_or(()); 
+            match word {
+                "LINEAR" => trajectory.interpolation = Interpolation::Linear, 
+                "SPLINE" => trajectory.interpolation = Interpolation::Spline, 
+                "SPEED"  => trajectory.max_speed = Some(value()?.parse::<f32>().map_err(|_| ())?), 
+                "ACCEL"  => trajectory.max_accel = Some(value()?.parse::<f32>().map_err(|_| ())?), 
+                "EVERY"  => trajectory.frame_ms = value()?.parse::<u16>().map_err(|_| ())?, 
+                _ => return Err(()), 
+            }
+        }
+        trajectory.validate().map_err(|_| ())?; 
+        return Ok(trajectory); 
+    }
+
+    fn _try_parse_animation(words: &mut dyn Iterator<Item = &str>) -> Result<Animation, ()> {
+        let words = color::join_words(words); 
+        let mut words = words.iter().map(String::as_str).peekable(); 
+        let colors_until_over = |words: &mut std::iter::Peekable<_>| -> Result<Vec<u32>, ()> {
+            let mut colors = Vec::new(); 
+            while let Some(w) = words.next_if(|w: &&str| *w != "OVER" && *w != "AT") {
+                colors.push(color::parse(w).map_err(|_| ())?); 
+            }
+            Ok(colors)
+        }; 
+        let effect = match words.next().ok_or(())? {
+            "KEYFRAMES" => {
+                let mut keyframes = Vec::new(); 
+                while words.next_if_eq(&"AT").is_some() {
+                    let at_ms = words.next().ok_or(())?.parse::<u64>().map_err(|_| ())?; 
+                    keyframes.push(Keyframe { at_ms, colors: colors_until_over(&mut words)? }); 
+                }
+                Effect::Keyframes { keyframes }
+            }, 
+            "FADE" => match colors_until_over(&mut words)?[..] {
+                [from, to] => Effect::Fade { from, to }, 
+                _ => return Err(()), 
+            }, 
+            "PULSE" => match colors_until_over(&mut words)?[..] {
+                [color] => Effect::Pulse { color }, 
+                _ => return Err(()), 
+            }, 
+            "RAINBOW" => Effect::Rainbow, 
+            "CHASE" => match colors_until_over(&mut words)?[..] {
+                [color] => Effect::Chase { color, background: 0, width: 1 }, 
+                [color, background] => Effect::Chase { color, background, width: 1 }, 
+                _ => return Err(()), 
+            }, 
+            _ => return Err(()), 
+        }; 
+        if words.next() != Some("OVER") { return Err(()); }
+        let ms = words.next().ok_or(())?.parse::<u64>().map_err(|_| ())?; 
+
+        let mut animation = Animation::new(effect, Duration::from_millis(ms)); 
+        while let Some(word) = words.next() {
+            let mut value = || words.next().ok_or(()); 
+            match word {
+                "LOOP" => animation.looping = true, 
+                "LEDS" => animation.leds = Some(value()?.parse::<usize>().map_err(|_| ())?), 
+                "FPS"  => animation.max_fps = Some(value()?.parse::<f32>().map_err(|_| ())?), 
+                "WIDTH" => match &mut animation.effect {
+                    Effect::Chase { width, .. } => *width = value()?.parse::<usize>().map_err(|_| ())?, 
+                    _ => return Err(()), 
+                }, 
+                _ => return Err(()), 
+            }
         }
+        animation.validate().map_err(|_| ())?; 
+        return Ok(animation); 
+    }
+
+    /// Arduino op names accepted after `WRITE`, with their opcodes. 
+    pub const OPCODES: [(&'static str, u8); 9] = [
+        ("SENSOR", bindings::SENSOR), 
+        ("MAGNET", bindings::MAGNET), 
+        ("LED",    bindings::LED), 
+        ("MAGNET_DELTA", opcode::MAGNET_DELTA), 
+        ("LED_DELTA",    opcode::LED_DELTA), 
+        // ("HANDSHAKE", bindings::HANDSHAKE), 
+        ("HEARTBEAT", opcode::HEARTBEAT), 
+        ("STREAM", opcode::STREAM), 
+        ("ACK",    bindings::ACK), 
+        ("QUIT",   bindings::QUIT), 
+    ]; 
+
+    fn _try_parse_opcode(opword: &str) -> Result<u8, ()> {
+        Request::OPCODES
+            .iter()
+            .find(|(name, _)| *name == opword)
+            .map(|(_, opcode)| *opcode)
+            .ok_or(())
     }
 
     fn _try_parse_arguments_into(
@@ -42,8 +239,8 @@ impl Request {
                 for elem in &elems {
                     if let Some((x, y, is_on)) = elem.collect_tuple() {
                         let res = (
-                            x.parse::<f32>(), 
-                            y.parse::<f32>(), 
+                            Request::_try_parse_coord(x), 
+                            Request::_try_parse_coord(y), 
                             is_on.parse::<bool>()
                         ); 
                         if res.0.is_err() || res.1.is_err() || res.2.is_err() { return Err(()); }
@@ -71,18 +268,37 @@ impl Request {
                 return Ok(idx - 1); 
             }, 
             bindings::LED => {
-                for word in words {
-                    if let Ok(rgb_int) =  word.parse::<u32>() {
-                        let tmp = rgb_int.to_be_bytes(); 
-                        for b in &tmp[1..] {
-                            // Correctness? 
-                            instr_buf.push(*b); 
-                            idx += 1; 
-                        }
-                    }
-                    // Else malformed, continue.
+                // => Colours in any form of `color::parse`, out-of-range ones rejected
+                for word in color::join_words(words) {
+                    let rgb_int = color::parse(&word).map_err(|_| ())?; 
+                    instr_buf.extend_from_slice(&rgb_int.to_be_bytes()[1..]); 
                 }
-                return Ok(idx - 1); 
+                return Ok(instr_buf.len() - 1); 
+            }, 
+            opcode::MAGNET_DELTA => {
+                // => As MAGNET, each cell prefixed by its index
+                for elem in &words.chunks(4) {
+                    let (i, x, y, is_on) = elem.collect_tuple().ok_or(())?; 
+                    instr_buf.push(i.parse::<u8>().map_err(|_| ())?); 
+                    instr_buf.extend_from_slice(&Request::_try_parse_coord(x)?.to_le_bytes()); 
+                    instr_buf.extend_from_slice(&Request::_try_parse_coord(y)?.to_le_bytes()); 
+                    instr_buf.push(is_on.parse::<bool>().map_err(|_| ())?.into()); 
+                }
+                return Ok(instr_buf.len() - 1); 
+            }, 
+            opcode::LED_DELTA => {
+                // => As LED, each colour prefixed by its index
+                for elem in &color::join_words(words).into_iter().chunks(2) {
+                    let (i, rgb) = elem.collect_tuple().ok_or(())?; 
+                    instr_buf.push(i.parse::<u8>().map_err(|_| ())?); 
+                    instr_buf.extend_from_slice(&color::parse(&rgb).map_err(|_| ())?.to_be_bytes()[1..]); 
+                }
+                return Ok(instr_buf.len() - 1); 
+            }, 
+            opcode::STREAM => {
+                let interval_ms = words.next().ok_or(())?.parse::<u16>().map_err(|_| ())?; 
+                instr_buf.extend_from_slice(&interval_ms.to_le_bytes()); 
+                return Ok(2); 
             }, 
             _ => 
                 return Ok(0), 
@@ -97,13 +313,43 @@ pub enum RequestConversionError {
     MalformedOpSequence(String), 
 }
 
+impl Display for RequestConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestConversionError::UndefinedOpSequence(s) | 
+            RequestConversionError::EmptyOpSequence(s) | 
+            RequestConversionError::MalformedOpSequence(s) => 
+                write!(f, "{s}"), 
+        }
+    }
+}
+
 impl Display for Request {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Request::Read => 
-                write!(f, "READ"), 
+            Request::Read(spec) => {
+                write!(f, "READ")?; 
+                match spec.mode {
+                    ReadMode::Available => (), 
+                    ReadMode::Exact(n)  => write!(f, " {n}")?, 
+                    ReadMode::Until(b)  => write!(f, " UNTIL {b:#04x}")?, 
+                    ReadMode::Frame     => write!(f, " FRAME")?, 
+                }
+                match spec.timeout {
+                    Some(t) => write!(f, " TIMEOUT {}", t.as_millis()), 
+                    None    => Ok(()), 
+                }
+            }, 
             Request::Write(s) => 
                 write!(f, "WRITE {:?}", s), 
+            Request::State => 
+                write!(f, "STATE"), 
+            Request::Move(t) => 
+                write!(f, "{t}"), 
+            Request::Animate(a) => 
+                write!(f, "{a}"), 
+            Request::Stop => 
+                write!(f, "STOP"), 
         }
     }
 }
@@ -117,11 +363,47 @@ impl TryFrom<&str> for Request {
         /* 1. Parse serial-communicator op */
         let mut split = action.split_ascii_whitespace(); 
         match split.next() {
-            Some("READ")  => return Ok(Request::Read), 
+            Some("READ")  => {
+                return Request::_try_parse_read_spec(&mut split)
+                    .map(Request::Read)
+                    .map_err(|_| RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Malformed READ arguments: {}", action.trim())
+                    )); 
+            }, 
             Some("WRITE") => (), 
+            Some("STATE") => {
+                if let Some(s) = split.next() {
+                    return Err(RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Unexpected argument to STATE: {s}")
+                    )); 
+                }
+                return Ok(Request::State); 
+            }, 
+            Some("MOVE") => {
+                return Request::_try_parse_trajectory(&mut split)
+                    .map(Request::Move)
+                    .map_err(|_| RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Malformed MOVE arguments: {}", action.trim())
+                    )); 
+            }, 
+            Some("ANIMATE") => {
+                return Request::_try_parse_animation(&mut split)
+                    .map(Request::Animate)
+                    .map_err(|_| RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Malformed ANIMATE arguments: {}", action.trim())
+                    )); 
+            }, 
+            Some("STOP") => {
+                if let Some(s) = split.next() {
+                    return Err(RequestConversionError::MalformedOpSequence(
+                        format!("{_FN_NAME} Unexpected argument to STOP: {s}")
+                    )); 
+                }
+                return Ok(Request::Stop); 
+            }, 
             Some(s) => 
                 return Err(RequestConversionError::UndefinedOpSequence(
-                    format!("{_FN_NAME} Expected \"READ\" or \"WRITE\", got {s}")
+                    format!("{_FN_NAME} Expected \"READ\", \"WRITE\", \"STATE\", \"MOVE\", \"ANIMATE\" or \"STOP\", got {s}")
                 )), 
             None => 
                 return Err(RequestConversionError::EmptyOpSequence(
@@ -1,173 +1,963 @@
 #![allow(dead_code)]
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
+use std::fs;
 use std::io;
-use std::io::Write; 
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
 use std::time::Duration;
-use std::thread::sleep;
+#[cfg(feature = "rhai")]
+use std::{cell::RefCell, rc::Rc};
 
-use serialport::{SerialPortType, SerialPort};
-use serial_communicator::Request; 
+use clap::{Parser, Subcommand};
+use serial_communicator::{Instruction, ReadMode, ReadSpec, Request}; 
+use serial_communicator::calibration::Calibration; 
+use serial_communicator::color::ColorCorrection; 
+use serial_communicator::communicator::{
+    Communicator, Connector, HeartbeatPolicy, Outcome, PendingPolicy, ReconnectPolicy
+}; 
+use serial_communicator::control::ControlLoop; 
+use serial_communicator::delta::{self, DeltaEncoder}; 
+use serial_communicator::datalog::{LogFormat, Rotation, SensorLog}; 
+use serial_communicator::discovery::{self, DtrMode, OpenOptions}; 
+use serial_communicator::format::{Format, Status}; 
+use serial_communicator::gcode::{self, GcodeOptions, Program}; 
+use serial_communicator::image::{self, LedLayout, Sequence}; 
+use serial_communicator::repl::{self, Repl}; 
+use serial_communicator::safety::{SafetyGuard, SafetyLimits}; 
+use serial_communicator::script::{Runner, Script}; 
+use serial_communicator::shutdown::{exit_code, Interrupt, SafeState}; 
+use serial_communicator::stream::{self, DropPolicy, SensorStream, Source}; 
+use serial_communicator::term; 
+#[cfg(feature = "rhai")]
+use serial_communicator::scripting; 
+#[cfg(unix)]
+use serial_communicator::simulator::{self, Firmware}; 
 use log::{error, info};
 
 mod util;
 mod bindings;
 
-use util::serial_helper::*; 
+/// How often idle loops check for signals and send heartbeats. 
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100); 
+/// Shortest wait of idle loops for input while an animation frame is overdue. 
+const ANIMATION_MIN_POLL: Duration = Duration::from_millis(1); 
 
-const BAUD_RATE_OPTIONS: [u32; 2] = [115_200, 9_600]; 
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Wire format of requests on stdin and responses on stdout
+    #[arg(long, value_enum, default_value_t)]
+    format: Format, 
 
-/// Tries to connect to relevant Arduino tty devices (i.e., all Arduinos connected to host). 
+    /// Run against a simulated Arduino instead of connected devices
+    #[cfg(unix)]
+    #[arg(long)]
+    simulate: bool, 
+
+    /// What to do with a request in flight when the connection to the Arduino is lost
+    #[arg(long, value_enum, default_value_t)]
+    on_disconnect: PendingPolicy, 
+
+    /// Seconds to keep trying to reconnect before failing a request
+    #[arg(long, default_value_t = 30)]
+    give_up_after: u64, 
+
+    /// How to drive DTR on open, which resets most Arduinos
+    #[arg(long, value_enum, default_value_t)]
+    dtr: DtrMode, 
+
+    /// Milliseconds to wait for an Arduino to reply to HANDSHAKE or print its boot banner
+    #[arg(long, default_value_t = 5000)]
+    ready_timeout: u64, 
+
+    /// Boot banner printed by the firmware, which also signals readiness
+    #[arg(long)]
+    boot_banner: Option<String>, 
+
+    /// Skip an Arduino that is not ready within --ready-timeout, instead of proceeding with a 
+    /// warning 
+    #[arg(long)]
+    strict_ready: bool, 
+
+    /// Send HEARTBEAT every <MS> milliseconds while idle, so that the firmware watchdog puts the 
+    /// Arduino into a safe state if this process stalls. Not sent in terminal mode. 
+    #[arg(long, value_name = "MS")]
+    heartbeat: Option<u64>, 
+
+    /// Milliseconds without heartbeat reply after which the link is reported stale
+    #[arg(long, default_value_t = 1000)]
+    stale_after: u64, 
+
+    /// WRITE request putting the Arduino into a safe state on exit, e.g. "WRITE QUIT". 
+    /// Repeat for a sequence. Defaults to all magnets off, all LEDs off, then QUIT. 
+    #[arg(long, value_name = "REQUEST")]
+    safe_state: Vec<String>, 
+
+    /// Milliseconds to wait for the Arduino to acknowledge each safe-state request
+    #[arg(long, default_value_t = 1000)]
+    ack_timeout: u64, 
+
+    /// Run a fixed-rate control loop at <HZ> instead of the WRITE-READ loop: each tick writes the 
+    /// latest commands from stdin, then SENSOR, and publishes the reading 
+    #[arg(long, value_name = "HZ")]
+    rate: Option<f64>, 
+
+    /// Length of SENSOR replies in bytes, so that the control loop and polled streams need not 
+    /// wait half a tick for them
+    #[arg(long, value_name = "N")]
+    sensor_bytes: Option<usize>, 
+
+    /// Log decoded SENSOR readings, with the MAGNET and LED commands in effect, to <PATH>. 
+    /// Applies to the WRITE-READ loop, the control loop and stream mode. 
+    #[arg(long, value_name = "PATH")]
+    log: Option<PathBuf>, 
+
+    /// File format of the log. Parquet requires the `parquet` feature
+    #[arg(long, value_enum, default_value_t, requires = "log")]
+    log_format: LogFormat, 
+
+    /// Move on to a new log file after <N> readings
+    #[arg(long, value_name = "N", requires = "log")]
+    rotate_rows: Option<u64>, 
+
+    /// Move on to a new log file after <SECS> seconds
+    #[arg(long, value_name = "SECS", requires = "log")]
+    rotate_secs: Option<u64>, 
+
+    /// Size of the Arduino receive buffer in bytes. Longer MAGNET and LED commands are split into 
+    /// several frames, each awaiting its ACK. The firmware must append the cells or colours of 
+    /// frames whose opcode has the CONTINUED flag (0x40) set to those of the next frame 
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u16).range(2..))]
+    max_frame: Option<u16>, 
+
+    /// Check MAGNET commands against the coordinate ranges, maximum of magnets on and duty cycle 
+    /// budget in the JSON file at <PATH>, rejecting or clamping violations 
+    #[arg(long, value_name = "PATH")]
+    safety: Option<PathBuf>, 
+
+    /// Take MAGNET coordinates in the units, frame and grid of the JSON calibration file at <PATH>, 
+    /// encoding them in firmware coordinates. Safety limits apply in these units 
+    #[arg(long, value_name = "PATH")]
+    calibration: Option<PathBuf>, 
+
+    /// Scale LED colours by <LEVEL> within [0, 1] before sending them 
+    #[arg(long, value_name = "LEVEL", default_value_t = 1.0)]
+    brightness: f32, 
+
+    /// Raise LED colours to the power of <GAMMA> before sending them, e.g. 2.2 for perceptually 
+    /// even steps 
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32, 
+
+    /// Seconds between control loop statistics reports
+    #[arg(long, default_value_t = 5, requires = "rate")]
+    stats_interval: u64, 
+
+    /// Only send the MAGNET cells and LEDs that changed since the last acknowledged state in the 
+    /// control loop 
+    #[arg(long, requires = "rate")]
+    delta: bool, 
+
+    /// Seconds between full MAGNET and LED refreshes when delta-encoding 
+    #[arg(long, value_name = "SECS", default_value_t = 10, requires = "delta")]
+    full_refresh_secs: u64, 
+
+    #[command(subcommand)]
+    mode: Option<Mode>, 
+}
+
+#[derive(Subcommand)]
+enum Mode {
+    /// Interactive REPL with history, completion and decoded responses
+    Interactive {
+        /// History file. Defaults to `$HOME/.serial_communicator_history`.
+        #[arg(long)]
+        history: Option<PathBuf>, 
+    }, 
+    /// Raw terminal passthrough, like minicom. Press Ctrl-A ? for commands.
+    Term {
+        /// Start in hex-dump display instead of ASCII
+        #[arg(long)]
+        hex: bool, 
+    }, 
+    /// Stream timestamped SENSOR readings to stdout, best read with `--format jsonl`
+    Stream {
+        /// Milliseconds between readings
+        #[arg(long, default_value_t = 100)]
+        interval: u64, 
+        /// Poll SENSOR, or have the firmware push readings with STREAM
+        #[arg(long, value_enum, default_value_t)]
+        source: Source, 
+        /// Readings buffered while stdout falls behind
+        #[arg(long, default_value_t = stream::BUFFER_CAPACITY)]
+        buffer: usize, 
+        /// Which reading to drop once the buffer is full, or block polling until there is room
+        #[arg(long, value_enum, default_value_t)]
+        drop: DropPolicy, 
+        /// Stop after <N> readings instead of running until interrupted
+        #[arg(long, value_name = "N")]
+        count: Option<u64>, 
+    }, 
+    /// Run a test script of requests, sleeps, loops and expectations
+    Run {
+        /// Path to script file
+        script: PathBuf, 
+    }, 
+    /// Run a G-code program of magnet moves, dwells and LED colours
+    Gcode {
+        /// Path to G-code file
+        file: PathBuf, 
+        /// Print the generated requests as JSON lines instead of connecting
+        #[arg(long)]
+        dry_run: bool, 
+        /// Number of LEDs set by M150 without I
+        #[arg(long, default_value_t = 1)]
+        leds: usize, 
+        /// Feed rate of G0, in mm/min
+        #[arg(long, default_value_t = gcode::RAPID_FEED)]
+        rapid_feed: f32, 
+        /// Feed rate of G1 until set by F, in mm/min
+        #[arg(long, default_value_t = gcode::FEED)]
+        feed: f32, 
+    }, 
+    /// Show a PNG or BMP image, or play an animated GIF, on an LED panel
+    Image {
+        /// Path to PNG, BMP or GIF file
+        file: PathBuf, 
+        /// LED layout of the panel, as JSON, e.g. `{"width":16,"height":16,"order":"serpentine"}`
+        #[arg(long)]
+        layout: PathBuf, 
+        /// Draw the frames in the terminal instead of connecting
+        #[arg(long)]
+        preview: bool, 
+        /// Print the generated requests as JSON lines instead of connecting
+        #[arg(long, conflicts_with = "preview")]
+        dry_run: bool, 
+        /// Play animated GIFs until interrupted instead of once
+        #[arg(long = "loop")]
+        looping: bool, 
+    }, 
+    /// Run a Rhai script against the communicator API (requires the `rhai` feature)
+    #[cfg(feature = "rhai")]
+    Exec {
+        /// Path to Rhai script file
+        script: PathBuf, 
+    }, 
+    /// Serve the HTTP/WebSocket API for browser dashboards (requires the `http` feature)
+    #[cfg(feature = "http")]
+    Serve {
+        /// Address to listen on. Defaults to loopback only.
+        #[arg(default_value = "127.0.0.1:8080")]
+        addr: String, 
+    }, 
+}
+
+/// Connects to the first Arduino found, or to a simulated one if `simulate`. 
+/// Arduinos are opened as per `opts`. 
+/// The returned `Communicator` reconnects to the same Arduino as per `policy` if connection is lost. 
+fn _connect(
+    simulate: bool, 
+    opts: &OpenOptions, 
+    policy: ReconnectPolicy, 
+    max_frame_len: Option<usize>
+) -> io::Result<Communicator> {
+    if simulate {
+        #[cfg(unix)]
+        {
+            let firmware = Firmware { max_frame_len, ..Firmware::default() }; 
+            let (port, _) = simulator::spawn_firmware(Duration::from_secs(1), firmware.clone())?; 
+            let connector: Connector = Box::new(move || {
+                let (port, _) = simulator::spawn_firmware(Duration::from_secs(1), firmware.clone())?; 
+                return Ok(port); 
+            }); 
+            let comm = Communicator::with_device_name(port, simulator::DEVICE_NAME)
+                .with_reconnect(connector, policy); 
+            return Ok(_with_max_frame_len(comm, max_frame_len)); 
+        }
+    }
+
+    // [TODO] Currently this would be the sole Arduino connected. No idea how many is actually used! 
+    let found = discovery::find_arduino_serialports(None, opts)?.swap_remove(0); 
+    // Match by serial number on reconnect, as the `tty` device may be renamed after hot-plug
+    let serial_number = found.serial_number; 
+    let opts = opts.clone(); 
+    let connector: Connector = Box::new(move || {
+        let mut found = discovery::find_arduino_serialports(serial_number.as_deref(), &opts)?; 
+        return Ok(found.swap_remove(0).port); 
+    }); 
+    let comm = Communicator::new(found.port).with_reconnect(connector, policy); 
+    return Ok(_with_max_frame_len(comm, max_frame_len)); 
+}
+
+fn _with_max_frame_len(comm: Communicator, max_frame_len: Option<usize>) -> Communicator {
+    match max_frame_len {
+        Some(len) => comm.with_max_frame_len(len), 
+        None => comm, 
+    }
+}
+
+fn _with_safety(comm: Communicator, limits: Option<&SafetyLimits>) -> Communicator {
+    match limits {
+        Some(limits) => comm.with_safety(SafetyGuard::new(limits.clone())), 
+        None => comm, 
+    }
+}
+
+fn _with_calibration(comm: Communicator, calibration: Option<&Calibration>) -> Communicator {
+    match calibration {
+        Some(calibration) => comm.with_calibration(calibration.clone()), 
+        None => comm, 
+    }
+}
+
+fn _with_color_correction(comm: Communicator, correction: Option<&ColorCorrection>) -> Communicator {
+    match correction {
+        Some(correction) => comm.with_color_correction(correction.clone()), 
+        None => comm, 
+    }
+}
+
+/// Reads and parses the script at `path`. 
 ///
 /// ### Returns
-/// - `Ok(ports)` which encapsulates `Vec` of `dyn SerialPort` trait objects.
-/// - `Err(io::Error)` which is of kind `io::ErrorKind::NotFound`, indicating that no suitable `tty`
-///    devices could be found.
-fn _find_arduino_serialports() -> io::Result<Vec<Box<dyn SerialPort>>> {
-    const _FN_NAME: &str = "[serial-communicator::find_arduino_serialport]";
-
-    let mut port_buf: Vec<Box<dyn SerialPort>> = Vec::with_capacity(2); 
-    let available_ports = serialport::available_ports()?;
-    for info in &available_ports {
-        if let SerialPortType::UsbPort(t) = &info.port_type {
-            // Do not check for metadata, which enables 3rd party boards to be used
-            info!("{:#?}", t); 
-            if t.vid != 0x3343 || t.pid != 0x0042 { continue; } // Not an Arduino
-
-            for baud_rate in BAUD_RATE_OPTIONS {
-                let port = serialport::new(&info.port_name, baud_rate)
-                    .timeout(Duration::from_secs(1))
-                    .flow_control(serialport::FlowControl::None)
-                    .open();
-                if port.is_err() { continue; } // Cannot open port
-                let port = port.unwrap();
-
-                // Give time for Arduino to reset connection
-                sleep(Duration::from_secs(3)); 
-
-                port_buf.push(port); 
-            }
-        }
+/// - `Ok(script)` if `path` contains a well-formed script. 
+/// - `Err(exit_code::USAGE)` otherwise. 
+fn _load_script(path: &Path) -> Result<Script, i32> {
+    const _FN_NAME: &str = "[serial-communicator::load_script]";
+
+    let src = fs::read_to_string(path).map_err(|e| {
+        error!("{_FN_NAME} Cannot read script {}: \n{:#?}", path.display(), e); 
+        exit_code::USAGE
+    })?; 
+    Script::parse(&src).map_err(|e| {
+        error!("{_FN_NAME} {}:{}: {}", path.display(), e.line, e.message); 
+        exit_code::USAGE
+    })
+}
+
+/// Reads and parses the G-code program at `path`. 
+///
+/// ### Returns
+/// - `Ok(program)` if `path` contains a supported program. 
+/// - `Err(exit_code::USAGE)` otherwise. 
+fn _load_gcode(path: &Path, opts: GcodeOptions) -> Result<Program, i32> {
+    const _FN_NAME: &str = "[serial-communicator::load_gcode]";
+
+    if !(opts.rapid_feed.is_finite() && opts.rapid_feed > 0.0 && opts.feed.is_finite() && opts.feed > 0.0) {
+        error!("{_FN_NAME} Expected positive feed rates"); 
+        return Err(exit_code::USAGE); 
     }
+    let src = fs::read_to_string(path).map_err(|e| {
+        error!("{_FN_NAME} Cannot read G-code {}: \n{:#?}", path.display(), e); 
+        exit_code::USAGE
+    })?; 
+    Program::parse(&src, opts).map_err(|e| {
+        error!("{_FN_NAME} {}:{}: {}", path.display(), e.line, e.message); 
+        exit_code::USAGE
+    })
+}
+
+/// Reads the image at `path` and maps its frames onto the LED layout at `layout`. 
+///
+/// ### Returns
+/// - `Ok(sequence)` if `path` contains a supported image and `layout` a valid layout. 
+/// - `Err(exit_code::USAGE)` otherwise. 
+fn _load_image(path: &Path, layout: &Path) -> Result<Sequence, i32> {
+    const _FN_NAME: &str = "[serial-communicator::load_image]";
 
-    if port_buf.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("{_FN_NAME} No Arduino `tty` device connected to host!")
-        ));
-    } else {
-        return Ok(port_buf); 
+    let layout = LedLayout::load(layout).map_err(|e| {
+        error!("{_FN_NAME} Invalid LED layout {}: {e}", layout.display()); 
+        exit_code::USAGE
+    })?; 
+    let frames = image::load(path).map_err(|e| {
+        error!("{_FN_NAME} Cannot load image {}: {e}", path.display()); 
+        exit_code::USAGE
+    })?; 
+    return Ok(Sequence::new(&frames, layout)); 
+}
+
+/// Parses the safe-state sequence given on the command line, if any. 
+/// 
+/// ### Returns
+/// - `Ok(safe_state)` if every request in `requests` is a well-formed WRITE. 
+/// - `Err(exit_code::USAGE)` otherwise. 
+fn _parse_safe_state(requests: &[String], ack_timeout_ms: u64) -> Result<SafeState, i32> {
+    const _FN_NAME: &str = "[serial-communicator::parse_safe_state]";
+
+    let mut safe_state = SafeState {
+        ack_timeout: Duration::from_millis(ack_timeout_ms), 
+        ..SafeState::default()
+    }; 
+    if requests.is_empty() { return Ok(safe_state); }
+
+    safe_state.sequence.clear(); 
+    for r in requests {
+        match Request::try_from(r.as_str()) {
+            Ok(Request::Write(v)) => safe_state.sequence.push(v), 
+            Ok(Request::Read(_) | Request::State | Request::Move(_) | Request::Animate(_) | Request::Stop) => {
+                error!("{_FN_NAME} Safe-state request must be WRITE, got {r}"); 
+                return Err(exit_code::USAGE); 
+            }, 
+            Err(e) => {
+                error!("{_FN_NAME} Invalid safe-state request {r}: {e}"); 
+                return Err(exit_code::USAGE); 
+            }, 
+        }
     }
+    return Ok(safe_state); 
 }
 
-/// Communicator which works in a WRITE-READ loop. 
-/// Assumming Cosmos' ctrl loop it should be sufficient? 
+#[allow(clippy::too_many_lines)]
 fn main() {
     const _FN_NAME: &str = "[serial-communicator::main]";
     simple_logger::init_with_env().unwrap(); 
+    let cli = Cli::parse(); 
 
-    /* 1. Find Arduino devices */
-    let mut arduino_ports = match _find_arduino_serialports() {
-        Ok(p) => p,
+    /* 1. Parse script ahead of connecting, if any */
+    let script = match &cli.mode {
+        Some(Mode::Run { script }) => match _load_script(script) {
+            Ok(s) => Some(s), 
+            Err(code) => process::exit(code), 
+        }, 
+        _ => None, 
+    }; 
+    let program = match &cli.mode {
+        Some(Mode::Gcode { file, dry_run, leds, rapid_feed, feed }) => {
+            let opts = GcodeOptions { rapid_feed: *rapid_feed, feed: *feed, leds: *leds }; 
+            let program = _load_gcode(file, opts).unwrap_or_else(|code| process::exit(code)); 
+            if *dry_run {
+                // => Preview only, without connecting
+                let code = match program.write_preview(&mut io::stdout()) {
+                    Ok(()) => exit_code::OK, 
+                    Err(e) => {
+                        error!("{_FN_NAME} Cannot write preview: \n{:#?}", e); 
+                        exit_code::FAILURE
+                    }, 
+                }; 
+                process::exit(code); 
+            }
+            Some(program)
+        }, 
+        _ => None, 
+    }; 
+    let sequence = match &cli.mode {
+        Some(Mode::Image { file, layout, preview, dry_run, .. }) => {
+            let sequence = _load_image(file, layout).unwrap_or_else(|code| process::exit(code)); 
+            if *preview || *dry_run {
+                // => Preview only, without connecting
+                let res = if *preview {
+                    sequence.render(&mut io::stdout())
+                } else {
+                    sequence.write_preview(&mut io::stdout())
+                }; 
+                let code = match res {
+                    Ok(()) => exit_code::OK, 
+                    Err(e) => {
+                        error!("{_FN_NAME} Cannot write preview: \n{:#?}", e); 
+                        exit_code::FAILURE
+                    }, 
+                }; 
+                process::exit(code); 
+            }
+            Some(sequence)
+        }, 
+        _ => None, 
+    }; 
+    let safe_state = match _parse_safe_state(&cli.safe_state, cli.ack_timeout) {
+        Ok(s) => s, 
+        Err(code) => process::exit(code), 
+    }; 
+    let safety = cli.safety.as_deref().map(|path| SafetyLimits::load(path).unwrap_or_else(|e| {
+        error!("{_FN_NAME} Invalid safety limits {}: {e}", path.display()); 
+        process::exit(exit_code::USAGE); 
+    })); 
+    let calibration = cli.calibration.as_deref().map(|path| Calibration::load(path).unwrap_or_else(|e| {
+        error!("{_FN_NAME} Invalid calibration {}: {e}", path.display()); 
+        process::exit(exit_code::USAGE); 
+    })); 
+    let correction = match ColorCorrection::new(cli.brightness, cli.gamma) {
+        Ok(c) if c.is_identity() => None, 
+        Ok(c) => Some(c), 
+        Err(e) => {
+            error!("{_FN_NAME} Invalid colour correction: {e}"); 
+            process::exit(exit_code::USAGE); 
+        }, 
+    }; 
+
+    let period = match cli.rate {
+        None => None, 
+        Some(_) if cli.mode.is_some() => {
+            error!("{_FN_NAME} --rate only applies without a subcommand"); 
+            process::exit(exit_code::USAGE); 
+        }, 
+        Some(hz) if hz.is_finite() && hz > 0.0 => Some(Duration::from_secs_f64(1.0 / hz)), 
+        Some(hz) => {
+            error!("{_FN_NAME} Invalid rate {hz} Hz"); 
+            process::exit(exit_code::USAGE); 
+        }, 
+    }; 
+
+    if cli.heartbeat.is_some() && matches!(cli.mode, Some(Mode::Stream { source: Source::Push, .. })) {
+        // Heartbeat replies would interleave with pushed readings
+        error!("{_FN_NAME} --heartbeat does not apply to pushed streams"); 
+        process::exit(exit_code::USAGE); 
+    }
+
+    if cli.log.is_some() && matches!(cli.mode, Some(Mode::Interactive { .. } | Mode::Term { .. } | Mode::Run { .. } | Mode::Gcode { .. } | Mode::Image { .. })) {
+        error!("{_FN_NAME} --log only applies to the WRITE-READ loop, the control loop and stream mode"); 
+        process::exit(exit_code::USAGE); 
+    }
+    let mut log = cli.log.as_deref().map(|path| {
+        SensorLog::new(path, cli.log_format).with_rotation(Rotation {
+            max_rows: cli.rotate_rows, 
+            max_age: cli.rotate_secs.map(Duration::from_secs), 
+        })
+    }); 
+
+    /* 2. Find Arduino devices */
+    #[cfg(unix)]
+    let simulate = cli.simulate; 
+    #[cfg(not(unix))]
+    let simulate = false; 
+    let policy = ReconnectPolicy {
+        pending: cli.on_disconnect, 
+        give_up_after: Duration::from_secs(cli.give_up_after), 
+        ..ReconnectPolicy::default()
+    }; 
+    let opts = OpenOptions {
+        dtr: cli.dtr, 
+        ready_timeout: Duration::from_millis(cli.ready_timeout), 
+        banner: cli.boot_banner.map(String::into_bytes), 
+        strict_ready: cli.strict_ready, 
+    }; 
+    let max_frame_len = cli.max_frame.map(usize::from); 
+    let comm = match _connect(simulate, &opts, policy, max_frame_len) {
+        Ok(c) => c,
         Err(e) => {
             // => Cannot find arduino ttyusb @ given baud rate, return
             error!("{}", e);
-            return;
+            process::exit(exit_code::FAILURE);
         }
     };
-    // [TODO] Currently this would be the sole Arduino connected. No idea how many is actually used! 
-    let arduino_port: &mut dyn SerialPort = arduino_ports[0].as_mut(); 
+    info!("{_FN_NAME} Connected to {}", comm.device()); 
+    let comm = _with_calibration(comm, calibration.as_ref()); 
+    let comm = _with_color_correction(comm, correction.as_ref()); 
+    let mut comm = _with_safety(comm, safety.as_ref()).with_safe_state(safe_state.clone()); 
+    let heartbeat = cli.heartbeat.map(|ms| HeartbeatPolicy {
+        interval: Duration::from_millis(ms), 
+        stale_after: Duration::from_millis(cli.stale_after), 
+        ..HeartbeatPolicy::default()
+    }); 
+    if let Some(policy) = heartbeat {
+        comm = comm.with_heartbeat(policy); 
+    }
 
-    info!("{_FN_NAME} Connected to Arduino"); 
-    let mut action_buffer: String  = String::with_capacity(512);
-    let mut read_buffer:   Vec<u8> = vec![0; 512]; 
+    /* 3. Dispatch to mode */
+    // Interactive modes take Ctrl-C as input, and exit on their own commands or SIGTERM
+    let interactive = matches!(cli.mode, Some(Mode::Interactive { .. } | Mode::Term { .. })); 
+    let installed = if interactive { Interrupt::install_terminate() } else { Interrupt::install() }; 
+    let interrupt = installed.unwrap_or_else(|e| {
+        error!("{_FN_NAME} Cannot install signal handlers: \n{:#?}", e); 
+        Interrupt::default()
+    }); 
+    let code = match cli.mode {
+        None => match period {
+            Some(period) => _run_control_loop(
+                &mut comm, 
+                cli.format, 
+                period, 
+                cli.sensor_bytes, 
+                Duration::from_secs(cli.stats_interval), 
+                cli.delta.then(|| DeltaEncoder::new()
+                    .with_full_refresh(Duration::from_secs(cli.full_refresh_secs))
+                    .with_max_frame_len(max_frame_len.unwrap_or(delta::MAX_FRAME_LEN))), 
+                log.as_mut(), 
+                &interrupt
+            ), 
+            None => _run_stdin_loop(&mut comm, cli.format, log.as_mut(), &interrupt), 
+        }, 
+        Some(Mode::Interactive { history }) => {
+            let mut reconnect = || {
+                _connect(simulate, &opts, policy, max_frame_len)
+                    .map(|c| _with_calibration(c, calibration.as_ref()))
+                    .map(|c| _with_color_correction(c, correction.as_ref()))
+                    .map(|c| _with_safety(c, safety.as_ref()).with_safe_state(safe_state.clone()))
+                    .map(|c| match heartbeat {
+                        Some(policy) => c.with_heartbeat(policy), 
+                        None => c, 
+                    })
+            }; 
+            let history = history.or_else(repl::default_history_path); 
+            let mut repl = Repl::new(comm, &mut reconnect, history).with_interrupt(interrupt.clone()); 
+            let res = repl.run(); 
+            let code = match res {
+                Ok(()) => exit_code::OK, 
+                Err(e) => {
+                    error!("{_FN_NAME} Unexpected error in REPL: \n{:#?}", e); 
+                    exit_code::FAILURE
+                }
+            }; 
+            match repl.into_communicator() {
+                Some(c) => comm = c, 
+                None => process::exit(code), 
+            }
+            code
+        }, 
+        Some(Mode::Term { hex }) => match term::run(&mut comm, hex, &interrupt) {
+            Ok(()) => exit_code::OK, 
+            Err(e) => {
+                error!("{_FN_NAME} Unexpected error in terminal: \n{:#?}", e); 
+                exit_code::FAILURE
+            }
+        }, 
+        Some(Mode::Stream { interval, source, buffer, drop, count }) => {
+            let mut s = SensorStream::new(&mut comm, source, Duration::from_millis(interval))
+                .with_buffer(buffer, drop); 
+            if let Some(n) = cli.sensor_bytes {
+                s = s.with_sensor_reply(ReadSpec { mode: ReadMode::Exact(n), timeout: Some(Duration::from_millis(interval)) }); 
+            }
+            if let Some(n) = count { s = s.with_count(n); }
+            if let Some(log) = log.as_mut() { s = s.with_log(log); }
+            match s.run(cli.format, &mut io::stdout(), &interrupt) {
+                Ok(()) => exit_code::OK, 
+                Err(e) => {
+                    error!("{_FN_NAME} Unexpected error when streaming: \n{:#?}", e); 
+                    exit_code::FAILURE
+                }, 
+            }
+        }, 
+        Some(Mode::Run { script: path }) => {
+            let mut stdout = io::stdout(); 
+            let res = Runner::new(&mut comm, cli.format, &mut stdout)
+                .with_interrupt(interrupt.clone())
+                .run(script.as_ref().unwrap()); 
+            match res {
+                Ok(()) => {
+                    info!("{_FN_NAME} Script {} passed", path.display()); 
+                    exit_code::OK
+                }, 
+                Err(e) => {
+                    error!("{_FN_NAME} {}:{}: {}", path.display(), e.line, e.message); 
+                    interrupt.exit_code().unwrap_or(exit_code::FAILURE)
+                }, 
+            }
+        }, 
+        Some(Mode::Gcode { file: path, .. }) => match program.as_ref().unwrap().run(&mut comm, &interrupt) {
+            Ok(()) => {
+                info!("{_FN_NAME} G-code {} done", path.display()); 
+                exit_code::OK
+            }, 
+            Err(e) => {
+                error!("{_FN_NAME} {}:{}: {}", path.display(), e.line, e.message); 
+                interrupt.exit_code().unwrap_or(exit_code::FAILURE)
+            }, 
+        }, 
+        Some(Mode::Image { file: path, looping, .. }) => match sequence.as_ref().unwrap().run(&mut comm, &interrupt, looping) {
+            Ok(()) => {
+                info!("{_FN_NAME} Image {} shown", path.display()); 
+                exit_code::OK
+            }, 
+            Err(e) => {
+                error!("{_FN_NAME} Cannot show image {}: \n{:#?}", path.display(), e); 
+                interrupt.exit_code().unwrap_or(exit_code::FAILURE)
+            }, 
+        }, 
+        #[cfg(feature = "rhai")]
+        Some(Mode::Exec { script: path }) => {
+            let shared = Rc::new(RefCell::new(comm)); 
+            let res = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|src| scripting::run(&shared, &src, &interrupt).map_err(|e| e.to_string())); 
+            comm = Rc::into_inner(shared).expect("Rhai engine outlived script").into_inner(); 
+            match res {
+                Ok(()) => exit_code::OK, 
+                Err(e) => {
+                    error!("{_FN_NAME} {}: {}", path.display(), e); 
+                    interrupt.exit_code().unwrap_or(exit_code::FAILURE)
+                }, 
+            }
+        }, 
+        #[cfg(feature = "http")]
+        Some(Mode::Serve { addr }) => match serial_communicator::server::serve(comm, addr, &interrupt) {
+            Ok(c) => {
+                comm = c; 
+                interrupt.exit_code().unwrap_or(exit_code::OK)
+            }, 
+            Err(e) => {
+                error!("{_FN_NAME} Cannot serve HTTP API: \n{:#?}", e); 
+                process::exit(exit_code::FAILURE); 
+            }, 
+        }, 
+    }; 
+    let mut code = interrupt.exit_code().unwrap_or(code); 
+    if let Some(Err(e)) = log.map(SensorLog::close) {
+        error!("{_FN_NAME} Cannot close sensor log: \n{:#?}", e); 
+        if code == exit_code::OK { code = exit_code::FAILURE; }
+    }
+    _shutdown(comm, code); 
+}
+
+/// Puts the Arduino into its safe state, then exits with `code`. 
+/// Exits with `exit_code::UNSAFE` instead if `code` is `exit_code::OK` but the safe state was not 
+/// acknowledged. 
+fn _shutdown(mut comm: Communicator, code: i32) -> ! {
+    const _FN_NAME: &str = "[serial-communicator::shutdown]";
+
+    match comm.enter_safe_state() {
+        Ok(()) => process::exit(code), 
+        Err(e) => {
+            error!("{_FN_NAME} Cannot put {} into safe state: \n{:#?}", comm.device(), e); 
+            process::exit(if code == exit_code::OK { exit_code::UNSAFE } else { code }); 
+        }, 
+    }
+}
+
+/// Parses `stdin` into instructions for the control loop on its own thread. 
+/// READ requests make no sense at a fixed rate, so these are skipped along with invalid requests. 
+fn _spawn_command_reader(format: Format) -> mpsc::Receiver<Instruction> {
+    const _FN_NAME: &str = "[serial-communicator::command_reader]";
+
+    let (tx, rx) = mpsc::channel(); 
+    let lines = _spawn_stdin_reader(); 
+    thread::spawn(move || {
+        for line in lines {
+            match line.map(|l| format.parse_request(&l)) {
+                Ok(Ok(Request::Write(instr))) => if tx.send(instr).is_err() { return; }, 
+                Ok(Ok(Request::Read(_))) => error!("{_FN_NAME} READ is implied at fixed rate, skipped"), 
+                Ok(Ok(Request::State)) => error!("{_FN_NAME} STATE is unsupported at fixed rate, skipped"), 
+                Ok(Ok(Request::Move(_))) => error!("{_FN_NAME} MOVE is unsupported at fixed rate, skipped"), 
+                Ok(Ok(Request::Animate(_) | Request::Stop)) => error!("{_FN_NAME} Animations are unsupported at fixed rate, skipped"), 
+                Ok(Err(e)) => error!("{_FN_NAME} Invalid input from stdin: \n{:#?}", e), 
+                Err(e) => {
+                    error!("{_FN_NAME} Unexpected error when reading from stdin: \n{:#?}", e); 
+                    return; 
+                }, 
+            }
+        }
+    }); 
+    return rx; 
+}
+
+/// Runs `comm` in a fixed-rate control loop ticking every `period`, with commands from `stdin`. 
+/// 
+/// ### Returns
+/// Exit code, i.e. `exit_code::OK` on EOF, `interrupt`'s on signal, `exit_code::FAILURE` otherwise. 
+#[allow(clippy::too_many_arguments)]
+fn _run_control_loop(
+    comm: &mut Communicator, 
+    format: Format, 
+    period: Duration, 
+    sensor_bytes: Option<usize>, 
+    stats_interval: Duration, 
+    delta: Option<DeltaEncoder>, 
+    log: Option<&mut SensorLog>, 
+    interrupt: &Interrupt
+) -> i32 {
+    const _FN_NAME: &str = "[serial-communicator::run_control_loop]";
+
+    let commands = _spawn_command_reader(format); 
+    let mut ctrl = ControlLoop::new(comm, period).with_stats_interval(stats_interval); 
+    if let Some(n) = sensor_bytes {
+        ctrl = ctrl.with_sensor_reply(ReadSpec { mode: ReadMode::Exact(n), timeout: Some(period) }); 
+    }
+    if let Some(log) = log { ctrl = ctrl.with_log(log); }
+    if let Some(delta) = delta { ctrl = ctrl.with_delta(delta); }
+    if let Err(e) = ctrl.run(&commands, format, &mut io::stdout(), interrupt) {
+        error!("{_FN_NAME} Unexpected error when writing to stdout: \n{:#?}", e); 
+        return exit_code::FAILURE; 
+    }
+    return interrupt.exit_code().unwrap_or(exit_code::OK); 
+}
+
+/// Reads `stdin` line by line on its own thread, so that the reader can wait on it with a time-out. 
+/// The returned channel disconnects on EOF or after the first error. 
+fn _spawn_stdin_reader() -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel(); 
+    thread::spawn(move || loop {
+        let mut line = String::with_capacity(512); 
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => return, 
+            Ok(_) => if tx.send(Ok(line)).is_err() { return; }, 
+            Err(e) => {
+                let _ = tx.send(Err(e)); 
+                return; 
+            }, 
+        }
+    }); 
+    return rx; 
+}
+
+/// Communicator which works in a WRITE-READ loop. 
+/// Assumming Cosmos' ctrl loop it should be sufficient? 
+/// SENSOR readings and the commands before them are logged to `log`, if any. 
+/// 
+/// ### Returns
+/// Exit code, i.e. `exit_code::OK` on EOF, `interrupt`'s on signal, `exit_code::FAILURE` otherwise. 
+fn _run_stdin_loop(
+    comm: &mut Communicator, 
+    format: Format, 
+    mut log: Option<&mut SensorLog>, 
+    interrupt: &Interrupt
+) -> i32 {
+    const _FN_NAME: &str = "[serial-communicator::run_stdin_loop]";
+
+    let lines = _spawn_stdin_reader(); 
+    let mut stdout = io::stdout(); 
     
     loop {
-        arduino_port.clear(serialport::ClearBuffer::All); 
-
-        /* 2. Read from `stdin` and re-send to Arduino */
-        action_buffer.clear();
-        let action; 
-        match io::stdin().read_line(&mut action_buffer) {
-            Ok(0) => {
-                // => EOF reached, close pipe
-                info!("{_FN_NAME} EOF reached at stdin");
-                return; 
-            },
-            Ok(_) => {
+        // Written bytes may not have reached the simulator yet, and flushed ones are gone anyway
+        let _ = comm.clear(serialport::ClearBuffer::Input); 
+
+        /* Read from `stdin` and re-send to Arduino */
+        let line = loop {
+            if let Some(code) = interrupt.exit_code() {
+                info!("{_FN_NAME} Interrupted by signal"); 
+                return code; 
+            }
+            // => Woken up in time for animation frames, if any
+            let timeout = comm.next_frame_in().map_or(IDLE_POLL_INTERVAL, |d| d.clamp(ANIMATION_MIN_POLL, IDLE_POLL_INTERVAL)); 
+            match lines.recv_timeout(timeout) {
+                Ok(l) => break l, 
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(e) = comm.tick() {
+                        error!("{_FN_NAME} Unexpected error when sending heartbeat: \n{:#?}", e); 
+                    }
+                    _report_link_events(comm, format, &mut stdout); 
+                }, 
+                Err(RecvTimeoutError::Disconnected) => {
+                    // => EOF reached, close pipe
+                    info!("{_FN_NAME} EOF reached at stdin");
+                    return exit_code::OK; 
+                }, 
+            }
+        }; 
+        let action = match line {
+            Ok(l) => {
                 // => Try convert to `Action` instance
-                action = Request::try_from(action_buffer.as_ref())
+                format.parse_request(&l)
             },
             Err(e) => {
                 error!("{_FN_NAME} Unexpected error when reading from stdin: \n{:#?}", e);
-                return;
+                if format.reports_request_errors() {
+                    let _ = format.write_error(&mut stdout, Status::Error, comm.device(), &e.to_string()); 
+                }
+                return exit_code::FAILURE;
             }
         };
 
         match action {
-            Ok(Request::Read) => {
-                // => Wait read on Arduino, send to `stdout`
-                while let Err(e) = read_all_bytes_into(
-                    arduino_port, 
-                    &mut read_buffer
-                ) { 
-                    if e.kind() == std::io::ErrorKind::TimedOut { continue; }
-                    error!(
-                        "{_FN_NAME} Unexpected error when reading from Arduino: \n{:#?}", 
-                        e
-                    ); 
-                    break; 
+            Ok(Request::Read(spec)) => {
+                if let Err(e) = _answer_read(comm, format, log.as_deref_mut(), &mut stdout, spec) {
+                    error!("{_FN_NAME} Unexpected error when writing to stdout: \n{:#?}", e); 
+                    return exit_code::FAILURE; 
                 }
-                let mut stdout = io::stdout(); 
-                if let Err(e) = stdout.write_all(&read_buffer) {
-                    error!(
-                        "{_FN_NAME} Unexpected error when writing to stdout: \n{:#?}", 
-                        e
-                    ); 
-                    return; 
+            }, 
+            Ok(Request::State) => {
+                if let Err(e) = _answer_state(comm, format, &mut stdout) {
+                    error!("{_FN_NAME} Unexpected error when writing to stdout: \n{:#?}", e); 
+                    return exit_code::FAILURE; 
                 }
-                if let Err(e) = stdout.flush() {
-                    error!(
-                        "{_FN_NAME} Unexpected error when flushing stdout: \n{:#?}", 
-                        e
-                    ); 
-                    return; 
-                } 
-                info!(
-                    "{_FN_NAME} Received \"{:x?}\"", 
-                    read_buffer
-                ); 
-                read_buffer.clear(); 
             }, 
-            Ok(Request::Write(v)) => {
-                // => Write to Arduino
-                if let Err(e) = write_all_bytes(
-                    arduino_port, 
-                    &v, 
+            Ok(req @ (Request::Write(_) | Request::Move(_) | Request::Animate(_) | Request::Stop)) => {
+                _send_request(comm, format, log.as_deref_mut(), &mut stdout, &req); 
+            }, 
+            Err(e) => {
+                error!("{_FN_NAME} Invalid input from stdin: \n{:#?}", e); 
+                if !format.reports_request_errors() { continue; }
+                if let Err(e) = format.write_error(
+                    &mut stdout, Status::Invalid, comm.device(), &e.to_string()
                 ) {
                     error!(
-                        "{} Unexpected error when sending to arduino tty: \n{:#?}", 
-                        _FN_NAME, 
-                        e
-                    );
-                    return;
-                }
-                if let Err(e) = arduino_port.flush() {
-                    error!(
-                        "{_FN_NAME} Unexpected error when flushing arduino tty: \n{:#?}", 
+                        "{_FN_NAME} Unexpected error when writing to stdout: \n{:#?}", 
                         e
                     ); 
-                    return; 
+                    return exit_code::FAILURE; 
                 }
-                info!(
-                    "{_FN_NAME} Written {:x?}", 
-                    v
-                ); 
             }, 
-            Err(e) => 
-                error!("{_FN_NAME} Invalid input from stdin: \n{:#?}", e), 
         }
     }
+}
+
+/// Waits for a read on `comm` as per `spec`, writing the response or error to `out` in `format`.
+/// Responses are also recorded in `log`, if any.
+/// 
+/// # Errors
+/// Any `io::Error` when writing to `out`. 
+fn _answer_read(
+    comm: &mut Communicator, 
+    format: Format, 
+    log: Option<&mut SensorLog>, 
+    out: &mut dyn io::Write, 
+    spec: ReadSpec
+) -> io::Result<()> {
+    const _FN_NAME: &str = "[serial-communicator::answer_read]";
+
+    let res = comm.execute(&Request::Read(spec)); 
+    _report_link_events(comm, format, out); 
+    return match res {
+        Ok(Outcome::Received(res)) => {
+            if let Some(Err(e)) = log.map(|l| l.record_response(&res)) {
+                error!("{_FN_NAME} Cannot log reading: \n{:#?}", e); 
+            }
+            format.write_response(out, &res)
+        }, 
+        Ok(Outcome::Written(_) | Outcome::State(_)) => unreachable!(), 
+        Err(e) => {
+            error!("{_FN_NAME} Unexpected error when reading from Arduino: \n{:#?}", e); 
+            format.write_error(out, Status::from(&e), comm.device(), &e.to_string())
+        }
+    }; 
+}
 
-    // arduino_port.clear(serialport::ClearBuffer::All); 
+/// Writes the host-side mirror of the board behind `comm` to `out` in `format`, once ACKs already
+/// received are applied. 
+/// 
+/// # Errors
+/// Any `io::Error` when writing to `out`. 
+fn _answer_state(comm: &mut Communicator, format: Format, out: &mut dyn io::Write) -> io::Result<()> {
+    return match comm.poll_board_state().cloned() {
+        Ok(state) => format.write_state(out, comm.device(), &state), 
+        Err(e) => format.write_error(out, Status::from(&e), comm.device(), &e.to_string()), 
+    }; 
+}
+
+/// Sends `req` to the Arduino behind `comm`, recording written instructions in `log`, if any.
+/// Errors are reported to `out` if `format` reports request errors, and the pipe is kept open
+/// since the next request attempts to reconnect. 
+fn _send_request(
+    comm: &mut Communicator, 
+    format: Format, 
+    log: Option<&mut SensorLog>, 
+    out: &mut dyn io::Write, 
+    req: &Request
+) {
+    const _FN_NAME: &str = "[serial-communicator::send_request]";
+
+    let res = comm.execute(req); 
+    _report_link_events(comm, format, out); 
+    match (res, log) {
+        (Ok(Outcome::Written(instr)), Some(l)) => l.record_command(&instr), 
+        (Ok(_), _) => {}, 
+        (Err(e), _) => {
+            error!("{_FN_NAME} Unexpected error when sending to arduino tty: \n{:#?}", e);
+            if format.reports_request_errors() {
+                let _ = format.write_error(out, Status::from(&e), comm.device(), &e.to_string()); 
+            }
+        }, 
+    }
+}
+
+/// Reports connection state changes of `comm` to the client, in `format`. 
+fn _report_link_events(comm: &mut Communicator, format: Format, out: &mut dyn io::Write) {
+    const _FN_NAME: &str = "[serial-communicator::report_link_events]";
+
+    for event in comm.take_events() {
+        info!("{_FN_NAME} {:?}", event); 
+        if let Err(e) = format.write_event(out, &event) {
+            error!("{_FN_NAME} Unexpected error when writing to stdout: \n{:#?}", e); 
+        }
+    }
 }
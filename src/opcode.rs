@@ -0,0 +1,20 @@
+//! Opcodes spoken by this host on top of those generated from `arduino_comms/opcode.h` into 
+//! `bindings`. 
+//! 
+//! `bindings.rs` is regenerated by the build script, so opcodes the header does not define yet 
+//! live here instead. Firmware must define them with the same values to understand them; the 
+//! simulator does. 
+
+/// Sets the magnets listed by index, leaving the others as they are. 
+pub const MAGNET_DELTA: u8 = 4; 
+/// Sets the LEDs listed by index, leaving the others as they are. 
+pub const LED_DELTA: u8 = 5; 
+/// Flag set on the opcode of `MAGNET` and `LED` frames whose cells or colours are continued by
+/// the next frame, as per `chunk`.
+pub const CONTINUED: u8 = 0x40; 
+/// Liveness probe, echoed back by the board. 
+pub const HEARTBEAT: u8 = 17; 
+/// Toggles streaming of sensor readings. 
+pub const STREAM: u8 = 18; 
+/// Asks the board for a dump of its magnets and LEDs. 
+pub const STATE: u8 = 19; 
@@ -0,0 +1,361 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Interactive REPL for bring-up, with line editing, persistent history, tab-completion and
+//! inline argument hints.
+//!
+//! Lines are `READ ...`/`WRITE ...`/`STATE`/`MOVE ...`/`ANIMATE ...`/`STOP` requests as parsed
+//! by `Request::try_from`, or meta-commands:
+//! - `:devices`: Lists serial ports available on host.
+//! - `:baud [<rate>]`: Shows or sets baud rate of the connected port.
+//! - `:reconnect`: Drops the connection and connects anew.
+//! - `:help`: Lists meta-commands.
+//! - `:quit`: Exits the REPL, as does EOF (Ctrl-D).
+//!
+//! Lines are read on their own thread, so that animations keep playing and heartbeats keep being
+//! sent while waiting for input. Ctrl-C only cancels the line being edited, whereas a signal
+//! caught by the `Interrupt` given to `Repl::with_interrupt`, i.e. `SIGTERM`, exits the REPL.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{info, warn};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::board::BoardState;
+use crate::communicator::{Communicator, Outcome};
+use crate::response::{to_hex, Decoded, Response};
+use crate::shutdown::Interrupt;
+use crate::Request;
+
+const PROMPT: &str = "> ";
+const VERBS: [&str; 6] = ["READ", "WRITE", "STATE", "MOVE", "ANIMATE", "STOP"];
+const META_COMMANDS: [&str; 5] = [":devices", ":baud", ":reconnect", ":help", ":quit"];
+const READ_KEYWORDS: [&str; 3] = ["UNTIL", "FRAME", "TIMEOUT"];
+/// How often the REPL ticks its `Communicator` while waiting for input.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// Shortest wait for input while an animation frame is due.
+const ANIMATION_MIN_POLL: Duration = Duration::from_millis(1);
+
+/// Default history file, i.e. `$HOME/.serial_communicator_history`.
+#[must_use]
+pub fn default_history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".serial_communicator_history"))
+}
+
+/// Rustyline helper providing completion of verbs, opcode names and keywords, and inline hints
+/// of expected arguments.
+pub struct ReplHelper;
+
+impl ReplHelper {
+    /// Candidates for the word following `prev`, i.e. the words before the cursor.
+    fn _candidates(prev: &[&str]) -> Vec<&'static str> {
+        match prev {
+            [] => VERBS.iter().chain(META_COMMANDS.iter()).copied().collect(),
+            ["WRITE"] => Request::OPCODES.iter().map(|(name, _)| *name).collect(),
+            ["READ"] => READ_KEYWORDS.to_vec(),
+            ["READ", w] if *w != "UNTIL" && *w != "TIMEOUT" => vec!["TIMEOUT"],
+            ["READ", "UNTIL", _] => vec!["TIMEOUT"],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Hint of the arguments expected after `prev`, i.e. the words before the cursor.
+    fn _argument_hint(prev: &[&str]) -> Option<&'static str> {
+        match prev {
+            ["WRITE"] => Some("<OP>"),
+            ["WRITE", "MAGNET", ..] => Some("<x> <y> <true|false> ..."),
+            ["WRITE", "LED", ..] => Some("<#RRGGBB | 0xRRGGBB | name | rgb(r,g,b) | hsv(h,s,v)> ..."),
+            ["WRITE", "MAGNET_DELTA", ..] => Some("<i> <x> <y> <true|false> ..."),
+            ["WRITE", "LED_DELTA", ..] => Some("<i> <colour> ..."),
+            ["WRITE", "STREAM"] => Some("<ms>"),
+            ["READ"] => Some("[<n> | UNTIL <byte> | FRAME] [TIMEOUT <ms>]"),
+            ["READ", "UNTIL"] => Some("<byte>"),
+            ["MOVE"] => Some("<x0> <y0> <x1> <y1> ... OVER <ms> [LINEAR | SPLINE] [SPEED <v>] [ACCEL <a>]"),
+            ["ANIMATE"] => Some("<FADE|PULSE|RAINBOW|CHASE|KEYFRAMES> ... OVER <ms> [LOOP] [LEDS <n>] [FPS <f>]"),
+            [.., "TIMEOUT"] => Some("<ms>"),
+            [":baud"] => Some("[<rate>]"),
+            _ => None,
+        }
+    }
+
+    /// Splits `line` up to `pos` into completed words and the word under the cursor.
+    fn _split_at_cursor(line: &str, pos: usize) -> (Vec<&str>, usize, &str) {
+        let head = &line[..pos];
+        let start = head.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        (head[..start].split_ascii_whitespace().collect(), start, &head[start..])
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (prev, start, word) = Self::_split_at_cursor(line, pos);
+        let candidates = Self::_candidates(&prev)
+            .into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(|c| Pair { display: c.to_owned(), replacement: format!("{c} ") })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() { return None; }
+        let (prev, _, word) = Self::_split_at_cursor(line, pos);
+        if word.is_empty() {
+            return Self::_argument_hint(&prev).map(str::to_owned);
+        }
+        // Complete the word under the cursor inline if unambiguous
+        let mut matches = Self::_candidates(&prev).into_iter().filter(|c| c.starts_with(word));
+        match (matches.next(), matches.next()) {
+            (Some(c), None) => Some(c[word.len()..].to_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{hint}\x1b[0m"))
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Formats `res` as its decoded fields followed by its raw bytes in hex.
+#[must_use]
+pub fn pretty_response(res: &Response) -> String {
+    let decoded = match &res.decoded {
+        Decoded::Sensor { readings } => format!("SENSOR {readings:?}"),
+        Decoded::State { magnets, leds } => format!("STATE {} magnets, {} LEDs", magnets.len(), leds.len()),
+        Decoded::Handshake => String::from("HANDSHAKE"),
+        Decoded::Heartbeat => String::from("HEARTBEAT"),
+        Decoded::Ack       => String::from("ACK"),
+        Decoded::Quit      => String::from("QUIT"),
+        Decoded::Unknown   => String::from("<unknown>"),
+    };
+    format!("{decoded}  [{} bytes: {}]", res.raw.len(), to_hex(&res.raw))
+}
+
+/// Formats `state` as one line per magnet cell and LED colour, after whether it is synced.
+#[must_use]
+pub fn pretty_state(state: &BoardState) -> String {
+    let mut s = String::from(if state.synced { "synced" } else { "not synced" });
+    for (i, m) in state.magnets.iter().enumerate() {
+        let _ = write!(s, "\nmagnet {i}: ({}, {}) {}", m.x, m.y, if m.on { "on" } else { "off" });
+    }
+    for (i, c) in state.leds.iter().enumerate() {
+        let _ = write!(s, "\nled {i}: #{c:06x}");
+    }
+    return s;
+}
+
+/// Reads non-empty lines with `editor` on its own thread, as it blocks, sending them over the
+/// returned receiver. Each line is added to history, and the next prompt waits for a message on
+/// the returned sender. Once that is dropped, history is saved to `history_path`, if any.
+fn _spawn_reader(
+    mut editor: Editor<ReplHelper, DefaultHistory>,
+    history_path: Option<PathBuf>,
+) -> (Receiver<rustyline::Result<String>>, Sender<()>, JoinHandle<()>) {
+    const _FN_NAME: &str = "[repl::reader]";
+
+    let (tx, lines) = mpsc::channel();
+    let (next, rx) = mpsc::channel();
+    let reader = thread::spawn(move || {
+        loop {
+            let res = match editor.readline(PROMPT) {
+                Err(ReadlineError::Interrupted) => continue,
+                Ok(l) if l.trim().is_empty() => continue,
+                Ok(l) => {
+                    let _ = editor.add_history_entry(l.trim());
+                    Ok(l)
+                },
+                Err(e) => Err(e),
+            };
+            let done = res.is_err();
+            if tx.send(res).is_err() || done || rx.recv().is_err() { break; }
+        }
+        if let Some(path) = &history_path {
+            if let Err(e) = editor.save_history(path) {
+                warn!("{_FN_NAME} Cannot save history to {}: {e}", path.display());
+            }
+        }
+    });
+    return (lines, next, reader);
+}
+
+/// Interactive REPL over a `Communicator`.
+pub struct Repl<'a> {
+    comm: Option<Communicator>,
+    reconnect: &'a mut dyn FnMut() -> io::Result<Communicator>,
+    history_path: Option<PathBuf>,
+    interrupt: Interrupt,
+}
+
+impl<'a> Repl<'a> {
+    /// Creates a REPL over `comm`, calling `reconnect` on `:reconnect` and keeping history at
+    /// `history_path` if any.
+    pub fn new(
+        comm: Communicator,
+        reconnect: &'a mut dyn FnMut() -> io::Result<Communicator>,
+        history_path: Option<PathBuf>,
+    ) -> Self {
+        Self { comm: Some(comm), reconnect, history_path, interrupt: Interrupt::default() }
+    }
+
+    /// Exits the REPL before running another line once `interrupt` is set.
+    #[must_use]
+    pub fn with_interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Current connection, if any, e.g. to shut it down after `Repl::run`.
+    #[must_use]
+    pub fn into_communicator(self) -> Option<Communicator> {
+        self.comm
+    }
+
+    /// Runs the REPL until `:quit`, EOF or an interrupt. Animations are played and heartbeats
+    /// sent as per `Communicator::tick` while waiting for input.
+    ///
+    /// # Errors
+    /// Any `ReadlineError` from the terminal other than interrupts and EOF.
+    pub fn run(&mut self) -> rustyline::Result<()> {
+        let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+        editor.set_helper(Some(ReplHelper));
+        if let Some(path) = &self.history_path {
+            // Missing history file is expected on first run
+            let _ = editor.load_history(path);
+        }
+        let (lines, next, reader) = _spawn_reader(editor, self.history_path.clone());
+
+        let res = loop {
+            if self.interrupt.is_set() { break Ok(()); }
+            // => Woken up in time for animation frames, if any
+            let timeout = self.comm
+                .as_ref()
+                .and_then(Communicator::next_frame_in)
+                .map_or(TICK_INTERVAL, |d| d.clamp(ANIMATION_MIN_POLL, TICK_INTERVAL));
+            match lines.recv_timeout(timeout) {
+                Ok(Ok(line)) => {
+                    let line = line.trim();
+                    if line.starts_with(':') {
+                        if !self._run_meta_command(line) { break Ok(()); }
+                    } else {
+                        println!("{}", self._run_request(line));
+                    }
+                    // => Prompt again only once the output is printed
+                    let _ = next.send(());
+                },
+                Ok(Err(ReadlineError::Eof)) | Err(RecvTimeoutError::Disconnected) => break Ok(()),
+                Ok(Err(e)) => break Err(e),
+                Err(RecvTimeoutError::Timeout) => self._tick(),
+            }
+        };
+
+        // The reader saves history once told to stop, unless still blocked on input
+        drop(next);
+        if !self.interrupt.is_set() { let _ = reader.join(); }
+        return res;
+    }
+
+    fn _tick(&mut self) {
+        const _FN_NAME: &str = "[Repl::tick]";
+
+        let Some(comm) = self.comm.as_mut() else { return; };
+        if let Err(e) = comm.tick() { warn!("{_FN_NAME} Cannot tick {}: {e}", comm.device()); }
+        for event in comm.take_events() {
+            info!("{_FN_NAME} {event:?}");
+        }
+    }
+
+    fn _run_request(&mut self, line: &str) -> String {
+        let req = match Request::try_from(line) {
+            Ok(r) => r,
+            Err(e) => return format!("invalid request: {e}"),
+        };
+        let Some(comm) = self.comm.as_mut() else {
+            return String::from("not connected, try :reconnect");
+        };
+        match comm.execute(&req) {
+            Ok(Outcome::Written(v))   => format!("written [{} bytes: {}]", v.len(), to_hex(&v)),
+            Ok(Outcome::Received(r))  => pretty_response(&r),
+            Ok(Outcome::State(s))     => pretty_state(&s),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => String::from("timed out"),
+            Err(e) => format!("error: {e}"),
+        }
+    }
+
+    /// Runs meta-command `line`, returning whether the REPL should go on.
+    fn _run_meta_command(&mut self, line: &str) -> bool {
+        let mut words = line.split_ascii_whitespace();
+        match (words.next(), words.next()) {
+            (Some(":quit" | ":exit"), None) => return false,
+            (Some(":help"), None) => println!("{}", META_COMMANDS.join("  ")),
+            (Some(":devices"), None) => {
+                let current = self.comm.as_ref().map(Communicator::device);
+                match serialport::available_ports() {
+                    Ok(ports) => for p in ports {
+                        let mark = if Some(p.port_name.as_str()) == current { "*" } else { " " };
+                        println!("{mark} {}  {:?}", p.port_name, p.port_type);
+                    },
+                    Err(e) => println!("error: {e}"),
+                }
+            },
+            (Some(":baud"), rate) => {
+                let Some(port) = self.comm.as_mut().and_then(Communicator::port_mut) else {
+                    println!("not connected, try :reconnect");
+                    return true;
+                };
+                let res = match rate.map(str::parse::<u32>) {
+                    None => port.baud_rate(),
+                    Some(Ok(r)) => port.set_baud_rate(r).and_then(|()| port.baud_rate()),
+                    Some(Err(e)) => {
+                        println!("invalid baud rate: {e}");
+                        return true;
+                    },
+                };
+                match res {
+                    Ok(r)  => println!("{r}"),
+                    Err(e) => println!("error: {e}"),
+                }
+            },
+            (Some(":reconnect"), None) => {
+                // Release port first, else it cannot be reopened
+                self.comm = None;
+                match (self.reconnect)() {
+                    Ok(c) => {
+                        println!("connected to {}", c.device());
+                        self.comm = Some(c);
+                    },
+                    Err(e) => println!("error: {e}"),
+                }
+            },
+            _ => println!("unknown meta-command {line}, try :help"),
+        }
+        return true;
+    }
+}
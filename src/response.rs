@@ -0,0 +1,93 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer};
+
+use crate::{bindings, opcode};
+use crate::board::BoardState;
+use crate::command::MagnetCell;
+
+/// Fields decoded from the bytes returned by the Arduino.
+///
+/// The first byte of a reply is taken as its opcode. `SENSOR` replies carry their readings as
+/// little-endian `f32`s, the same way `MAGNET` coordinates are sent. `STATE` replies are decoded
+/// as per `BoardState::decode_reply`, or as `Unknown` if malformed.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "UPPERCASE")]
+pub enum Decoded {
+    Sensor { readings: Vec<f32> },
+    State { magnets: Vec<MagnetCell>, leds: Vec<u32> },
+    Handshake,
+    Heartbeat,
+    Ack,
+    Quit,
+    Unknown,
+}
+
+impl Decoded {
+    #[must_use]
+    pub fn from_bytes(raw: &[u8]) -> Self {
+        match raw.split_first() {
+            Some((&bindings::SENSOR, payload)) => Self::Sensor {
+                readings: payload
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect(),
+            },
+            Some((&opcode::STATE, payload)) => BoardState::decode_reply(payload)
+                .map_or(Self::Unknown, |s| Self::State { magnets: s.magnets, leds: s.leds }),
+            Some((&bindings::HANDSHAKE, _)) => Self::Handshake,
+            Some((&opcode::HEARTBEAT, _)) => Self::Heartbeat,
+            Some((&bindings::ACK, _))       => Self::Ack,
+            Some((&bindings::QUIT, _))      => Self::Quit,
+            _                               => Self::Unknown,
+        }
+    }
+}
+
+/// A reply read from an Arduino, stamped with its source device and time of receipt.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Response {
+    pub device: String,
+    /// Milliseconds since UNIX epoch.
+    pub timestamp: u64,
+    #[serde(serialize_with = "serialize_hex")]
+    pub raw: Vec<u8>,
+    pub decoded: Decoded,
+}
+
+impl Response {
+    #[must_use]
+    pub fn new(device: &str, raw: Vec<u8>) -> Self {
+        Self {
+            device: device.to_owned(),
+            timestamp: timestamp_now(),
+            decoded: Decoded::from_bytes(&raw),
+            raw,
+        }
+    }
+}
+
+/// Milliseconds since UNIX epoch, or 0 if the system clock is set before it.
+#[must_use]
+pub fn timestamp_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Formats `bytes` as a contiguous lowercase hex string, e.g. `[0x01, 0xab]` as `"01ab"`.
+#[must_use]
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{b:02x}");
+    }
+    return s;
+}
+
+pub(crate) fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(bytes))
+}
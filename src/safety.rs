@@ -0,0 +1,311 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Safety limits on `MAGNET` commands, configured per rig, to keep magnets within the board and
+//! from overheating.
+//!
+//! Limits are read from a JSON file of form:
+//! ```json
+//! {"x": [0.0, 300.0], "y": [0.0, 200.0], "max_on": 4,
+//!  "duty": {"max_on_ms": 5000, "duty_cycle": 0.25}, "mode": "clamp"}
+//! ```
+//! where every field is optional. Coordinates must be finite regardless.
+//!
+//! Duty cycles are tracked per magnet position as heat: each millisecond on adds one millisecond
+//! of heat, and each millisecond off takes away `duty_cycle / (1 - duty_cycle)` of one. A magnet
+//! whose heat has reached `max_on_ms` may not be turned or kept on, so that it stays on for at
+//! most `max_on_ms` in one go and for at most `duty_cycle` of the time in the long run. Heat is
+//! only checked on commands, so a magnet left on is only caught by the next `MAGNET`.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{bindings, opcode};
+use crate::board::{decode_cells, decode_magnet_delta, BoardState};
+use crate::command::{Command, MagnetCell};
+use crate::Instruction;
+
+/// What to do with a `MAGNET` command that violates a limit.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SafetyMode {
+    /// Refuse to send the command.
+    #[default]
+    Reject,
+    /// Clamp coordinates into bounds and turn off magnets over the limits, then send it.
+    Clamp,
+}
+
+/// Per-magnet on-time budget.
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+pub struct DutyBudget {
+    /// Longest time a magnet may stay on in one go, from cold.
+    pub max_on_ms: u64,
+    /// Fraction of time a magnet may stay on in the long run, within `[0, 1]`.
+    pub duty_cycle: f32,
+}
+
+/// Limits of one rig. Unset limits are not enforced.
+#[derive(Debug, PartialEq, Clone, Default, Deserialize)]
+pub struct SafetyLimits {
+    /// Valid range of `x`, as `[min, max]`.
+    #[serde(default)]
+    pub x: Option<(f32, f32)>,
+    /// Valid range of `y`, as `[min, max]`.
+    #[serde(default)]
+    pub y: Option<(f32, f32)>,
+    /// Maximum number of magnets on at once.
+    #[serde(default)]
+    pub max_on: Option<usize>,
+    #[serde(default)]
+    pub duty: Option<DutyBudget>,
+    #[serde(default)]
+    pub mode: SafetyMode,
+}
+
+impl SafetyLimits {
+    /// Reads limits from the JSON file at `path`.
+    ///
+    /// # Errors
+    /// - `io::ErrorKind::InvalidData` if the file is not valid JSON of the form above, or a range
+    ///   or the duty cycle is invalid.
+    /// - Any other `io::Error` from reading the file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let limits: Self = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let invalid_range = |r: Option<(f32, f32)>| r
+            .is_some_and(|(min, max)| min.partial_cmp(&max).is_none_or(Ordering::is_gt));
+        if invalid_range(limits.x) || invalid_range(limits.y) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected ranges as [min, max]"));
+        }
+        if limits.duty.is_some_and(|d| !(0.0..=1.0).contains(&d.duty_cycle)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected duty cycle within [0, 1]"));
+        }
+        return Ok(limits);
+    }
+}
+
+/// A limit hit by a `MAGNET` command, with `cell` the index of the offending cell.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Violation {
+    NonFinite { cell: usize },
+    OutOfBounds { cell: usize, axis: char, value: f32, min: f32, max: f32 },
+    TooManyOn { on: usize, max: usize },
+    DutyExceeded { cell: usize, heat_ms: u64, max_on_ms: u64 },
+}
+
+impl Violation {
+    /// Name of the rule hit, as in the limits file, or `finite`.
+    #[must_use]
+    pub const fn rule(&self) -> &'static str {
+        match self {
+            Self::NonFinite { .. }    => "finite",
+            Self::OutOfBounds { axis: 'x', .. } => "x",
+            Self::OutOfBounds { .. }  => "y",
+            Self::TooManyOn { .. }    => "max_on",
+            Self::DutyExceeded { .. } => "duty",
+        }
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Safety rule `{}` hit: ", self.rule())?;
+        match self {
+            Self::NonFinite { cell } =>
+                write!(f, "cell {cell} has non-finite coordinates"),
+            Self::OutOfBounds { cell, axis, value, min, max } =>
+                write!(f, "cell {cell} has {axis} = {value}, outside [{min}, {max}]"),
+            Self::TooManyOn { on, max } =>
+                write!(f, "{on} magnets on, at most {max} allowed"),
+            Self::DutyExceeded { cell, heat_ms, max_on_ms } =>
+                write!(f, "cell {cell} has been on for {heat_ms} ms of its {max_on_ms} ms budget"),
+        }
+    }
+}
+
+impl Error for Violation {}
+
+/// Magnet position, as bit patterns so that it can be hashed.
+type Position = (u32, u32);
+
+const fn _position(cell: &MagnetCell) -> Position {
+    (cell.x.to_bits(), cell.y.to_bits())
+}
+
+/// Clamps `value` of `axis` of `cell` into `range`, if any.
+///
+/// ### Returns
+/// The violation clamped away, if out of range.
+fn _bound(cell: usize, axis: char, value: &mut f32, range: Option<(f32, f32)>) -> Option<Violation> {
+    let (min, max) = range?;
+    if (min..=max).contains(value) { return None; }
+    let violation = Violation::OutOfBounds { cell, axis, value: *value, min, max };
+    *value = value.clamp(min, max);
+    Some(violation)
+}
+
+/// Checks `MAGNET` commands against `SafetyLimits`, tracking the heat of each magnet.
+#[derive(Debug, Clone)]
+pub struct SafetyGuard {
+    limits: SafetyLimits,
+    /// Heat in milliseconds by magnet position, of magnets not yet cooled down.
+    heat: HashMap<Position, f64>,
+    /// Positions of the magnets on as of the last accepted command.
+    on: Vec<Position>,
+    updated: Instant,
+}
+
+impl SafetyGuard {
+    #[must_use]
+    pub fn new(limits: SafetyLimits) -> Self {
+        Self { limits, heat: HashMap::new(), on: Vec::new(), updated: Instant::now() }
+    }
+
+    #[must_use]
+    pub const fn limits(&self) -> &SafetyLimits {
+        &self.limits
+    }
+
+    /// Heat of the magnet at `cell`'s position, in milliseconds, as of now.
+    #[must_use]
+    pub fn heat_ms(&self, cell: &MagnetCell) -> f64 {
+        let mut heat = self.heat.clone();
+        self._cool_into(&mut heat);
+        heat.get(&_position(cell)).copied().unwrap_or(0.0)
+    }
+
+    /// Heat after the time since the last update, with magnets on heating and the others cooling.
+    fn _cool_into(&self, heat: &mut HashMap<Position, f64>) {
+        let Some(duty) = self.limits.duty else { return; };
+        let elapsed_ms = self.updated.elapsed().as_secs_f64() * 1000.0;
+        let duty_cycle = f64::from(duty.duty_cycle);
+        let cooling_ms = if duty_cycle < 1.0 {
+            elapsed_ms * duty_cycle / (1.0 - duty_cycle)
+        } else {
+            f64::INFINITY
+        };
+        heat.retain(|p, h| {
+            *h = if self.on.contains(p) { *h + elapsed_ms } else { (*h - cooling_ms).max(0.0) };
+            *h > 0.0
+        });
+        for p in &self.on {
+            heat.entry(*p).or_insert(elapsed_ms);
+        }
+    }
+
+    /// Checks `cells`, the full set of cells about to be sent, clamping them in place if the mode
+    /// says so. Accepted cells become the state against which heat is tracked.
+    ///
+    /// ### Returns
+    /// The violations clamped away, in order of cells.
+    ///
+    /// # Errors
+    /// The first `Violation` hit, if rejecting or the coordinates are non-finite. `cells` is left
+    /// in an unspecified state.
+    pub fn check(&mut self, cells: &mut [MagnetCell]) -> Result<Vec<Violation>, Violation> {
+        let reject = self.limits.mode == SafetyMode::Reject;
+        let mut clamped = Vec::new();
+        let mut hit = |v: Violation| if reject { Err(v) } else { clamped.push(v); Ok(()) };
+
+        /* 1. Coordinates */
+        for (i, c) in cells.iter_mut().enumerate() {
+            if !c.x.is_finite() || !c.y.is_finite() { return Err(Violation::NonFinite { cell: i }); }
+            if let Some(v) = _bound(i, 'x', &mut c.x, self.limits.x) { hit(v)?; }
+            if let Some(v) = _bound(i, 'y', &mut c.y, self.limits.y) { hit(v)?; }
+        }
+
+        /* 2. Duty cycles */
+        let mut heat = self.heat.clone();
+        self._cool_into(&mut heat);
+        if let Some(duty) = self.limits.duty {
+            #[allow(clippy::cast_precision_loss)]
+            let budget = duty.max_on_ms as f64;
+            for (i, c) in cells.iter_mut().enumerate().filter(|(_, c)| c.on) {
+                let h = heat.get(&_position(c)).copied().unwrap_or(0.0);
+                if h < budget { continue; }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                hit(Violation::DutyExceeded { cell: i, heat_ms: h as u64, max_on_ms: duty.max_on_ms })?;
+                c.on = false;
+            }
+        }
+
+        /* 3. Number of magnets on */
+        if let Some(max) = self.limits.max_on {
+            let on = cells.iter().filter(|c| c.on).count();
+            if on > max {
+                hit(Violation::TooManyOn { on, max })?;
+                for c in cells.iter_mut().filter(|c| c.on).skip(max) { c.on = false; }
+            }
+        }
+
+        self.heat = heat;
+        self.on = cells.iter().filter(|c| c.on).map(_position).collect();
+        self.updated = Instant::now();
+        return Ok(clamped);
+    }
+
+    /// Checks `instr` before it is written, given the board as last mirrored. `MAGNET_DELTA` is
+    /// checked as applied to `board`, or on its own for cells `board` does not know of. `QUIT`
+    /// resets the magnets on; other instructions pass through unchecked.
+    ///
+    /// ### Returns
+    /// The instruction to write instead, i.e. `instr` re-encoded once clamped, with the
+    /// violations clamped away. A clamped `MAGNET_DELTA` turns into a full `MAGNET` if it had to
+    /// change cells outside the delta.
+    ///
+    /// # Errors
+    /// The first `Violation` hit, as per `SafetyGuard::check`.
+    pub fn guard(&mut self, instr: &[u8], board: &BoardState) -> Result<(Instruction, Vec<Violation>), Violation> {
+        match instr.split_first() {
+            Some((&bindings::MAGNET, args)) => {
+                let mut cells = decode_cells(args);
+                let clamped = self.check(&mut cells)?;
+                if clamped.is_empty() { return Ok((instr.to_vec(), clamped)); }
+                return Ok((Command::Magnet { cells }.encode(), clamped));
+            },
+            Some((&opcode::MAGNET_DELTA, args)) => {
+                let delta = decode_magnet_delta(args);
+                if delta.iter().any(|&(i, _)| usize::from(i) >= board.magnets.len()) {
+                    let mut cells: Vec<MagnetCell> = delta.iter().map(|&(_, c)| c).collect();
+                    let clamped = self.check(&mut cells)?;
+                    let delta = delta.iter().zip(cells).map(|(&(i, _), c)| (i, c)).collect();
+                    return Ok((Command::MagnetDelta { cells: delta }.encode(), clamped));
+                }
+
+                let mut cells = board.magnets.clone();
+                for &(i, c) in &delta { cells[usize::from(i)] = c; }
+                let clamped = self.check(&mut cells)?;
+                if clamped.is_empty() { return Ok((instr.to_vec(), clamped)); }
+                let outside_delta = cells
+                    .iter()
+                    .zip(&board.magnets)
+                    .enumerate()
+                    .any(|(i, (c, b))| c != b && !delta.iter().any(|&(j, _)| usize::from(j) == i));
+                if outside_delta { return Ok((Command::Magnet { cells }.encode(), clamped)); }
+                let delta = delta.iter().map(|&(i, _)| (i, cells[usize::from(i)])).collect();
+                return Ok((Command::MagnetDelta { cells: delta }.encode(), clamped));
+            },
+            Some((&bindings::QUIT, _)) => self.reset(),
+            _ => {},
+        }
+        return Ok((instr.to_vec(), Vec::new()));
+    }
+
+    /// Notes that all magnets were turned off, e.g. by `QUIT`.
+    pub fn reset(&mut self) {
+        let mut heat = self.heat.clone();
+        self._cool_into(&mut heat);
+        self.heat = heat;
+        self.on.clear();
+        self.updated = Instant::now();
+    }
+}
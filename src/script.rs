@@ -0,0 +1,336 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Line-based test scripts on top of the `Request` grammar.
+//!
+//! ## Grammar
+//! One statement per line. Blank lines and comments are ignored, i.e. everything from a `#` at the
+//! start of a line or after whitespace, unless it begins a colour such as `#ff0000`.
+//! - `READ ...` / `WRITE ...` / `STATE` / `MOVE ...` / `ANIMATE ...` / `STOP`: As parsed by
+//!   `Request::try_from`. `STATE` writes the host-side board state to the output.
+//! - `SLEEP <ms>`: Sleeps for `ms` milliseconds.
+//! - `SET <name> <value...>`: Sets variable `name`. `$name` or `${name}` in any later line is
+//!   substituted with its value at the time that line runs.
+//! - `REPEAT <n> {` ... `}`: Runs the enclosed lines `n` times, with `$ITER` set to the 0-based
+//!   iteration count.
+//! - `EXPECT ...`: Checks the result of the last `READ`, where `...` is one of:
+//!   - `0x<hex>`: Received bytes, in full.
+//!   - `TIMEOUT`: Nothing received within the deadline.
+//!   - `ACK`, `HANDSHAKE`, `HEARTBEAT`, `QUIT`: Decoded opcode.
+//!   - `SENSOR [<reading>...] [WITHIN <tolerance>]`: Decoded opcode and, if given, readings.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use log::info;
+
+use crate::color;
+use crate::communicator::{Communicator, Outcome};
+use crate::format::Format;
+use crate::response::{to_hex, Decoded, Response};
+use crate::shutdown::Interrupt;
+use crate::Request;
+
+/// Error from parsing or running a script, pointing at the offending line.
+#[derive(Debug)]
+pub struct ScriptError {
+    /// 1-based line number.
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScriptErrorKind {
+    /// Script is malformed, detected before anything is run.
+    Syntax,
+    /// A statement could not be run, e.g. malformed after substitution or I/O failure.
+    Runtime,
+    /// An `EXPECT` did not hold.
+    Expectation,
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Stmt {
+    /// `READ`/`WRITE` line, parsed after substitution.
+    Request(String),
+    Sleep(String),
+    Set(String, String),
+    Repeat(String, Vec<(usize, Stmt)>),
+    Expect(String),
+}
+
+/// A parsed script, ready to run.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Script {
+    stmts: Vec<(usize, Stmt)>,
+}
+
+fn _syntax_error(line: usize, message: String) -> ScriptError {
+    ScriptError { line, kind: ScriptErrorKind::Syntax, message }
+}
+
+fn _runtime_error(line: usize, message: String) -> ScriptError {
+    ScriptError { line, kind: ScriptErrorKind::Runtime, message }
+}
+
+/// `line` up to its comment, if any, as laid out in the grammar above.
+fn _strip_comment(line: &str) -> &str {
+    for (i, _) in line.match_indices('#') {
+        let before = &line[..i];
+        if !before.chars().next_back().is_none_or(char::is_whitespace) { continue; }
+        let word = line[i..].split_ascii_whitespace().next().unwrap_or_default();
+        if before.trim().is_empty() || color::parse(word).is_err() { return before; }
+    }
+    return line;
+}
+
+impl Script {
+    /// Parses `src` into a `Script`.
+    ///
+    /// # Errors
+    /// `ScriptError` of kind `ScriptErrorKind::Syntax` at the first malformed line.
+    pub fn parse(src: &str) -> Result<Self, ScriptError> {
+        let mut lines = src.lines().enumerate().map(|(i, l)| (i + 1, l));
+        let stmts = Self::_parse_block(&mut lines, None)?;
+        return Ok(Self { stmts });
+    }
+
+    fn _parse_block<'a>(
+        lines: &mut dyn Iterator<Item = (usize, &'a str)>,
+        opened_at: Option<usize>,
+    ) -> Result<Vec<(usize, Stmt)>, ScriptError> {
+        let mut stmts = Vec::new();
+        while let Some((n, line)) = lines.next() {
+            let line = _strip_comment(line).trim();
+            let mut words = line.split_ascii_whitespace();
+            let stmt = match words.next() {
+                None => continue,
+                Some("}") if opened_at.is_some() => return Ok(stmts),
+                Some("}") => return Err(_syntax_error(n, String::from("Unmatched `}`"))),
+                Some("READ" | "WRITE" | "STATE" | "MOVE" | "ANIMATE" | "STOP") => {
+                    // Lines with variables can only be checked once substituted
+                    if !line.contains('$') {
+                        Request::try_from(line).map_err(|e| _syntax_error(n, e.to_string()))?;
+                    }
+                    Stmt::Request(line.to_owned())
+                },
+                Some("SLEEP") => match (words.next(), words.next()) {
+                    (Some(ms), None) => Stmt::Sleep(ms.to_owned()),
+                    _ => return Err(_syntax_error(n, String::from("Expected `SLEEP <ms>`"))),
+                },
+                Some("SET") => match line["SET".len()..].trim().split_once(char::is_whitespace) {
+                    Some((name, value)) => Stmt::Set(name.to_owned(), value.trim().to_owned()),
+                    None => return Err(_syntax_error(n, String::from("Expected `SET <name> <value>`"))),
+                },
+                Some("REPEAT") => match (words.next(), words.next(), words.next()) {
+                    (Some(count), Some("{"), None) =>
+                        Stmt::Repeat(count.to_owned(), Self::_parse_block(lines, Some(n))?),
+                    _ => return Err(_syntax_error(n, String::from("Expected `REPEAT <n> {`"))),
+                },
+                Some("EXPECT") => Stmt::Expect(line["EXPECT".len()..].trim().to_owned()),
+                Some(s) => return Err(_syntax_error(n, format!("Undefined statement {s}"))),
+            };
+            stmts.push((n, stmt));
+        }
+        match opened_at {
+            Some(n) => Err(_syntax_error(n, String::from("Unclosed `REPEAT` block"))),
+            None    => Ok(stmts),
+        }
+    }
+}
+
+/// Runs `Script`s against a `Communicator`, writing READ results to `out` in `format`.
+pub struct Runner<'a> {
+    comm: &'a mut Communicator,
+    format: Format,
+    out: &'a mut dyn Write,
+    vars: HashMap<String, String>,
+    last_read: Option<io::Result<Response>>,
+    interrupt: Interrupt,
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(comm: &'a mut Communicator, format: Format, out: &'a mut dyn Write) -> Self {
+        Self { comm, format, out, vars: HashMap::new(), last_read: None, interrupt: Interrupt::default() }
+    }
+
+    /// Stops the script before its next statement once `interrupt` is set.
+    #[must_use]
+    pub fn with_interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Runs `script` to completion.
+    ///
+    /// # Errors
+    /// `ScriptError` at the first line that failed to run or whose expectation did not hold.
+    pub fn run(&mut self, script: &Script) -> Result<(), ScriptError> {
+        self._run_block(&script.stmts)
+    }
+
+    fn _substitute(&self, line: usize, s: &str) -> Result<String, ScriptError> {
+        let mut res = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(idx) = rest.find('$') {
+            res.push_str(&rest[..idx]);
+            rest = &rest[idx + 1..];
+            let (name, tail) = if let Some(braced) = rest.strip_prefix('{') {
+                let end = braced.find('}')
+                    .ok_or_else(|| _runtime_error(line, String::from("Unclosed `${`")))?;
+                (&braced[..end], &braced[end + 1..])
+            } else {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+            let value = self.vars.get(name)
+                .ok_or_else(|| _runtime_error(line, format!("Undefined variable {name}")))?;
+            res.push_str(value);
+            rest = tail;
+        }
+        res.push_str(rest);
+        return Ok(res);
+    }
+
+    fn _run_block(&mut self, stmts: &[(usize, Stmt)]) -> Result<(), ScriptError> {
+        for (line, stmt) in stmts {
+            let line = *line;
+            if let Some(sig) = self.interrupt.signal() {
+                return Err(_runtime_error(line, format!("Interrupted by signal {sig}")));
+            }
+            self.comm.tick().map_err(|e| _runtime_error(line, format!("Heartbeat failed: {e}")))?;
+            match stmt {
+                Stmt::Request(s) => {
+                    let s = self._substitute(line, s)?;
+                    let req = Request::try_from(s.as_str())
+                        .map_err(|e| _runtime_error(line, e.to_string()))?;
+                    self._run_request(line, &req)?;
+                },
+                Stmt::Sleep(ms) => {
+                    let ms = self._substitute(line, ms)?;
+                    let ms = ms.parse::<u64>()
+                        .map_err(|e| _runtime_error(line, format!("Invalid SLEEP duration {ms}: {e}")))?;
+                    self.comm.idle_for(Duration::from_millis(ms))
+                        .map_err(|e| _runtime_error(line, format!("Heartbeat failed: {e}")))?;
+                },
+                Stmt::Set(name, value) => {
+                    let value = self._substitute(line, value)?;
+                    self.vars.insert(name.clone(), value);
+                },
+                Stmt::Repeat(count, body) => {
+                    let count = self._substitute(line, count)?;
+                    let count = count.parse::<u64>()
+                        .map_err(|e| _runtime_error(line, format!("Invalid REPEAT count {count}: {e}")))?;
+                    for i in 0..count {
+                        self.vars.insert(String::from("ITER"), i.to_string());
+                        self._run_block(body)?;
+                    }
+                },
+                Stmt::Expect(expected) => {
+                    let expected = self._substitute(line, expected)?;
+                    self._check_expectation(line, &expected)?;
+                },
+            }
+        }
+        return Ok(());
+    }
+
+    fn _run_request(&mut self, line: usize, req: &Request) -> Result<(), ScriptError> {
+        const _FN_NAME: &str = "[script::Runner::run_request]";
+
+        match req {
+            Request::Read(spec) => {
+                let res = match self.comm.execute(&Request::Read(*spec)) {
+                    Ok(Outcome::Received(r)) => Ok(r),
+                    Ok(Outcome::Written(_) | Outcome::State(_)) => unreachable!(),
+                    Err(e) => Err(e),
+                };
+                match &res {
+                    Ok(r) => self.format.write_response(self.out, r)
+                        .map_err(|e| _runtime_error(line, format!("Cannot write response: {e}")))?,
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut =>
+                        info!("{_FN_NAME} READ at line {line} timed out"),
+                    Err(e) => return Err(_runtime_error(line, format!("Cannot read: {e}"))),
+                }
+                self.last_read = Some(res);
+            },
+            Request::Write(_) | Request::Move(_) | Request::Animate(_) | Request::Stop => {
+                self.comm.execute(req)
+                    .map_err(|e| _runtime_error(line, format!("Cannot write: {e}")))?;
+            },
+            Request::State => {
+                let state = self.comm.poll_board_state()
+                    .cloned()
+                    .map_err(|e| _runtime_error(line, format!("Cannot read state: {e}")))?;
+                self.format.write_state(self.out, self.comm.device(), &state)
+                    .map_err(|e| _runtime_error(line, format!("Cannot write state: {e}")))?;
+            },
+        }
+        return Ok(());
+    }
+
+    fn _check_expectation(&self, line: usize, expected: &str) -> Result<(), ScriptError> {
+        let fail = |message: String| Err(ScriptError {
+            line, kind: ScriptErrorKind::Expectation, message
+        });
+        let res = match &self.last_read {
+            None => return fail(String::from("EXPECT without a preceding READ")),
+            Some(Err(_)) if expected == "TIMEOUT" => return Ok(()),
+            Some(Err(e)) => return fail(format!("Expected {expected}, got {e}")),
+            Some(Ok(_)) if expected == "TIMEOUT" => return fail(String::from("Expected TIMEOUT, got reply")),
+            Some(Ok(r)) => r,
+        };
+
+        /* 1. Raw bytes */
+        if let Some(hex) = expected.strip_prefix("0x") {
+            let hex = hex.to_ascii_lowercase();
+            let got = to_hex(&res.raw);
+            if hex == got { return Ok(()); }
+            return fail(format!("Expected 0x{hex}, got 0x{got}"));
+        }
+
+        /* 2. Decoded fields */
+        let mut words = expected.split_ascii_whitespace();
+        match (words.next(), &res.decoded) {
+            (Some("ACK"), Decoded::Ack)
+            | (Some("HANDSHAKE"), Decoded::Handshake)
+            | (Some("HEARTBEAT"), Decoded::Heartbeat)
+            | (Some("QUIT"), Decoded::Quit) => Ok(()),
+            (Some("SENSOR"), Decoded::Sensor { readings }) => {
+                let mut want: Vec<f32> = Vec::new();
+                let mut tolerance = 0.0_f32;
+                while let Some(w) = words.next() {
+                    let parsed = if w == "WITHIN" {
+                        words.next().map(str::parse::<f32>).map(|t| t.map(|t| tolerance = t))
+                    } else {
+                        Some(w.parse::<f32>().map(|v| want.push(v)))
+                    };
+                    if !matches!(parsed, Some(Ok(()))) {
+                        return Err(_runtime_error(line, format!("Malformed EXPECT SENSOR argument {w}")));
+                    }
+                }
+                let matched = want.is_empty() || (
+                    want.len() == readings.len()
+                    && want.iter().zip(readings).all(|(w, r)| (w - r).abs() <= tolerance)
+                );
+                if matched { return Ok(()); }
+                fail(format!("Expected {expected}, got readings {readings:?}"))
+            },
+            (Some("ACK" | "HANDSHAKE" | "HEARTBEAT" | "QUIT" | "SENSOR"), d) =>
+                fail(format!("Expected {expected}, got {d:?} (0x{})", to_hex(&res.raw))),
+            _ => Err(_runtime_error(line, format!("Malformed EXPECT {expected}"))),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Rhai scripting engine with a communicator API, for test sequences that outgrow the line-based
+//! grammar of `script`. Enabled with the `rhai` feature.
+//!
+//! ## API
+//! - `send(line)`: Runs a `READ ...`/`WRITE ...`/`STATE`/`MOVE ...`/`ANIMATE ...`/`STOP` text
+//!   request. Returns the response for `READ` and the board state for `STATE`. Animations play
+//!   while the script sleeps.
+//! - `send_bytes(blob)`: Writes raw bytes.
+//! - `read()`, `read(len)`, `read_timeout(ms)`: Reads a response, or `()` on time-out.
+//! - `magnet(cells)`: Writes `MAGNET`, where each cell is `#{x, y, on}` or `[x, y, on]`.
+//! - `led(colors)`: Writes `LED`, where each colour is an `0xRRGGBB` integer or a string in any
+//!   form of `color::parse`, e.g. `"#ff8000"` or `"hsv(30, 1, 1)"`.
+//! - `sensor()`: Writes `SENSOR` and returns the decoded readings, or `()` on time-out.
+//! - `state()`: Returns the host-side board state, as `#{magnets, leds, synced}` with magnet
+//!   cells as for `magnet`.
+//! - `sleep(ms)`: Sleeps for `ms` milliseconds, sending heartbeats and animation frames meanwhile.
+//! - `print(msg)`, `debug(msg)`, `warn(msg)`, `error(msg)`: Logs `msg`.
+//!
+//! Responses are object maps of form `#{device, timestamp, raw, hex, kind, readings}`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Map, Position, FLOAT, INT};
+
+use crate::board::BoardState;
+use crate::color;
+use crate::command::{Command, MagnetCell};
+use crate::communicator::{Communicator, Outcome};
+use crate::response::{to_hex, Decoded, Response};
+use crate::shutdown::Interrupt;
+use crate::{ReadMode, ReadSpec, Request};
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn _runtime_error(message: String) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorRuntime(message.into(), Position::NONE))
+}
+
+fn _response_to_map(res: Response) -> Map {
+    let mut map = Map::new();
+    let (kind, readings): (&str, Array) = match &res.decoded {
+        Decoded::Sensor { readings } => (
+            "SENSOR",
+            readings.iter().map(|r| Dynamic::from_float(FLOAT::from(*r))).collect(),
+        ),
+        Decoded::State { .. } => ("STATE", Array::new()),
+        Decoded::Handshake => ("HANDSHAKE", Array::new()),
+        Decoded::Heartbeat => ("HEARTBEAT", Array::new()),
+        Decoded::Ack       => ("ACK", Array::new()),
+        Decoded::Quit      => ("QUIT", Array::new()),
+        Decoded::Unknown   => ("UNKNOWN", Array::new()),
+    };
+    map.insert("device".into(), res.device.clone().into());
+    map.insert("timestamp".into(), Dynamic::from_int(INT::try_from(res.timestamp).unwrap_or(INT::MAX)));
+    map.insert("hex".into(), to_hex(&res.raw).into());
+    map.insert("kind".into(), kind.into());
+    map.insert("readings".into(), readings.into());
+    map.insert("raw".into(), Dynamic::from_blob(res.raw));
+    return map;
+}
+
+fn _state_to_map(state: &BoardState) -> Map {
+    let magnets: Array = state.magnets
+        .iter()
+        .map(|m| {
+            let mut cell = Map::new();
+            cell.insert("x".into(), Dynamic::from_float(FLOAT::from(m.x)));
+            cell.insert("y".into(), Dynamic::from_float(FLOAT::from(m.y)));
+            cell.insert("on".into(), m.on.into());
+            Dynamic::from_map(cell)
+        })
+        .collect();
+    let leds: Array = state.leds.iter().map(|c| Dynamic::from_int(INT::from(*c))).collect();
+    let mut map = Map::new();
+    map.insert("magnets".into(), magnets.into());
+    map.insert("leds".into(), leds.into());
+    map.insert("synced".into(), state.synced.into());
+    return map;
+}
+
+fn _read(comm: &RefCell<Communicator>, spec: &ReadSpec) -> RhaiResult<Dynamic> {
+    match comm.borrow_mut().execute(&Request::Read(*spec)) {
+        Ok(Outcome::Received(res)) => Ok(_response_to_map(res).into()),
+        Ok(Outcome::Written(_) | Outcome::State(_)) => unreachable!(),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(Dynamic::UNIT),
+        Err(e) => Err(_runtime_error(format!("Cannot read: {e}"))),
+    }
+}
+
+fn _state(comm: &RefCell<Communicator>) -> RhaiResult<Map> {
+    comm.borrow_mut()
+        .poll_board_state()
+        .map(_state_to_map)
+        .map_err(|e| _runtime_error(format!("Cannot read state: {e}")))
+}
+
+fn _write(comm: &RefCell<Communicator>, instr: &[u8]) -> RhaiResult<()> {
+    comm.borrow_mut()
+        .execute(&Request::Write(instr.to_vec()))
+        .map(|_| ())
+        .map_err(|e| _runtime_error(format!("Cannot write: {e}")))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn _to_f32(v: &Dynamic) -> RhaiResult<f32> {
+    v.as_float()
+        .or_else(|_| v.as_int().map(|i| i as FLOAT))
+        .map(|f| f as f32)
+        .map_err(|t| _runtime_error(format!("Expected number, got {t}")))
+}
+
+fn _to_magnet_cell(v: &Dynamic) -> RhaiResult<MagnetCell> {
+    let (x, y, on) = if let Some(map) = v.read_lock::<Map>() {
+        let get = |k: &str| map.get(k).cloned()
+            .ok_or_else(|| _runtime_error(format!("Magnet cell missing `{k}`")));
+        (get("x")?, get("y")?, get("on")?)
+    } else if let Some(arr) = v.read_lock::<Array>() {
+        match arr.as_slice() {
+            [x, y, on] => (x.clone(), y.clone(), on.clone()),
+            _ => return Err(_runtime_error(String::from("Expected magnet cell as [x, y, on]"))),
+        }
+    } else {
+        return Err(_runtime_error(format!("Expected magnet cell, got {}", v.type_name())));
+    };
+    Ok(MagnetCell {
+        x: _to_f32(&x)?,
+        y: _to_f32(&y)?,
+        on: on.as_bool().map_err(|t| _runtime_error(format!("Expected bool, got {t}")))?,
+    })
+}
+
+/// Builds a Rhai `Engine` with the communicator API bound to `comm`.
+#[must_use]
+pub fn engine(comm: Rc<RefCell<Communicator>>) -> Engine {
+    let mut engine = Engine::new();
+
+    engine.on_print(|s| info!("[rhai] {s}"));
+    engine.on_debug(|s, _, pos| debug!("[rhai] {pos:?} {s}"));
+    engine.register_fn("warn", |s: &str| warn!("[rhai] {s}"));
+    engine.register_fn("error", |s: &str| error!("[rhai] {s}"));
+    let c = comm.clone();
+    engine.register_fn("sleep", move |ms: INT| -> RhaiResult<()> {
+        c.borrow_mut()
+            .idle_for(Duration::from_millis(ms.try_into().unwrap_or(0)))
+            .map_err(|e| _runtime_error(format!("Heartbeat failed: {e}")))
+    });
+
+    let c = comm.clone();
+    engine.register_fn("send", move |line: &str| -> RhaiResult<Dynamic> {
+        let req = Request::try_from(line).map_err(|e| _runtime_error(e.to_string()))?;
+        match req {
+            Request::Read(spec) => _read(&c, &spec),
+            Request::Write(v)   => _write(&c, &v).map(|()| Dynamic::UNIT),
+            Request::State      => _state(&c).map(Dynamic::from),
+            Request::Move(t)    => c
+                .borrow_mut()
+                .run_trajectory(&t)
+                .map(|_| Dynamic::UNIT)
+                .map_err(|e| _runtime_error(format!("Cannot move: {e}"))),
+            Request::Animate(_) | Request::Stop => c
+                .borrow_mut()
+                .execute(&req)
+                .map(|_| Dynamic::UNIT)
+                .map_err(|e| _runtime_error(format!("Cannot animate: {e}"))),
+        }
+    });
+    let c = comm.clone();
+    engine.register_fn("state", move || _state(&c));
+    let c = comm.clone();
+    engine.register_fn("send_bytes", move |bytes: Blob| _write(&c, &bytes));
+
+    let c = comm.clone();
+    engine.register_fn("read", move || _read(&c, &ReadSpec::default()));
+    let c = comm.clone();
+    engine.register_fn("read", move |len: INT| -> RhaiResult<Dynamic> {
+        let len = usize::try_from(len).map_err(|e| _runtime_error(e.to_string()))?;
+        _read(&c, &ReadSpec { mode: ReadMode::Exact(len), timeout: None })
+    });
+    let c = comm.clone();
+    engine.register_fn("read_timeout", move |ms: INT| -> RhaiResult<Dynamic> {
+        let ms = u64::try_from(ms).map_err(|e| _runtime_error(e.to_string()))?;
+        _read(&c, &ReadSpec { mode: ReadMode::Available, timeout: Some(Duration::from_millis(ms)) })
+    });
+
+    let c = comm.clone();
+    engine.register_fn("magnet", move |cells: Array| -> RhaiResult<()> {
+        let cells = cells.iter().map(_to_magnet_cell).collect::<RhaiResult<Vec<_>>>()?;
+        _write(&c, &Command::Magnet { cells }.encode())
+    });
+    let c = comm.clone();
+    engine.register_fn("led", move |colors: Array| -> RhaiResult<()> {
+        let colors = colors
+            .iter()
+            .map(|v| match v.clone().into_immutable_string() {
+                Ok(s) => color::parse(&s).map_err(|e| _runtime_error(e.to_string())),
+                Err(_) => v.as_int()
+                    .ok()
+                    .and_then(|i| u32::try_from(i).ok())
+                    .filter(|rgb| *rgb <= color::MAX)
+                    .ok_or_else(|| _runtime_error(format!("Expected colour as 0xRRGGBB, got {v}"))),
+            })
+            .collect::<RhaiResult<Vec<_>>>()?;
+        _write(&c, &Command::Led { colors }.encode())
+    });
+    let c = comm;
+    engine.register_fn("sensor", move || -> RhaiResult<Dynamic> {
+        let mut comm = c.borrow_mut();
+        if let Err(e) = comm.execute(&Request::from(&Command::Sensor)) {
+            return Err(_runtime_error(format!("Cannot write: {e}")));
+        }
+        match comm.execute(&Request::Read(ReadSpec::default())) {
+            Ok(Outcome::Received(Response { decoded: Decoded::Sensor { readings }, .. })) =>
+                Ok(readings.into_iter().map(|r| Dynamic::from_float(FLOAT::from(r))).collect::<Array>().into()),
+            Ok(Outcome::Received(res)) =>
+                Err(_runtime_error(format!("Expected SENSOR reply, got 0x{}", to_hex(&res.raw)))),
+            Ok(Outcome::Written(_) | Outcome::State(_)) => unreachable!(),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(Dynamic::UNIT),
+            Err(e) => Err(_runtime_error(format!("Cannot read: {e}"))),
+        }
+    });
+
+    return engine;
+}
+
+/// Runs the Rhai script `src` against `comm`, terminating it once `interrupt` is set.
+///
+/// # Errors
+/// `EvalAltResult` on syntax errors, runtime errors, errors raised by the communicator API, or
+/// termination by `interrupt`.
+pub fn run(comm: &Rc<RefCell<Communicator>>, src: &str, interrupt: &Interrupt) -> RhaiResult<()> {
+    let mut engine = engine(comm.clone());
+    let interrupt = interrupt.clone();
+    engine.on_progress(move |_| interrupt.signal().map(|sig| format!("Interrupted by signal {sig}").into()));
+    engine.run(src)
+}
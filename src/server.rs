@@ -0,0 +1,271 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Local HTTP/WebSocket API for browser dashboards. Enabled with the `http` feature.
+//!
+//! ## Endpoints
+//! - `GET /`: Dashboard page.
+//! - `POST /requests`: Runs a JSON-encoded `TypedRequest`, e.g. `{"op":"WRITE","cmd":"SENSOR"}`,
+//!   or `{"op":"ANIMATE",...}` and `{"op":"STOP"}` to start and stop LED animations, which play
+//!   in between requests.
+//! - `GET /responses`: Most recently received `Response`s, oldest first.
+//! - `GET /state`: Host-side `BoardState`, as also returned for `{"op":"STATE"}`.
+//! - `GET /events`: WebSocket stream of traffic `Event`s and `LinkEvent`s, one JSON object per
+//!   text message.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde::Serialize;
+use tiny_http::{Header, Method, Server, StatusCode};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::command::{Command, TypedRequest};
+use crate::communicator::{Communicator, Outcome};
+use crate::response::{serialize_hex, timestamp_now, Response};
+use crate::shutdown::Interrupt;
+use crate::Request;
+
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+const RESPONSE_HISTORY_LEN: usize = 64;
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Shortest wait for requests while an animation frame is overdue.
+const ANIMATION_MIN_POLL: Duration = Duration::from_millis(1);
+
+/// Traffic on the serial link, as pushed to `/events` subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Written {
+        device: String,
+        timestamp: u64,
+        #[serde(serialize_with = "serialize_hex")]
+        raw: Vec<u8>,
+        command: Option<Command>,
+    },
+    Received(Response),
+    Error {
+        device: String,
+        timestamp: u64,
+        message: String,
+    },
+}
+
+struct State {
+    comm: Mutex<Communicator>,
+    history: Mutex<VecDeque<Response>>,
+    subscribers: Mutex<Vec<Sender<String>>>,
+}
+
+impl State {
+    fn broadcast(&self, event: &impl Serialize) {
+        const _FN_NAME: &str = "[server::State::broadcast]";
+
+        let msg = match serde_json::to_string(event) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("{_FN_NAME} Cannot serialize event: \n{:#?}", e);
+                return;
+            }
+        };
+        // Drop subscribers whose socket thread has exited
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(msg.clone()).is_ok());
+    }
+
+    /// Sends a heartbeat or animation frame if due, broadcasting any resulting link events.
+    fn tick(&self) {
+        const _FN_NAME: &str = "[server::State::tick]";
+
+        let mut comm = self.comm.lock().unwrap();
+        if let Err(e) = comm.tick() {
+            error!("{_FN_NAME} Cannot send heartbeat: {e}");
+        }
+        let events = comm.take_events();
+        drop(comm);
+        for event in events {
+            self.broadcast(&event);
+        }
+    }
+
+    fn run(&self, req: &TypedRequest, request: &Request) -> io::Result<Outcome> {
+        let mut comm = self.comm.lock().unwrap();
+        let res = comm.execute(request);
+        let device = comm.device().to_owned();
+        let events = comm.take_events();
+        drop(comm);
+        for event in events {
+            self.broadcast(&event);
+        }
+        match res {
+            Ok(Outcome::Written(raw)) => {
+                let command = match req {
+                    TypedRequest::Write(c)    => Some(c.clone()),
+                    TypedRequest::Read { .. } | TypedRequest::State | TypedRequest::Move(_) |
+                    TypedRequest::Animate(_) | TypedRequest::Stop => None,
+                };
+                self.broadcast(&Event::Written {
+                    device, timestamp: timestamp_now(), raw: raw.clone(), command,
+                });
+                Ok(Outcome::Written(raw))
+            },
+            Ok(Outcome::Received(res)) => {
+                let mut history = self.history.lock().unwrap();
+                if history.len() == RESPONSE_HISTORY_LEN { history.pop_front(); }
+                history.push_back(res.clone());
+                drop(history);
+                self.broadcast(&Event::Received(res.clone()));
+                Ok(Outcome::Received(res))
+            },
+            Ok(Outcome::State(s)) => Ok(Outcome::State(s)),
+            Err(e) => {
+                self.broadcast(&Event::Error {
+                    device, timestamp: timestamp_now(), message: e.to_string(),
+                });
+                Err(e)
+            },
+        }
+    }
+}
+
+fn _json_response(status: u16, body: String) -> tiny_http::Response<io::Cursor<Vec<u8>>> {
+    tiny_http::Response::from_string(body)
+        .with_status_code(StatusCode(status))
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn _error_response(status: u16, message: &str) -> tiny_http::Response<io::Cursor<Vec<u8>>> {
+    _json_response(status, serde_json::json!({ "error": message }).to_string())
+}
+
+fn _handle_post_request(state: &State, req: &mut tiny_http::Request) -> tiny_http::Response<io::Cursor<Vec<u8>>> {
+    let mut body = String::new();
+    if let Err(e) = req.as_reader().read_to_string(&mut body) {
+        return _error_response(400, &e.to_string());
+    }
+    let typed_req: TypedRequest = match serde_json::from_str(&body) {
+        Ok(r) => r,
+        Err(e) => return _error_response(400, &e.to_string()),
+    };
+    let request = match Request::try_from(&typed_req) {
+        Ok(r) => r,
+        Err(e) => return _error_response(400, &e.to_string()),
+    };
+
+    match state.run(&typed_req, &request) {
+        Ok(Outcome::Written(raw)) => _json_response(
+            200,
+            serde_json::json!({ "written": crate::response::to_hex(&raw) }).to_string(),
+        ),
+        Ok(Outcome::Received(res)) => _json_response(
+            200,
+            serde_json::to_string(&res).unwrap_or_default(),
+        ),
+        Ok(Outcome::State(s)) => _json_response(200, serde_json::to_string(&s).unwrap_or_default()),
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => _error_response(504, &e.to_string()),
+        Err(e) => _error_response(502, &e.to_string()),
+    }
+}
+
+fn _handle_websocket(state: &State, req: tiny_http::Request) {
+    const _FN_NAME: &str = "[server::handle_websocket]";
+
+    let key = req.headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| h.value.to_string());
+    let Some(key) = key else {
+        let _ = req.respond(_error_response(400, "Expected WebSocket upgrade"));
+        return;
+    };
+
+    let upgrade_res = tiny_http::Response::empty(StatusCode(101))
+        .with_header(Header::from_bytes("Upgrade", "websocket").unwrap())
+        .with_header(Header::from_bytes("Connection", "Upgrade").unwrap())
+        .with_header(
+            Header::from_bytes("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes())).unwrap()
+        );
+    let stream = req.upgrade("websocket", upgrade_res);
+
+    let (tx, rx) = mpsc::channel::<String>();
+    state.subscribers.lock().unwrap().push(tx);
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        for msg in rx {
+            if let Err(e) = socket.send(Message::text(msg)) {
+                info!("{_FN_NAME} WebSocket subscriber disconnected: {e}");
+                return;
+            }
+        }
+    });
+}
+
+/// Serves the HTTP/WebSocket API on `addr`, running requests against `comm`, until `interrupt` is
+/// set. Returns `comm` back once interrupted.
+///
+/// # Errors
+/// Any error from binding to `addr` or accepting connections.
+pub fn serve<A: ToSocketAddrs>(
+    comm: Communicator,
+    addr: A,
+    interrupt: &Interrupt,
+) -> Result<Communicator, Box<dyn Error + Send + Sync>> {
+    const _FN_NAME: &str = "[server::serve]";
+
+    let server = Server::http(addr)?;
+    info!("{_FN_NAME} Listening on {}", server.server_addr());
+    let state = State {
+        comm: Mutex::new(comm),
+        history: Mutex::new(VecDeque::with_capacity(RESPONSE_HISTORY_LEN)),
+        subscribers: Mutex::new(Vec::new()),
+    };
+
+    while !interrupt.is_set() {
+        // => Woken up in time for animation frames, if any
+        let timeout = state.comm.lock().unwrap().next_frame_in()
+            .map_or(IDLE_POLL_INTERVAL, |d| d.clamp(ANIMATION_MIN_POLL, IDLE_POLL_INTERVAL));
+        let Some(mut req) = server.recv_timeout(timeout)? else {
+            state.tick();
+            continue;
+        };
+        let res = match (req.method(), req.url()) {
+            (Method::Get, "/") => tiny_http::Response::from_string(DASHBOARD_HTML)
+                .with_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap()),
+            (Method::Get, "/events") => {
+                _handle_websocket(&state, req);
+                continue;
+            },
+            (Method::Get, "/responses") => {
+                let history = state.history.lock().unwrap();
+                _json_response(200, serde_json::to_string(&*history).unwrap_or_default())
+            },
+            (Method::Get, "/state") => {
+                let mut comm = state.comm.lock().unwrap();
+                match comm.poll_board_state() {
+                    Ok(board) => _json_response(200, serde_json::to_string(board).unwrap_or_default()),
+                    Err(e) => _error_response(500, &e.to_string()),
+                }
+            },
+            (Method::Post, "/requests") => _handle_post_request(&state, &mut req),
+            (m, url) => {
+                warn!("{_FN_NAME} No route for {m} {url}");
+                _error_response(404, "Not found")
+            },
+        };
+        if let Err(e) = req.respond(res) {
+            error!("{_FN_NAME} Cannot respond to HTTP client: \n{:#?}", e);
+        }
+    }
+    info!("{_FN_NAME} Interrupted, shutting down");
+    return Ok(state.comm.into_inner().unwrap());
+}
@@ -0,0 +1,114 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Graceful shutdown: putting the hardware into a safe state before exit, and catching
+//! `SIGINT`/`SIGTERM` so that there is a chance to.
+//!
+//! A `Communicator` given a `SafeState` runs it when dropped, i.e. on return or panic, unless
+//! `Communicator::enter_safe_state` already did.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+
+use crate::command::Command;
+use crate::Instruction;
+
+/// Exit codes of the binary.
+pub mod exit_code {
+    pub const OK: i32 = 0;
+    /// Failed script, or unexpected error while running.
+    pub const FAILURE: i32 = 1;
+    /// Malformed script or arguments.
+    pub const USAGE: i32 = 2;
+    /// Safe-state sequence was not acknowledged by the Arduino.
+    pub const UNSAFE: i32 = 3;
+    /// Interrupted by signal `n` exits with `SIGNAL_BASE + n`, as shells do.
+    pub const SIGNAL_BASE: i32 = 128;
+}
+
+/// Instructions putting the Arduino into a safe state, each of which must be acknowledged.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SafeState {
+    pub sequence: Vec<Instruction>,
+    /// How long to wait for each `ACK`.
+    pub ack_timeout: Duration,
+}
+
+impl Default for SafeState {
+    /// All magnets off, all LEDs off, then `QUIT`.
+    fn default() -> Self {
+        Self {
+            sequence: vec![
+                Command::Magnet { cells: Vec::new() }.encode(),
+                Command::Led { colors: Vec::new() }.encode(),
+                Command::Quit.encode(),
+            ],
+            ack_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Set once `SIGINT` or `SIGTERM` is received. Long-running loops should poll it and return.
+///
+/// A second signal terminates the process at once, for when the hardware does not respond.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    signal: Arc<AtomicUsize>,
+}
+
+impl Interrupt {
+    /// Installs handlers for `SIGINT` and `SIGTERM`.
+    ///
+    /// # Errors
+    /// Any `io::Error` from registering the handlers.
+    pub fn install() -> io::Result<Self> {
+        Self::_install(&[SIGINT, SIGTERM])
+    }
+
+    /// Installs a handler for `SIGTERM` only, leaving `SIGINT` to interactive modes that take
+    /// Ctrl-C as input.
+    ///
+    /// # Errors
+    /// Any `io::Error` from registering the handler.
+    pub fn install_terminate() -> io::Result<Self> {
+        Self::_install(&[SIGTERM])
+    }
+
+    fn _install(signals: &[i32]) -> io::Result<Self> {
+        let interrupt = Self::default();
+        let received = Arc::new(AtomicBool::new(false));
+        for &sig in signals {
+            // Order matters: only exits if a previous signal already set `received`
+            flag::register_conditional_shutdown(sig, exit_code::SIGNAL_BASE + sig, received.clone())?;
+            flag::register(sig, received.clone())?;
+            #[allow(clippy::cast_sign_loss)]
+            flag::register_usize(sig, interrupt.signal.clone(), sig as usize)?;
+        }
+        return Ok(interrupt);
+    }
+
+    /// Number of the signal received, if any.
+    #[must_use]
+    pub fn signal(&self) -> Option<i32> {
+        match self.signal.load(Ordering::Relaxed) {
+            0 => None,
+            #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+            sig => Some(sig as i32),
+        }
+    }
+
+    #[must_use]
+    pub fn is_set(&self) -> bool {
+        self.signal().is_some()
+    }
+
+    /// Exit code reporting the signal received, if any.
+    #[must_use]
+    pub fn exit_code(&self) -> Option<i32> {
+        self.signal().map(|sig| exit_code::SIGNAL_BASE + sig)
+    }
+}
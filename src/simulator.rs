@@ -0,0 +1,216 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Simulated Arduino firmware on a pseudo-TTY pair, for running without hardware attached.
+//!
+//! Like the firmware, the simulator arms a watchdog on the first `HEARTBEAT` it receives. Once
+//! armed, if no instruction arrives within `WATCHDOG_TIMEOUT`, it turns all magnets and LEDs off
+//! and disarms until the next `HEARTBEAT`. `QUIT` also disarms it.
+//!
+//! After `STREAM <interval>`, the simulator pushes its `SENSOR` reply every interval as a `u8`
+//! length-prefixed frame, until `STREAM 0` or `QUIT`. `STATE` is answered with the current magnet
+//! cells and LED colours, as per `board::BoardState::encode_reply`.
+//!
+//! `MAGNET` and `LED` frames split as per `chunk` are joined back, i.e. those with
+//! `opcode::CONTINUED` set are appended to by the next frame of the same transfer.
+
+use std::io::{self, Read, Write};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serialport::{SerialPort, TTYPort};
+
+use crate::{bindings, opcode};
+use crate::chunk;
+use crate::board::{apply_delta, decode_cells, decode_colors, decode_led_delta, decode_magnet_delta, BoardState};
+use crate::command::MagnetCell;
+
+/// Name the simulated device is reported as.
+pub const DEVICE_NAME: &str = "simulator";
+
+/// Gap in incoming bytes after which the simulator takes what it has received as one instruction.
+const INSTRUCTION_GAP: Duration = Duration::from_millis(10);
+/// Silence after which an armed watchdog puts the simulator into a safe state.
+pub const WATCHDOG_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Firmware-side state of the simulated Arduino.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Firmware {
+    pub magnets: Vec<MagnetCell>,
+    pub leds: Vec<u32>,
+    /// Interval of pushed `SENSOR` readings, if streaming.
+    pub stream_interval: Option<Duration>,
+    /// Size of the receive buffer, if longer frames are to be dropped.
+    pub max_frame_len: Option<usize>,
+    /// Opcode of the transfer continued by the next frame, if any.
+    pub continued: Option<u8>,
+}
+
+impl Firmware {
+    /// Readings reported in reply to `SENSOR`, i.e. number of active magnets, then the mean `x`
+    /// and `y` of active magnets (0 if none).
+    #[must_use]
+    pub fn sensor_readings(&self) -> [f32; 3] {
+        let active: Vec<&MagnetCell> = self.magnets.iter().filter(|m| m.on).collect();
+        if active.is_empty() { return [0.0; 3]; }
+        #[allow(clippy::cast_precision_loss)]
+        let n = active.len() as f32;
+        let x = active.iter().map(|m| m.x).sum::<f32>() / n;
+        let y = active.iter().map(|m| m.y).sum::<f32>() / n;
+        return [n, x, y];
+    }
+
+    fn _sensor_reply(&self) -> Vec<u8> {
+        let mut reply = vec![bindings::SENSOR];
+        for r in self.sensor_readings() {
+            reply.extend_from_slice(&r.to_le_bytes());
+        }
+        return reply;
+    }
+
+    /// `SENSOR` reply as pushed while streaming, i.e. prefixed by its length as `u8`.
+    #[must_use]
+    pub fn sensor_frame(&self) -> Vec<u8> {
+        let reply = self._sensor_reply();
+        let mut frame = Vec::with_capacity(reply.len() + 1);
+        frame.push(u8::try_from(reply.len()).unwrap_or(u8::MAX));
+        frame.extend_from_slice(&reply);
+        return frame;
+    }
+
+    /// Turns all magnets and LEDs off, as the firmware watchdog does.
+    pub fn enter_safe_state(&mut self) {
+        self.magnets.clear();
+        self.leds.clear();
+    }
+
+    /// Handles one instruction as sent by the host. Frames longer than `max_frame_len` are
+    /// dropped, as if they overflowed the receive buffer.
+    ///
+    /// ### Returns
+    /// Bytes to reply with, if any.
+    pub fn handle(&mut self, instr: &[u8]) -> Option<Vec<u8>> {
+        let (&opcode, args) = instr.split_first()?;
+        if self.max_frame_len.is_some_and(|max_len| instr.len() > max_len) {
+            // => Overflows receive buffer
+            warn!("[simulator::Firmware::handle] Dropped frame of {} bytes", instr.len());
+            return None;
+        }
+        let transfer = chunk::continued(instr);
+        let opcode = transfer.unwrap_or(opcode);
+        let continues = self.continued.take() == Some(opcode);
+        self.continued = transfer;
+        match opcode {
+            bindings::SENSOR => Some(self._sensor_reply()),
+            bindings::MAGNET => {
+                if !continues { self.magnets.clear(); }
+                self.magnets.extend(decode_cells(args));
+                Some(vec![bindings::ACK])
+            },
+            bindings::LED => {
+                if !continues { self.leds.clear(); }
+                self.leds.extend(decode_colors(args));
+                Some(vec![bindings::ACK])
+            },
+            opcode::MAGNET_DELTA => {
+                apply_delta(&mut self.magnets, &decode_magnet_delta(args));
+                Some(vec![bindings::ACK])
+            },
+            opcode::LED_DELTA => {
+                apply_delta(&mut self.leds, &decode_led_delta(args));
+                Some(vec![bindings::ACK])
+            },
+            opcode::STATE => {
+                let state = BoardState { magnets: self.magnets.clone(), leds: self.leds.clone(), synced: true };
+                Some(state.encode_reply())
+            },
+            bindings::HANDSHAKE => Some(vec![bindings::HANDSHAKE]),
+            opcode::HEARTBEAT => Some(vec![opcode::HEARTBEAT]),
+            opcode::STREAM => {
+                let ms = u16::from_le_bytes([*args.first()?, *args.get(1)?]);
+                self.stream_interval = (ms != 0).then(|| Duration::from_millis(ms.into()));
+                Some(vec![bindings::ACK])
+            },
+            bindings::QUIT => {
+                *self = Self { max_frame_len: self.max_frame_len, ..Self::default() };
+                Some(vec![bindings::ACK])
+            },
+            _ => None,
+        }
+    }
+}
+
+fn _run_firmware(mut port: TTYPort, mut firmware: Firmware) {
+    const _FN_NAME: &str = "[simulator::run_firmware]";
+
+    let mut instr: Vec<u8> = Vec::with_capacity(512);
+    let mut buf = [0_u8; 512];
+    let mut watchdog_armed = false;
+    let mut last_instr = Instant::now();
+    let mut last_push = Instant::now();
+    loop {
+        match port.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => instr.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                if firmware.stream_interval.is_some_and(|i| last_push.elapsed() >= i) {
+                    last_push = Instant::now();
+                    let frame = firmware.sensor_frame();
+                    if port.write_all(&frame).and_then(|()| port.flush()).is_err() { return; }
+                }
+                if instr.is_empty() {
+                    if watchdog_armed && last_instr.elapsed() > WATCHDOG_TIMEOUT {
+                        warn!("{_FN_NAME} Heartbeat lost, entering safe state");
+                        firmware.enter_safe_state();
+                        watchdog_armed = false;
+                    }
+                    continue;
+                }
+                debug!("{_FN_NAME} Received {:x?}", instr);
+                last_instr = Instant::now();
+                match instr[0] {
+                    opcode::HEARTBEAT => watchdog_armed = true,
+                    bindings::QUIT      => watchdog_armed = false,
+                    _ => (),
+                }
+                if let Some(reply) = firmware.handle(&instr) {
+                    if port.write_all(&reply).and_then(|()| port.flush()).is_err() { return; }
+                }
+                if instr[0] == opcode::STREAM { last_push = Instant::now(); }
+                instr.clear();
+            },
+            Err(_) => {
+                // => Host end closed
+                info!("{_FN_NAME} Host disconnected, stopping simulator");
+                return;
+            },
+        }
+    }
+}
+
+/// Spawns a simulated Arduino on a pseudo-TTY pair.
+///
+/// ### Returns
+/// The host end of the pair, with the given `timeout`, and the handle of the firmware thread,
+/// which exits once the host end is dropped.
+///
+/// # Errors
+/// Any `serialport::Error` from creating the pseudo-TTY pair.
+pub fn spawn(timeout: Duration) -> serialport::Result<(Box<dyn SerialPort>, JoinHandle<()>)> {
+    spawn_firmware(timeout, Firmware::default())
+}
+
+/// Same as `spawn`, but starts from `firmware`, e.g. to set `Firmware::max_frame_len`.
+///
+/// # Errors
+/// Any `serialport::Error` from creating the pseudo-TTY pair.
+pub fn spawn_firmware(
+    timeout: Duration,
+    firmware: Firmware,
+) -> serialport::Result<(Box<dyn SerialPort>, JoinHandle<()>)> {
+    let (mut host, mut device) = TTYPort::pair()?;
+    host.set_timeout(timeout)?;
+    device.set_timeout(INSTRUCTION_GAP)?;
+    let handle = thread::spawn(move || _run_firmware(device, firmware));
+    return Ok((Box::new(host), handle));
+}
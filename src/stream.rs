@@ -0,0 +1,362 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Continuous streaming of `SENSOR` readings.
+//!
+//! Readings come either from polling `SENSOR` at a fixed interval, or from the firmware pushing
+//! them on its own after `STREAM <interval>`, each as a `u8` length-prefixed frame. They are
+//! handed to the consumer through a bounded buffer, drained on a thread of its own; once the
+//! buffer is full, `DropPolicy` decides which reading to drop, or whether to hold the source back
+//! until the consumer catches up.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::{error, info, warn};
+
+use crate::command::Command;
+use crate::communicator::{Communicator, LinkEvent, Outcome};
+use crate::datalog::SensorLog;
+use crate::format::{Format, Status};
+use crate::response::{Decoded, Response};
+use crate::shutdown::Interrupt;
+use crate::{ReadMode, ReadSpec, Request};
+
+/// Default number of items buffered for the consumer.
+pub const BUFFER_CAPACITY: usize = 64;
+/// How long to wait for the firmware to acknowledge `STREAM`.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often a source held back under `DropPolicy::Block` checks for signals.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where readings come from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum Source {
+    /// Write `SENSOR` every interval and read the reply.
+    #[default]
+    Poll,
+    /// Have the firmware push readings every interval with `STREAM`.
+    Push,
+}
+
+/// What to do with a reading once the buffer is full.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum DropPolicy {
+    /// Drop the oldest buffered reading, so that the consumer sees the latest ones.
+    #[default]
+    Oldest,
+    /// Drop the new reading, so that the consumer sees an unbroken run of older ones.
+    Newest,
+    /// Hold the source back until there is room. Pushed readings then queue up in the port.
+    Block,
+}
+
+/// Item handed to the consumer of a `SensorStream`.
+#[derive(Debug)]
+pub enum Item {
+    Reading(Response),
+    Error(io::Error),
+    Event(LinkEvent),
+}
+
+/// Bounded buffer of `Item`s between a `SensorStream` and its consumer.
+///
+/// Only readings count towards the capacity and are ever dropped; errors and link events are
+/// always queued.
+#[derive(Debug)]
+pub struct Buffer {
+    state: Mutex<(VecDeque<Item>, bool)>,
+    changed: Condvar,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl Buffer {
+    #[must_use]
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            state: Mutex::new((VecDeque::with_capacity(capacity), false)),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    fn _lock(&self) -> MutexGuard<'_, (VecDeque<Item>, bool)> {
+        self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn _readings(queue: &VecDeque<Item>) -> usize {
+        queue.iter().filter(|i| matches!(i, Item::Reading(_))).count()
+    }
+
+    /// Queues `item` for the consumer, applying the drop policy to readings if the buffer is full.
+    ///
+    /// Under `DropPolicy::Block`, waits until there is room, the buffer is closed or `interrupt`
+    /// is set, dropping the reading in the latter two cases.
+    ///
+    /// ### Returns
+    /// Whether a reading was dropped.
+    pub fn push(&self, item: Item, interrupt: &Interrupt) -> bool {
+        let mut state = self._lock();
+        if state.1 { return true; }
+        if matches!(item, Item::Reading(_)) && Self::_readings(&state.0) >= self.capacity {
+            match self.policy {
+                DropPolicy::Oldest => {
+                    let oldest = state.0.iter().position(|i| matches!(i, Item::Reading(_)));
+                    if let Some(idx) = oldest { state.0.remove(idx); }
+                    state.0.push_back(item);
+                    drop(state);
+                    self.changed.notify_all();
+                    return true;
+                },
+                DropPolicy::Newest => return true,
+                DropPolicy::Block => {
+                    while !state.1 && Self::_readings(&state.0) >= self.capacity {
+                        if interrupt.is_set() { return true; }
+                        state = self.changed
+                            .wait_timeout(state, BLOCK_POLL_INTERVAL)
+                            .unwrap_or_else(std::sync::PoisonError::into_inner)
+                            .0;
+                    }
+                    if state.1 { return true; }
+                },
+            }
+        }
+        state.0.push_back(item);
+        drop(state);
+        self.changed.notify_all();
+        return false;
+    }
+
+    /// Takes the oldest item, waiting for one to arrive.
+    ///
+    /// ### Returns
+    /// `None` once the buffer is closed and drained.
+    pub fn pop(&self) -> Option<Item> {
+        let mut state = self._lock();
+        loop {
+            if let Some(item) = state.0.pop_front() {
+                drop(state);
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if state.1 { return None; }
+            state = self.changed.wait(state).unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+    }
+
+    /// Stops accepting items. Items already queued can still be taken.
+    pub fn close(&self) {
+        self._lock().1 = true;
+        self.changed.notify_all();
+    }
+
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self._lock().1
+    }
+}
+
+/// Counters of a `SensorStream`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct StreamStats {
+    pub readings: u64,
+    /// Readings dropped because the consumer fell behind.
+    pub dropped: u64,
+    /// Readings that failed or timed out.
+    pub errors: u64,
+}
+
+/// Streams `SENSOR` readings from a `Communicator` to a consumer.
+pub struct SensorStream<'a> {
+    comm: &'a mut Communicator,
+    source: Source,
+    interval: Duration,
+    sensor_reply: ReadSpec,
+    capacity: usize,
+    policy: DropPolicy,
+    count: Option<u64>,
+    stats: StreamStats,
+    log: Option<&'a mut SensorLog>,
+}
+
+impl<'a> SensorStream<'a> {
+    /// Creates a stream of readings from `source`, one every `interval`.
+    ///
+    /// Until `SensorStream::with_sensor_reply` is given, polled `SENSOR` replies are read as
+    /// whatever arrived within an interval. Pushed readings are always read as frames.
+    pub fn new(comm: &'a mut Communicator, source: Source, interval: Duration) -> Self {
+        Self {
+            comm,
+            source,
+            interval,
+            sensor_reply: ReadSpec { mode: ReadMode::Available, timeout: Some(interval) },
+            capacity: BUFFER_CAPACITY,
+            policy: DropPolicy::default(),
+            count: None,
+            stats: StreamStats::default(),
+            log: None,
+        }
+    }
+
+    /// Reads polled `SENSOR` replies as per `spec`, e.g. `ReadMode::Exact` to return as soon as
+    /// the reply is complete.
+    #[must_use]
+    pub const fn with_sensor_reply(mut self, spec: ReadSpec) -> Self {
+        self.sensor_reply = spec;
+        self
+    }
+
+    /// Buffers up to `capacity` readings for the consumer, then drops as per `policy`.
+    #[must_use]
+    pub const fn with_buffer(mut self, capacity: usize, policy: DropPolicy) -> Self {
+        self.capacity = capacity;
+        self.policy = policy;
+        self
+    }
+
+    /// Stops after `count` readings, failed and dropped ones included.
+    #[must_use]
+    pub const fn with_count(mut self, count: u64) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Logs each reading to `log`, including those dropped for the consumer.
+    #[must_use]
+    pub fn with_log(mut self, log: &'a mut SensorLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    #[must_use]
+    pub const fn stats(&self) -> &StreamStats {
+        &self.stats
+    }
+
+    /// Has the firmware start pushing readings and waits for its `ACK`.
+    fn _start_push(&mut self) -> io::Result<()> {
+        // Do not mistake readings still pushed from an earlier stream for ACK
+        self.comm.clear(serialport::ClearBuffer::Input)?;
+        let interval_ms = u16::try_from(self.interval.as_millis()).unwrap_or(u16::MAX).max(1);
+        self.comm.execute(&Request::from(&Command::Stream { interval_ms }))?;
+        let spec = ReadSpec { mode: ReadMode::Exact(1), timeout: Some(ACK_TIMEOUT.max(self.interval)) };
+        let res = self.comm.read(&spec)?;
+        if res.decoded == Decoded::Ack { return Ok(()); }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected ACK to STREAM from {}, got {:x?}", self.comm.device(), res.raw)
+        ));
+    }
+
+    fn _is_done(&self) -> bool {
+        self.count.is_some_and(|n| self.stats.readings + self.stats.errors >= n)
+    }
+
+    /// Reads the next reading from the source, waiting for it to be due if polling.
+    fn _next_reading(&mut self, due: &mut Instant) -> io::Result<Response> {
+        match self.source {
+            Source::Poll => {
+                self.comm.idle_for(due.saturating_duration_since(Instant::now()))?;
+                // Skip polls already missed rather than running them in a burst
+                *due = (*due + self.interval).max(Instant::now());
+                // Do not mistake a late reply to the previous poll for this one
+                self.comm.clear(serialport::ClearBuffer::Input)?;
+                self.comm.execute(&Request::from(&Command::Sensor))?;
+                self.comm.read(&self.sensor_reply)
+            },
+            Source::Push => {
+                let spec = ReadSpec { mode: ReadMode::Frame, timeout: Some(self.interval * 2) };
+                match self.comm.execute(&Request::Read(spec))? {
+                    Outcome::Received(res) => Ok(res),
+                    Outcome::Written(_) | Outcome::State(_) => unreachable!(),
+                }
+            },
+        }
+    }
+
+    /// Runs the stream until `interrupt` is set, the count given by `SensorStream::with_count` is
+    /// reached or the consumer fails, writing each reading, error and link event to `out` in
+    /// `format` from a thread of its own.
+    ///
+    /// # Errors
+    /// - Any `io::Error` from writing to `out`.
+    /// - Same as `Communicator::execute` and `Communicator::read` if the firmware does not
+    ///   acknowledge `STREAM` when pushing. Errors from the Arduino are handed to the consumer and
+    ///   counted in the statistics otherwise.
+    pub fn run(
+        &mut self,
+        format: Format,
+        out: &mut (dyn Write + Send),
+        interrupt: &Interrupt,
+    ) -> io::Result<()> {
+        const _FN_NAME: &str = "[SensorStream::run]";
+
+        if self.source == Source::Push { self._start_push()?; }
+        let device = self.comm.device().to_owned();
+        let buffer = Buffer::new(self.capacity, self.policy);
+        let written = thread::scope(|s| {
+            let consumer = s.spawn(|| {
+                let res = _consume(&buffer, format, &device, out);
+                buffer.close();
+                res
+            });
+
+            let mut due = Instant::now();
+            while !interrupt.is_set() && !buffer.is_closed() && !self._is_done() {
+                let item = match self._next_reading(&mut due) {
+                    Ok(res) => {
+                        self.stats.readings += 1;
+                        if let Some(Err(e)) = self.log.as_mut().map(|log| log.record_response(&res)) {
+                            error!("{_FN_NAME} Cannot log reading: {e}");
+                        }
+                        Item::Reading(res)
+                    },
+                    Err(e) => {
+                        self.stats.errors += 1;
+                        warn!("{_FN_NAME} Reading failed: {e}");
+                        Item::Error(e)
+                    },
+                };
+                if buffer.push(item, interrupt) { self.stats.dropped += 1; }
+                for event in self.comm.take_events() {
+                    info!("{_FN_NAME} {event:?}");
+                    let reconnected = matches!(event, LinkEvent::Connected { .. });
+                    buffer.push(Item::Event(event), interrupt);
+                    // => Fresh firmware, which is not streaming yet
+                    if reconnected && self.source == Source::Push {
+                        if let Err(e) = self._start_push() {
+                            self.stats.errors += 1;
+                            buffer.push(Item::Error(e), interrupt);
+                        }
+                    }
+                }
+            }
+            buffer.close();
+            consumer.join().expect("Stream consumer panicked")
+        });
+        if self.source == Source::Push {
+            let _ = self.comm.execute(&Request::from(&Command::Stream { interval_ms: 0 }));
+            // Drain the `ACK` along with readings pushed meanwhile
+            let _ = self.comm.read(&ReadSpec { mode: ReadMode::Available, timeout: Some(self.interval) });
+        }
+        info!("{_FN_NAME} Stopped: {:?}", self.stats);
+        return written;
+    }
+}
+
+/// Writes items taken from `buffer` to `out` in `format` until `buffer` is closed and drained.
+fn _consume(buffer: &Buffer, format: Format, device: &str, out: &mut dyn Write) -> io::Result<()> {
+    while let Some(item) = buffer.pop() {
+        match item {
+            Item::Reading(res) => format.write_response(out, &res)?,
+            Item::Error(e)     => format.write_error(out, Status::from(&e), device, &e.to_string())?,
+            Item::Event(e)     => format.write_event(out, &e)?,
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,260 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Raw terminal passthrough, like minicom, for talking to firmware directly.
+//!
+//! Keystrokes are sent to the port as-is and received bytes are shown as ASCII or as a hex dump.
+//! Commands are given by `Ctrl-A` followed by:
+//! - `x`/`q`: Exit.
+//! - `h`: Toggle between ASCII and hex-dump display.
+//! - `t`: Toggle timestamps at the start of each line.
+//! - `b`: Send a break.
+//! - `d`/`r`: Toggle DTR/RTS.
+//! - `s`: Switch to the next baud rate in `BAUD_RATES`.
+//! - `a`: Send a literal `Ctrl-A`.
+//! - `?`: Show these commands.
+//!
+//! `Ctrl-C` is sent to the port like any other key, whereas a signal caught by the given
+//! `Interrupt`, i.e. `SIGTERM`, exits as `Ctrl-A x` does.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossterm::terminal;
+use serialport::SerialPort;
+
+use crate::communicator::Communicator;
+use crate::shutdown::Interrupt;
+
+/// Prefix of terminal commands, i.e. `Ctrl-A`.
+pub const ESCAPE: u8 = 0x01;
+/// Baud rates cycled through by `Ctrl-A s`.
+pub const BAUD_RATES: [u32; 5] = [9_600, 19_200, 38_400, 57_600, 115_200];
+
+const BREAK_DURATION: Duration = Duration::from_millis(250);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+const HEX_DUMP_WIDTH: usize = 16;
+const READ_CHUNK: usize = 512;
+const HELP: &str = "Ctrl-A + x/q: exit, h: hex/ascii, t: timestamps, b: break, d: DTR, r: RTS, \
+                    s: baud, a: send Ctrl-A, ?: help";
+
+/// Restores the terminal from raw mode on drop.
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Formats the current UTC time of day as `HH:MM:SS.mmm`.
+fn _time_of_day() -> String {
+    let ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    let (s, ms) = (ms / 1000, ms % 1000);
+    format!("{:02}:{:02}:{:02}.{ms:03}", s / 3600 % 24, s / 60 % 60, s % 60)
+}
+
+/// Renders received bytes for display on a raw terminal.
+#[derive(Debug, Default)]
+pub struct Display {
+    pub hex: bool,
+    pub timestamps: bool,
+    /// Bytes on the current line, i.e. 0 at the start of a line.
+    column: usize,
+    /// Printable rendering of the current hex-dump line.
+    ascii: String,
+}
+
+impl Display {
+    fn _start_line(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.column == 0 && self.timestamps {
+            write!(out, "[{}] ", _time_of_day())?;
+        }
+        Ok(())
+    }
+
+    /// Ends the current line, if any, e.g. before switching display mode.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to `out`.
+    pub fn end_line(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.column == 0 { return Ok(()); }
+        if self.hex {
+            let pad = (HEX_DUMP_WIDTH - self.column) * 3;
+            write!(out, "{:pad$} |{}|", "", self.ascii)?;
+            self.ascii.clear();
+        }
+        self.column = 0;
+        out.write_all(b"\r\n")
+    }
+
+    /// Writes `bytes` to `out` in the current display mode.
+    ///
+    /// # Errors
+    /// Any `io::Error` from writing to `out`.
+    pub fn write(&mut self, out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+        for &b in bytes {
+            self._start_line(out)?;
+            if self.hex {
+                write!(out, "{b:02x} ")?;
+                self.ascii.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+                self.column += 1;
+                if self.column == HEX_DUMP_WIDTH { self.end_line(out)?; }
+            } else if b == b'\n' {
+                self.column = 1;
+                self.end_line(out)?;
+            } else if b != b'\r' {
+                out.write_all(&[b])?;
+                self.column += 1;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// Output modem control lines, as last set.
+struct ModemLines {
+    dtr: bool,
+    rts: bool,
+}
+
+/// Prints `msg` on a line of its own, as a notice from the terminal rather than the device.
+fn _notice(out: &mut dyn Write, display: &mut Display, msg: &str) -> io::Result<()> {
+    display.end_line(out)?;
+    write!(out, "*** {msg}\r\n")?;
+    out.flush()
+}
+
+/// Runs `Ctrl-A` command `cmd` against `port`, returning whether to go on.
+fn _run_command(
+    port: &mut dyn SerialPort,
+    display: &mut Display,
+    out: &mut dyn Write,
+    cmd: u8,
+    lines: &mut ModemLines,
+) -> io::Result<bool> {
+    let msg = match cmd {
+        b'x' | b'q' => return Ok(false),
+        b'h' => {
+            display.end_line(out)?;
+            display.hex = !display.hex;
+            format!("display: {}", if display.hex { "hex" } else { "ascii" })
+        },
+        b't' => {
+            display.timestamps = !display.timestamps;
+            format!("timestamps: {}", if display.timestamps { "on" } else { "off" })
+        },
+        b'b' => {
+            port.set_break()?;
+            thread::sleep(BREAK_DURATION);
+            port.clear_break()?;
+            String::from("break sent")
+        },
+        b'd' => {
+            lines.dtr = !lines.dtr;
+            port.write_data_terminal_ready(lines.dtr)?;
+            format!("DTR: {}", if lines.dtr { "on" } else { "off" })
+        },
+        b'r' => {
+            lines.rts = !lines.rts;
+            port.write_request_to_send(lines.rts)?;
+            format!("RTS: {}", if lines.rts { "on" } else { "off" })
+        },
+        b's' => {
+            let current = port.baud_rate()?;
+            let next = BAUD_RATES
+                .iter()
+                .find(|&&r| r > current)
+                .copied()
+                .unwrap_or(BAUD_RATES[0]);
+            port.set_baud_rate(next)?;
+            format!("baud: {next}")
+        },
+        b'a' => {
+            port.write_all(&[ESCAPE])?;
+            return Ok(true);
+        },
+        _ => String::from(HELP),
+    };
+    _notice(out, display, &msg)?;
+    return Ok(true);
+}
+
+/// Runs the passthrough terminal on the port of `comm` until `Ctrl-A x`, EOF on stdin or
+/// `interrupt` is set.
+///
+/// # Errors
+/// Any `io::Error` from the terminal or the port.
+pub fn run(comm: &mut Communicator, hex: bool, interrupt: &Interrupt) -> io::Result<()> {
+    let device = comm.device().to_owned();
+    let Some(port) = comm.port_mut() else {
+        return Err(io::Error::new(io::ErrorKind::NotConnected, format!("{device} is not connected")));
+    };
+    let mut out = io::stdout();
+    let mut display = Display { hex, ..Display::default() };
+    // DTR and RTS are asserted on open
+    let mut lines = ModemLines { dtr: true, rts: true };
+
+    let _guard = RawModeGuard::enable()?;
+    _notice(&mut out, &mut display, &format!("connected to {device}, {HELP}"))?;
+
+    // Stdin blocks, so read it on its own thread
+    let (tx, rx) = mpsc::channel::<u8>();
+    thread::spawn(move || {
+        for b in io::stdin().lock().bytes() {
+            let Ok(b) = b else { return; };
+            if tx.send(b).is_err() { return; }
+        }
+    });
+
+    let mut buf = [0_u8; READ_CHUNK];
+    let mut escaped = false;
+    loop {
+        if interrupt.is_set() {
+            display.end_line(&mut out)?;
+            return Ok(());
+        }
+
+        /* 1. Port -> terminal */
+        let available = port.bytes_to_read()? as usize;
+        if available != 0 {
+            let n = port.read(&mut buf[..available.min(READ_CHUNK)])?;
+            display.write(&mut out, &buf[..n])?;
+        }
+
+        /* 2. Terminal -> port */
+        let mut idle = available == 0;
+        loop {
+            let b = match rx.try_recv() {
+                Ok(b) => b,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    display.end_line(&mut out)?;
+                    return Ok(());
+                },
+            };
+            idle = false;
+            if escaped {
+                escaped = false;
+                if !_run_command(port, &mut display, &mut out, b, &mut lines)? {
+                    display.end_line(&mut out)?;
+                    return Ok(());
+                }
+            } else if b == ESCAPE {
+                escaped = true;
+            } else {
+                port.write_all(&[b])?;
+            }
+        }
+
+        if idle { thread::sleep(POLL_INTERVAL); }
+    }
+}
@@ -0,0 +1,269 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Magnet trajectories, expanded into timed `MAGNET` frames that move one magnet along a path.
+//!
+//! A trajectory passes through its waypoints, joined by straight lines or by a Catmull-Rom
+//! spline, and ends at the last waypoint once its duration has elapsed. The magnet moves along
+//! the path with a trapezoidal speed profile: it accelerates at `max_accel` up to a cruise speed
+//! of at most `max_speed`, then decelerates to a stop. Without limits it moves at constant speed.
+//! The duration is stretched wherever the limits do not allow to make it in time.
+
+use std::fmt::Display;
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Command, MagnetCell};
+use crate::Instruction;
+
+/// Default interval between `MAGNET` frames, i.e. 50 Hz.
+pub const FRAME_INTERVAL_MS: u16 = 20;
+/// Number of points each spline segment is sampled at to measure its length.
+const SAMPLES_PER_SEGMENT: usize = 32;
+
+/// How the path joins waypoints.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Straight lines, with sharp turns at waypoints.
+    #[default]
+    Linear,
+    /// Catmull-Rom spline, smooth through all waypoints.
+    Spline,
+}
+
+const fn _default_frame_ms() -> u16 {
+    FRAME_INTERVAL_MS
+}
+
+/// Movement of one magnet through `waypoints` within `duration_ms`, in `MAGNET` coordinates.
+///
+/// Parsed from `MOVE <x0> <y0> <x1> <y1> [<x> <y> ...] OVER <ms> [LINEAR | SPLINE] [SPEED <v>]
+/// [ACCEL <a>] [EVERY <ms>]` in the text grammar, or `{"op":"MOVE","waypoints":[[0,0],[10,5]],
+/// "duration_ms":500}` as a `TypedRequest`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Trajectory {
+    pub waypoints: Vec<(f32, f32)>,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Maximum speed along the path, in units per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_speed: Option<f32>,
+    /// Maximum acceleration along the path, in units per second squared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_accel: Option<f32>,
+    /// Interval between frames.
+    #[serde(default = "_default_frame_ms")]
+    pub frame_ms: u16,
+}
+
+/// Speed profile along a path of `length` units.
+#[derive(Debug, Clone, Copy)]
+struct Profile {
+    length: f64,
+    /// Cruise speed.
+    speed: f64,
+    accel: f64,
+    /// Total duration, in seconds.
+    total: f64,
+}
+
+impl Profile {
+    /// Distance along the path at `t` seconds from the start.
+    fn _distance_at(&self, t: f64) -> f64 {
+        if t >= self.total { return self.length; }
+        let ramp = self.speed / self.accel;
+        let s = if t < ramp {
+            0.5 * self.accel * t * t
+        } else if t < self.total - ramp {
+            self.speed.mul_add(t - ramp, 0.5 * self.speed * ramp)
+        } else {
+            let left = self.total - t;
+            (0.5 * self.accel * left).mul_add(-left, self.length)
+        };
+        s.clamp(0.0, self.length)
+    }
+}
+
+/// Point at `t` within `[0, 1]` of the Catmull-Rom segment from `p1` to `p2`.
+fn _catmull_rom(p0: (f32, f32), p1: (f32, f32), p2: (f32, f32), p3: (f32, f32), t: f32) -> (f32, f32) {
+    let f = |a: f32, b: f32, c: f32, d: f32| {
+        let cubic = 3.0f32.mul_add(b - c, d - a);
+        let square = 2.0f32.mul_add(a, -5.0 * b) + 4.0f32.mul_add(c, -d);
+        0.5 * cubic.mul_add(t, square).mul_add(t, c - a).mul_add(t, 2.0 * b)
+    };
+    (f(p0.0, p1.0, p2.0, p3.0), f(p0.1, p1.1, p2.1, p3.1))
+}
+
+fn _distance(a: (f32, f32), b: (f32, f32)) -> f64 {
+    f64::from(b.0 - a.0).hypot(f64::from(b.1 - a.1))
+}
+
+impl Trajectory {
+    /// Straight movement through `waypoints` within `duration`, without limits.
+    #[must_use]
+    pub fn new(waypoints: Vec<(f32, f32)>, duration: Duration) -> Self {
+        Self {
+            waypoints,
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            interpolation: Interpolation::Linear,
+            max_speed: None,
+            max_accel: None,
+            frame_ms: FRAME_INTERVAL_MS,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_speed(mut self, speed: f32) -> Self {
+        self.max_speed = Some(speed);
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_accel(mut self, accel: f32) -> Self {
+        self.max_accel = Some(accel);
+        self
+    }
+
+    /// Checks that the trajectory can be expanded.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` if there are fewer than 2 waypoints, a coordinate is not
+    /// finite, a limit is not positive or the frame interval is 0.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        if self.waypoints.len() < 2 {
+            return invalid("Expected at least 2 waypoints");
+        }
+        if self.waypoints.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+            return invalid("Expected finite waypoints");
+        }
+        if [self.max_speed, self.max_accel].iter().flatten().any(|v| !(v.is_finite() && *v > 0.0)) {
+            return invalid("Expected positive speed and acceleration limits");
+        }
+        if self.frame_ms == 0 {
+            return invalid("Expected positive frame interval");
+        }
+        return Ok(());
+    }
+
+    /// Points of the path, close enough together to be joined by straight lines.
+    fn _polyline(&self) -> Vec<(f32, f32)> {
+        let w = &self.waypoints;
+        if self.interpolation == Interpolation::Linear || w.len() < 3 { return w.clone(); }
+
+        // => Ends mirrored so that the spline starts and ends heading to its neighbour
+        let mirror = |p: (f32, f32), q: (f32, f32)| (2.0f32.mul_add(p.0, -q.0), 2.0f32.mul_add(p.1, -q.1));
+        let last = w.len() - 1;
+        let at = |i: isize| match usize::try_from(i) {
+            Ok(i) if i <= last => w[i],
+            Ok(_) => mirror(w[last], w[last - 1]),
+            Err(_) => mirror(w[0], w[1]),
+        };
+        let mut points = Vec::with_capacity((w.len() - 1) * SAMPLES_PER_SEGMENT + 1);
+        for i in 0..w.len() - 1 {
+            #[allow(clippy::cast_possible_wrap)]
+            let i = i as isize;
+            for k in 0..SAMPLES_PER_SEGMENT {
+                #[allow(clippy::cast_precision_loss)]
+                let t = k as f32 / SAMPLES_PER_SEGMENT as f32;
+                points.push(_catmull_rom(at(i - 1), at(i), at(i + 1), at(i + 2), t));
+            }
+        }
+        points.push(w[w.len() - 1]);
+        return points;
+    }
+
+    fn _profile(&self, length: f64) -> Profile {
+        #[allow(clippy::cast_precision_loss)]
+        let requested = self.duration_ms as f64 / 1000.0;
+        let accel = self.max_accel.map_or(f64::INFINITY, f64::from);
+        let max_speed = self.max_speed.map_or(f64::INFINITY, f64::from);
+        if length <= 0.0 { return Profile { length, speed: 0.0, accel, total: requested }; }
+
+        // => Slowest cruise speed that makes it in time, i.e. the smaller root of
+        //    v^2 - a*T*v + a*L = 0, or as fast as possible if it cannot
+        let speed = if accel.is_infinite() {
+            length / requested
+        } else {
+            let discriminant = (4.0 * accel).mul_add(-length, (accel * requested).powi(2));
+            if discriminant < 0.0 {
+                (accel * length).sqrt()
+            } else {
+                accel.mul_add(requested, -discriminant.sqrt()) / 2.0
+            }
+        };
+        let speed = speed.min(max_speed);
+        let total = if accel.is_infinite() { length / speed } else { length / speed + speed / accel };
+        Profile { length, speed, accel, total: total.max(requested) }
+    }
+
+    /// Total duration, including any stretching to respect the limits.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        let polyline = self._polyline();
+        let length = polyline.windows(2).map(|p| _distance(p[0], p[1])).sum();
+        Duration::from_secs_f64(self._profile(length).total)
+    }
+
+    /// Expands the trajectory into `MAGNET` frames turning on only the magnet at its position,
+    /// every `frame_ms` and at the end.
+    ///
+    /// ### Returns
+    /// Frames with their offsets from the start, in order. Empty if there are fewer than 2
+    /// waypoints.
+    #[must_use]
+    pub fn frames(&self) -> Vec<(Duration, Instruction)> {
+        if self.waypoints.len() < 2 { return Vec::new(); }
+        let polyline = self._polyline();
+        let mut cumulative = vec![0.0];
+        for p in polyline.windows(2) {
+            cumulative.push(cumulative[cumulative.len() - 1] + _distance(p[0], p[1]));
+        }
+        let profile = self._profile(cumulative[cumulative.len() - 1]);
+
+        let point_at = |s: f64| -> (f32, f32) {
+            let i = cumulative.partition_point(|&c| c <= s).clamp(1, polyline.len() - 1);
+            let (a, b) = (polyline[i - 1], polyline[i]);
+            let span = cumulative[i] - cumulative[i - 1];
+            #[allow(clippy::cast_possible_truncation)]
+            let f = if span > 0.0 { ((s - cumulative[i - 1]) / span).clamp(0.0, 1.0) as f32 } else { 1.0 };
+            ((b.0 - a.0).mul_add(f, a.0), (b.1 - a.1).mul_add(f, a.1))
+        };
+        let frame = |(x, y): (f32, f32)| Command::Magnet { cells: vec![MagnetCell { x, y, on: true }] }.encode();
+
+        let interval = f64::from(self.frame_ms) / 1000.0;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let n = (profile.total / interval).ceil() as u64;
+        (0..=n)
+            .map(|k| {
+                #[allow(clippy::cast_precision_loss)]
+                let t = (k as f64 * interval).min(profile.total);
+                (Duration::from_secs_f64(t), frame(point_at(profile._distance_at(t))))
+            })
+            .collect()
+    }
+}
+
+impl Display for Trajectory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MOVE")?;
+        for (x, y) in &self.waypoints {
+            write!(f, " {x} {y}")?;
+        }
+        write!(f, " OVER {}", self.duration_ms)?;
+        if self.interpolation == Interpolation::Spline { write!(f, " SPLINE")?; }
+        if let Some(v) = self.max_speed { write!(f, " SPEED {v}")?; }
+        if let Some(a) = self.max_accel { write!(f, " ACCEL {a}")?; }
+        if self.frame_ms != FRAME_INTERVAL_MS { write!(f, " EVERY {}", self.frame_ms)?; }
+        return Ok(());
+    }
+}
@@ -5,6 +5,7 @@ extern crate serialport;
 
 use std::error::Error;
 use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::time::{Duration, Instant};
 
 use log::error;
 
@@ -191,10 +192,134 @@ pub fn write_str_ends_with(
     }
 }
 
+/// Interval at which deadline-bound reads poll the given `port` for incoming bytes.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(5); 
+
+/// Waits until the given `port` has incoming bytes or `deadline` passes, whichever is earlier.
+///
+/// ## Ok
+/// `usize` number of bytes available to read, non-zero.
+///
+/// ## Err
+/// - `io::Error` of kind `io::ErrorKind::TimedOut` if `deadline` passed with no byte to read.
+/// - `io::Error` if cannot query `port`.
+fn _wait_for_bytes(port: &dyn SerialPort, deadline: Instant, fn_name: &str) -> io::Result<usize> {
+    loop {
+        let available = port.bytes_to_read()? as usize; 
+        if available != 0 { return Ok(available); }
+        let now = Instant::now(); 
+        if now >= deadline {
+            return Err(std::io::Error::new(
+                ErrorKind::TimedOut, 
+                format!("{fn_name} Timed out while trying to read from port")
+            )); 
+        }
+        std::thread::sleep(READ_POLL_INTERVAL.min(deadline - now)); 
+    }
+}
+
+/// Tries to read exactly `n` bytes from the given `port` into `buf` within `timeout`. 
+/// 
+/// ## Ok
+/// `usize` number of bytes read, i.e. `n`. 
+/// 
+/// ## Err
+/// - `io::Error` of kind `io::ErrorKind::TimedOut` if fewer than `n` bytes arrived in time. `buf` 
+///   holds the bytes read so far. 
+/// - `io::Error` if cannot read from `port`. 
+pub fn read_exact_into(
+    port: &mut dyn SerialPort, 
+    buf: &mut Vec<u8>, 
+    n: usize, 
+    timeout: Duration
+) -> io::Result<usize> {
+    const _FN_NAME: &str = "[util::serial_helper::read_exact_into]"; 
+
+    let deadline = Instant::now() + timeout; 
+    buf.clear(); 
+    while buf.len() < n {
+        let available = _wait_for_bytes(port, deadline, _FN_NAME)?; 
+        let start = buf.len(); 
+        buf.resize(start + available.min(n - start), 0); 
+        port.read_exact(&mut buf[start..])?; 
+    }
+    return Ok(buf.len()); 
+}
+
+/// Tries to read from the given `port` into `buf` until and including `endbyte`, within `timeout`. 
+/// 
+/// ## Ok
+/// `usize` number of bytes read, including `endbyte`. 
+/// 
+/// ## Err
+/// Same as `read_exact_into`. 
+pub fn read_until_byte_into(
+    port: &mut dyn SerialPort, 
+    buf: &mut Vec<u8>, 
+    endbyte: u8, 
+    timeout: Duration
+) -> io::Result<usize> {
+    const _FN_NAME: &str = "[util::serial_helper::read_until_byte_into]"; 
+
+    let deadline = Instant::now() + timeout; 
+    let mut byte = [0_u8; 1]; 
+    buf.clear(); 
+    loop {
+        // Read bytewise to leave whatever follows `endbyte` in `port`
+        for _ in 0.._wait_for_bytes(port, deadline, _FN_NAME)? {
+            port.read_exact(&mut byte)?; 
+            buf.push(byte[0]); 
+            if byte[0] == endbyte { return Ok(buf.len()); }
+        }
+    }
+}
+
+/// Tries to read a length-prefixed frame from the given `port` into `buf` within `timeout`, i.e. 
+/// one `u8` payload length followed by that many payload bytes. 
+/// 
+/// ## Ok
+/// `usize` number of payload bytes read. `buf` holds the payload without its length prefix. 
+/// 
+/// ## Err
+/// Same as `read_exact_into`. 
+pub fn read_frame_into(
+    port: &mut dyn SerialPort, 
+    buf: &mut Vec<u8>, 
+    timeout: Duration
+) -> io::Result<usize> {
+    const _FN_NAME: &str = "[util::serial_helper::read_frame_into]"; 
+
+    let deadline = Instant::now() + timeout; 
+    let mut len = [0_u8; 1]; 
+    _wait_for_bytes(port, deadline, _FN_NAME)?; 
+    port.read_exact(&mut len)?; 
+    read_exact_into(port, buf, len[0] as usize, deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Tries to read all bytes sent to the given `port` within its timeout into `buf`. 
+/// 
+/// Same as `read_all_bytes_after` with the timeout of `port`. 
 pub fn read_all_bytes_into(port: &mut dyn SerialPort, buf: &mut Vec<u8>) -> io::Result<usize> {
-    const _FN_NAME: &str = "[util::serial_helper::read_all_bytes_into]"; 
+    let timeout = port.timeout(); 
+    read_all_bytes_after(port, buf, timeout)
+}
+
+/// Waits for `wait`, then tries to read all bytes sent to the given `port` into `buf`. 
+/// 
+/// ## Ok
+/// `usize` number of bytes read. 
+/// 
+/// ## Err
+/// - `io::Error` of kind `io::ErrorKind::TimedOut` if nothing was sent within `wait`. 
+/// - `io::Error` if cannot read from `port`. 
+pub fn read_all_bytes_after(
+    port: &mut dyn SerialPort, 
+    buf: &mut Vec<u8>, 
+    wait: Duration
+) -> io::Result<usize> {
+    const _FN_NAME: &str = "[util::serial_helper::read_all_bytes_after]"; 
 
-    std::thread::sleep(port.timeout()); 
+    std::thread::sleep(wait); 
     if port.bytes_to_read()? == 0 {
         return Err(std::io::Error::new(
             ErrorKind::TimedOut, 
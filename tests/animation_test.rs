@@ -0,0 +1,145 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serial_communicator::{ReadMode, ReadSpec, Request}; 
+use serial_communicator::animation::{Animation, Effect, Keyframe}; 
+use serial_communicator::command::{Command, TypedRequest}; 
+use serial_communicator::communicator::{Communicator, Outcome}; 
+use serial_communicator::response::Decoded; 
+use serial_communicator::simulator; 
+
+fn _ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn test_parse_animate() {
+    let req = Request::try_from("ANIMATE CHASE red rgb(0, 0, 16) OVER 800 LOOP LEDS 8 FPS 20 WIDTH 2")
+        .expect("[animation_test::test_parse_animate] Cannot parse ANIMATE"); 
+    let Request::Animate(a) = &req else { panic!("[ERROR] ANIMATE not parsed into Request::Animate") }; 
+    assert_eq!(a.effect, Effect::Chase { color: 0xFF0000, background: 0x10, width: 2 }, "[ERROR] Incorrect effect"); 
+    assert_eq!((a.period_ms, a.looping, a.leds, a.max_fps), (800, true, Some(8), Some(20.0)), "[ERROR] Incorrect options"); 
+    assert!(matches!(Request::try_from(req.to_string().as_str()), Ok(r) if r == req), "[ERROR] Display does not round-trip"); 
+
+    let req = Request::try_from("ANIMATE KEYFRAMES AT 0 black AT 500 #ff0000 #00ff00 OVER 500").unwrap(); 
+    let Request::Animate(a) = &req else { panic!("[ERROR] ANIMATE not parsed into Request::Animate") }; 
+    assert_eq!(a.effect, Effect::Keyframes { keyframes: vec![
+        Keyframe { at_ms: 0, colors: vec![0] }, 
+        Keyframe { at_ms: 500, colors: vec![0xFF0000, 0x00FF00] }, 
+    ] }, "[ERROR] Incorrect keyframes"); 
+    assert!(matches!(Request::try_from(req.to_string().as_str()), Ok(r) if r == req), "[ERROR] Display does not round-trip"); 
+    assert!(matches!(Request::try_from("STOP"), Ok(Request::Stop)), "[ERROR] STOP not parsed"); 
+
+    for line in [
+        "ANIMATE RAINBOW", 
+        "ANIMATE RAINBOW OVER 0", 
+        "ANIMATE FADE red OVER 100", 
+        "ANIMATE PULSE red OVER 100 WIDTH 2", 
+        "ANIMATE SPARKLE OVER 100", 
+        "ANIMATE KEYFRAMES AT 200 red AT 100 blue OVER 300", 
+        "ANIMATE KEYFRAMES AT 0 red OVER 100 LEDS 0", 
+        "STOP NOW", 
+    ] {
+        assert!(Request::try_from(line).is_err(), "[ERROR] Malformed {line} accepted"); 
+    }
+}
+
+#[test]
+fn test_typed_animate() {
+    let req: TypedRequest = serde_json::from_str(
+        r#"{"op":"ANIMATE","effect":"fade","from":"red","to":255,"period_ms":1000,"loop":true}"#
+    ).expect("[animation_test::test_typed_animate] Cannot deserialize ANIMATE"); 
+    let expected = Animation::new(Effect::Fade { from: 0xFF0000, to: 0xFF }, _ms(1000)).with_looping(true); 
+    assert_eq!(req, TypedRequest::Animate(expected.clone()), "[ERROR] Incorrect animation"); 
+    assert!(matches!(Request::try_from(&req), Ok(Request::Animate(a)) if a == expected), "[ERROR] Incorrect conversion"); 
+
+    let stop: TypedRequest = serde_json::from_str(r#"{"op":"STOP"}"#).unwrap(); 
+    assert!(matches!(Request::try_from(&stop), Ok(Request::Stop)), "[ERROR] STOP not converted"); 
+    let invalid: TypedRequest = serde_json::from_str(r#"{"op":"ANIMATE","effect":"rainbow","period_ms":0}"#).unwrap(); 
+    assert!(Request::try_from(&invalid).is_err(), "[ERROR] Invalid animation accepted"); 
+}
+
+#[test]
+fn test_effect_frames() {
+    let fade = Animation::new(Effect::Fade { from: 0x000000, to: 0xC8C8C8 }, _ms(1000)); 
+    assert_eq!(fade.frame_at(_ms(500), 2), vec![0x646464; 2], "[ERROR] Incorrect fade midpoint"); 
+    assert_eq!(fade.frame_at(_ms(5000), 2), vec![0xC8C8C8; 2], "[ERROR] Fade not held at end"); 
+    assert!(fade.is_done(_ms(1000)) && !fade.clone().with_looping(true).is_done(_ms(5000)), "[ERROR] Incorrect end"); 
+    assert_eq!(fade.with_looping(true).frame_at(_ms(1250), 1), vec![0x323232], "[ERROR] Loop not wrapped"); 
+
+    let pulse = Animation::new(Effect::Pulse { color: 0xFF0000 }, _ms(1000)); 
+    assert_eq!((pulse.frame_at(_ms(0), 1), pulse.frame_at(_ms(500), 1)), (vec![0], vec![0xFF0000]), "[ERROR] Incorrect pulse"); 
+
+    let rainbow = Animation::new(Effect::Rainbow, _ms(900)); 
+    assert_eq!(rainbow.frame_at(_ms(0), 3), vec![0xFF0000, 0x00FF00, 0x0000FF], "[ERROR] Incorrect rainbow"); 
+    assert_eq!(rainbow.frame_at(_ms(300), 3), vec![0x00FF00, 0x0000FF, 0xFF0000], "[ERROR] Rainbow not turning"); 
+
+    let chase = Animation::new(Effect::Chase { color: 1, background: 0, width: 2 }, _ms(600)); 
+    assert_eq!(chase.frame_at(_ms(300), 4), vec![0, 1, 1, 0], "[ERROR] Incorrect chase"); 
+
+    let keyframes = Animation::new(Effect::Keyframes { keyframes: vec![
+        Keyframe { at_ms: 100, colors: vec![0x000000] }, 
+        Keyframe { at_ms: 300, colors: vec![0x640000, 0x006400] }, 
+    ] }, _ms(400)); 
+    assert_eq!(keyframes.frame_at(_ms(50), 3), vec![0; 3], "[ERROR] First keyframe not held"); 
+    assert_eq!(keyframes.frame_at(_ms(200), 3), vec![0x320000, 0x003200, 0x320000], "[ERROR] Incorrect interpolation"); 
+    assert_eq!(keyframes.frame_at(_ms(400), 3), vec![0x640000, 0x006400, 0x640000], "[ERROR] Last keyframe not held"); 
+}
+
+#[test]
+fn test_frame_interval() {
+    let rainbow = Animation::new(Effect::Rainbow, _ms(1000)); 
+    assert_eq!(rainbow.frame_interval(1, None), Duration::from_secs_f64(1.0 / 30.0), "[ERROR] Incorrect default rate"); 
+    assert_eq!(rainbow.clone().with_max_fps(10.0).frame_interval(1, Some(115_200)), _ms(100), "[ERROR] Frame rate not limited"); 
+    // => 302 bytes of 10 bits on half of 9600 baud
+    let interval = rainbow.frame_interval(100, Some(9600)); 
+    assert!((interval.as_secs_f64() - 3020.0 / 4800.0).abs() < 1e-6, "[ERROR] Link bandwidth not respected: {interval:?}"); 
+}
+
+#[test]
+fn test_communicator_plays_animation() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[animation_test::test_communicator_plays_animation] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    let req = Request::try_from("ANIMATE FADE black blue OVER 200 LEDS 2").unwrap(); 
+    let outcome = comm.execute(&req).expect("[animation_test::test_communicator_plays_animation] Cannot start animation"); 
+    assert_eq!(outcome, Outcome::Written(Command::Led { colors: vec![0; 2] }.encode()), "[ERROR] First frame not written"); 
+    comm.idle_for(_ms(300)).expect("[animation_test::test_communicator_plays_animation] Cannot play animation"); 
+    assert!(comm.animation().is_none(), "[ERROR] Animation not done"); 
+    assert_eq!(comm.board_state().leds, vec![0xFF; 2], "[ERROR] Last frame not applied"); 
+    comm.sync_state().expect("[animation_test::test_communicator_plays_animation] Cannot sync state"); 
+    assert_eq!(comm.board_state().leds, vec![0xFF; 2], "[ERROR] Firmware not at last frame"); 
+
+    comm.execute(&Request::try_from("ANIMATE RAINBOW OVER 300 LOOP LEDS 3").unwrap()).unwrap(); 
+    comm.idle_for(_ms(100)).unwrap(); 
+    assert_ne!(comm.board_state().leds, vec![0xFF0000, 0x00FF00, 0x0000FF], "[ERROR] Rainbow not turning"); 
+    assert!(matches!(comm.execute(&Request::Stop), Ok(Outcome::State(_))), "[ERROR] Incorrect outcome of STOP"); 
+    let leds = comm.board_state().leds.clone(); 
+    comm.idle_for(_ms(100)).unwrap(); 
+    assert_eq!(comm.board_state().leds, leds, "[ERROR] Animation not stopped"); 
+}
+
+#[test]
+fn test_animation_after_unread_ack() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[animation_test::test_animation_after_unread_ack] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    comm.execute(&Request::try_from("ANIMATE FADE black blue OVER 200 LEDS 2").unwrap())
+        .expect("[animation_test::test_animation_after_unread_ack] Cannot start animation"); 
+    // => ACK to MAGNET left unread by the client
+    comm.execute(&Request::try_from("WRITE MAGNET 1.0 2.0 true").unwrap())
+        .expect("[animation_test::test_animation_after_unread_ack] Cannot write MAGNET"); 
+    comm.idle_for(_ms(300)).expect("[animation_test::test_animation_after_unread_ack] Cannot play animation"); 
+    assert!(comm.animation().is_none(), "[ERROR] Animation stalled by unread ACK"); 
+    assert_eq!(comm.board_state().leds, vec![0xFF; 2], "[ERROR] Last frame not applied"); 
+
+    let res = comm.read(&ReadSpec { mode: ReadMode::Exact(1), timeout: Some(_ms(100)) })
+        .expect("[animation_test::test_animation_after_unread_ack] ACK to MAGNET not held"); 
+    assert_eq!(res.decoded, Decoded::Ack, "[ERROR] Incorrect held reply"); 
+    assert_eq!(comm.board_state().magnets.len(), 1, "[ERROR] MAGNET not applied"); 
+}
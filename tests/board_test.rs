@@ -0,0 +1,117 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serialport::TTYPort; 
+use serial_communicator::{ReadMode, ReadSpec, Request}; 
+use serial_communicator::board::BoardState; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::{Communicator, Connector, Outcome, PendingPolicy, ReconnectPolicy}; 
+use serial_communicator::response::Decoded; 
+use serial_communicator::simulator; 
+
+const ACK: ReadSpec = ReadSpec { mode: ReadMode::Exact(1), timeout: Some(Duration::from_millis(500)) }; 
+
+fn _cells() -> Vec<MagnetCell> {
+    vec![MagnetCell { x: 1.0, y: 2.0, on: true }, MagnetCell { x: -3.5, y: 0.25, on: false }]
+}
+
+#[test]
+fn test_state_reply_roundtrip() {
+    let state = BoardState { magnets: _cells(), leds: vec![0xff0000, 0x00ff7f], synced: true }; 
+    let reply = state.encode_reply(); 
+    assert_eq!(
+        Decoded::from_bytes(&reply), 
+        Decoded::State { magnets: state.magnets.clone(), leds: state.leds.clone() }, 
+        "[ERROR] Incorrect decoding of STATE reply"
+    ); 
+    assert_eq!(BoardState::decode_reply(&reply[1..]), Some(state), "[ERROR] STATE reply not roundtripped"); 
+    assert_eq!(BoardState::decode_reply(&reply[1..reply.len() - 1]), None, "[ERROR] Truncated STATE reply accepted"); 
+}
+
+#[test]
+fn test_state_parsed_from_text() {
+    assert!(matches!(Request::try_from("STATE"), Ok(Request::State)), "[ERROR] STATE not parsed"); 
+    assert!(Request::try_from("STATE 1").is_err(), "[ERROR] STATE with arguments accepted"); 
+}
+
+#[test]
+fn test_mirror_tracks_acknowledged_commands() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[board_test::test_mirror_tracks_acknowledged_commands] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    comm.execute(&Request::from(&Command::Magnet { cells: _cells() }))
+        .expect("[board_test::test_mirror_tracks_acknowledged_commands] Cannot write MAGNET"); 
+    assert!(comm.board_state().magnets.is_empty(), "[ERROR] MAGNET applied before ACK"); 
+    comm.read(&ACK).expect("[board_test::test_mirror_tracks_acknowledged_commands] No ACK to MAGNET"); 
+    assert_eq!(comm.board_state().magnets, _cells(), "[ERROR] MAGNET not applied after ACK"); 
+
+    comm.execute(&Request::from(&Command::Led { colors: vec![0x123456] }))
+        .expect("[board_test::test_mirror_tracks_acknowledged_commands] Cannot write LED"); 
+    comm.read(&ACK).expect("[board_test::test_mirror_tracks_acknowledged_commands] No ACK to LED"); 
+    match comm.execute(&Request::State) {
+        Ok(Outcome::State(s)) => {
+            assert_eq!(s.leds, vec![0x123456], "[ERROR] LED not applied after ACK"); 
+            assert_eq!(s.magnets, _cells(), "[ERROR] LED changed magnet state"); 
+        }, 
+        other => panic!("[ERROR] Expected state, got {other:?}"), 
+    }
+
+    comm.sync_state().expect("[board_test::test_mirror_tracks_acknowledged_commands] Cannot sync state"); 
+    assert_eq!(
+        comm.board_state(), 
+        &BoardState { magnets: _cells(), leds: vec![0x123456], synced: true }, 
+        "[ERROR] Mirror differs from firmware state"
+    ); 
+
+    comm.execute(&Request::from(&Command::Quit))
+        .expect("[board_test::test_mirror_tracks_acknowledged_commands] Cannot write QUIT"); 
+    comm.read(&ACK).expect("[board_test::test_mirror_tracks_acknowledged_commands] No ACK to QUIT"); 
+    assert_eq!(
+        comm.board_state(), 
+        &BoardState { synced: true, ..BoardState::default() }, 
+        "[ERROR] QUIT did not reset mirror"
+    ); 
+}
+
+#[test]
+fn test_mirror_resynced_after_reconnect() {
+    // Writing to a pty whose device side is closed fails, as after a USB glitch
+    let (host, device) = TTYPort::pair().expect("[board_test::test_mirror_resynced_after_reconnect] Cannot open pty pair"); 
+    drop(device); 
+    let connector: Connector = Box::new(|| Ok(simulator::spawn(Duration::from_secs(1))?.0)); 
+    let policy = ReconnectPolicy {
+        pending: PendingPolicy::Queue, 
+        give_up_after: Duration::from_secs(2), 
+        retry_interval: Duration::from_millis(50), 
+    }; 
+    let mut comm = Communicator::with_device_name(Box::new(host), simulator::DEVICE_NAME)
+        .with_reconnect(connector, policy); 
+    assert!(!comm.board_state().synced, "[ERROR] Mirror synced before reconnect"); 
+
+    comm.execute(&Request::from(&Command::Magnet { cells: _cells() }))
+        .expect("[board_test::test_mirror_resynced_after_reconnect] Request not run after reconnection"); 
+    assert!(comm.board_state().synced, "[ERROR] Mirror not resynced after reconnect"); 
+    comm.read(&ACK).expect("[board_test::test_mirror_resynced_after_reconnect] No ACK to MAGNET"); 
+    assert_eq!(comm.board_state().magnets, _cells(), "[ERROR] MAGNET not applied after resync"); 
+}
+
+#[test]
+fn test_state_after_unread_ack() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[board_test::test_state_after_unread_ack] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    comm.execute(&Request::from(&Command::Led { colors: vec![0xFF_0000, 0x00_FF00] }))
+        .expect("[board_test::test_state_after_unread_ack] Cannot write LED"); 
+    std::thread::sleep(Duration::from_millis(200)); 
+    match comm.execute(&Request::State) {
+        Ok(Outcome::State(state)) => assert_eq!(state.leds, vec![0xFF_0000, 0x00_FF00], "[ERROR] Received ACK not applied"), 
+        res => panic!("[board_test::test_state_after_unread_ack] Unexpected outcome {res:?}"), 
+    }
+    let res = comm.read(&ACK).expect("[board_test::test_state_after_unread_ack] ACK not held for client"); 
+    assert_eq!(res.decoded, Decoded::Ack, "[ERROR] Incorrect held reply"); 
+}
@@ -0,0 +1,95 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::fs; 
+use std::time::Duration; 
+
+use serial_communicator::{ReadMode, ReadSpec, Request}; 
+use serial_communicator::board::decode_cells; 
+use serial_communicator::calibration::{Affine, Calibration, Grid, Unit}; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::simulator; 
+
+fn _cell(x: f32, y: f32) -> MagnetCell {
+    MagnetCell { x, y, on: true }
+}
+
+fn _assert_near(a: MagnetCell, b: MagnetCell) {
+    assert!((a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4 && a.on == b.on, "[ERROR] {a:?} differs from {b:?}"); 
+}
+
+/// Rotated by 90 degrees and moved by 300 mm along `y`.
+fn _calibration(unit: Unit) -> Calibration {
+    Calibration {
+        unit, 
+        transform: Affine([[0.0, 1.0, 0.0], [-1.0, 0.0, 300.0]]), 
+        grid: Some(Grid { pitch: (25.0, 20.0), origin: (12.5, 10.0) }), 
+        snap: false, 
+    }
+}
+
+#[test]
+fn test_units_to_firmware() {
+    _assert_near(_calibration(Unit::Mm).to_firmware(_cell(10.0, 20.0)), _cell(20.0, 290.0)); 
+    _assert_near(_calibration(Unit::Cm).to_firmware(_cell(1.0, 2.0)), _cell(20.0, 290.0)); 
+    // => Grid index (1, 2) lies at (37.5, 50) mm
+    _assert_near(_calibration(Unit::Grid).to_firmware(_cell(1.0, 2.0)), _cell(50.0, 262.5)); 
+
+    for unit in [Unit::Mm, Unit::Cm, Unit::Grid] {
+        let calibration = _calibration(unit); 
+        let cell = _cell(3.5, -1.25); 
+        _assert_near(calibration.from_firmware(calibration.to_firmware(cell)), cell); 
+    }
+}
+
+#[test]
+fn test_snap_to_grid() {
+    let calibration = Calibration { snap: true, .._calibration(Unit::Cm) }; 
+    _assert_near(calibration.snap(_cell(4.4, 2.4)), _cell(3.75, 3.0)); 
+
+    let instr = Command::Magnet { cells: vec![_cell(4.4, 2.4)] }.encode(); 
+    let encoded = calibration.to_firmware_instruction(&calibration.snap_instruction(&instr)); 
+    _assert_near(decode_cells(&encoded[1..])[0], _cell(30.0, 262.5)); 
+}
+
+#[test]
+fn test_invalid_calibration_rejected() {
+    let dir = std::env::temp_dir().join(format!("serial-communicator-calibration-{}", std::process::id())); 
+    fs::create_dir_all(&dir).unwrap(); 
+    let path = dir.join("calibration.json"); 
+
+    fs::write(&path, r#"{"unit": "cm", "transform": [[2, 0, 5], [0, 2, 5]]}"#).unwrap(); 
+    let calibration = Calibration::load(&path).expect("[calibration_test::test_invalid_calibration_rejected] Valid calibration rejected"); 
+    assert_eq!(calibration.unit, Unit::Cm, "[ERROR] Incorrect unit"); 
+
+    for src in [
+        r#"{"transform": [[1, 1, 0], [1, 1, 0]]}"#, 
+        r#"{"unit": "grid"}"#, 
+        r#"{"snap": true, "grid": {"pitch": [0, 10]}}"#, 
+    ] {
+        fs::write(&path, src).unwrap(); 
+        assert!(Calibration::load(&path).is_err(), "[ERROR] Invalid calibration {src} accepted"); 
+    }
+    fs::remove_dir_all(&dir).unwrap(); 
+}
+
+#[test]
+fn test_communicator_mirrors_user_units() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[calibration_test::test_communicator_mirrors_user_units] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME)
+        .with_calibration(Calibration { snap: true, .._calibration(Unit::Grid) }); 
+
+    let cells = vec![_cell(0.2, 1.0), _cell(2.0, 3.0)]; 
+    comm.execute(&Request::from(&Command::Magnet { cells }))
+        .expect("[calibration_test::test_communicator_mirrors_user_units] Cannot write MAGNET"); 
+    comm.read(&ReadSpec { mode: ReadMode::Exact(1), timeout: None })
+        .expect("[calibration_test::test_communicator_mirrors_user_units] No ACK"); 
+    let snapped = vec![_cell(0.0, 1.0), _cell(2.0, 3.0)]; 
+    assert_eq!(comm.board_state().magnets, snapped, "[ERROR] Incorrect mirrored state"); 
+
+    comm.sync_state().expect("[calibration_test::test_communicator_mirrors_user_units] Cannot sync state"); 
+    for (&a, &b) in comm.board_state().magnets.iter().zip(&snapped) { _assert_near(a, b); }
+}
@@ -0,0 +1,79 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serial_communicator::{ReadMode, ReadSpec, Request}; 
+use serial_communicator::chunk; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::response::Decoded; 
+use serial_communicator::simulator::{self, Firmware}; 
+
+fn _cells(n: usize) -> Vec<MagnetCell> {
+    #[allow(clippy::cast_precision_loss)]
+    (0..n).map(|i| MagnetCell { x: i as f32, y: 1.0, on: i % 2 == 0 }).collect()
+}
+
+#[test]
+fn test_short_instruction_not_split() {
+    let instr = Command::Led { colors: vec![0xff0000; 4] }.encode(); 
+    assert_eq!(chunk::split(&instr, 64).unwrap(), vec![instr], "[ERROR] Short LED split"); 
+}
+
+#[test]
+fn test_long_magnet_split_into_transfer() {
+    // => 3 cells, i.e. 28 bytes, per 32-byte frame
+    let instr = Command::Magnet { cells: _cells(7) }.encode(); 
+    let frames = chunk::split(&instr, 32).unwrap(); 
+    assert_eq!(frames.len(), 3, "[ERROR] Incorrect number of frames"); 
+    assert!(frames.iter().all(|f| f.len() <= 32 && chunk::continued(f).unwrap_or(f[0]) == instr[0]), "[ERROR] Invalid frame"); 
+    assert_eq!(frames.iter().map(|f| chunk::continued(f)).collect::<Vec<_>>(), vec![Some(instr[0]), Some(instr[0]), None], "[ERROR] Incorrect continuation"); 
+    assert_eq!(frames[2][0], instr[0], "[ERROR] Transfer not ended by plain MAGNET"); 
+    let args: Vec<u8> = frames.iter().flat_map(|f| f[1..].iter().copied()).collect(); 
+    assert_eq!(args, instr[1..], "[ERROR] Frames do not add up to instruction"); 
+
+    // => No empty frame if arguments fill whole frames
+    let instr = Command::Magnet { cells: _cells(6) }.encode(); 
+    let frames = chunk::split(&instr, 32).unwrap(); 
+    assert_eq!(frames.len(), 2, "[ERROR] Incorrect number of full frames"); 
+    assert_eq!(frames[1].len(), 28, "[ERROR] Transfer ended by empty frame"); 
+    let instr = Command::Magnet { cells: _cells(3) }.encode(); 
+    assert_eq!(chunk::split(&instr, 32).unwrap(), vec![instr], "[ERROR] Full frame split"); 
+}
+
+#[test]
+fn test_delta_split_without_end() {
+    let instr = Command::LedDelta { colors: (0..20).map(|i| (i, 0x00ff00)).collect() }.encode(); 
+    let frames = chunk::split(&instr, 33).unwrap(); 
+    assert_eq!(frames.len(), 3, "[ERROR] Incorrect number of frames"); 
+    assert!(frames.iter().all(|f| f.len() > 1 && f[0] == instr[0]), "[ERROR] Delta chunk continued or empty"); 
+}
+
+#[test]
+fn test_unsplittable_instruction_rejected() {
+    let mut instr = Command::Sensor.encode(); 
+    instr.extend_from_slice(&[0; 40]); 
+    assert!(chunk::split(&instr, 32).is_err(), "[ERROR] Oversized SENSOR accepted"); 
+}
+
+#[test]
+fn test_chunked_magnet_reaches_firmware() {
+    let firmware = Firmware { max_frame_len: Some(32), ..Firmware::default() }; 
+    let (port, _handle) = simulator::spawn_firmware(Duration::from_secs(1), firmware)
+        .expect("[chunk_test::test_chunked_magnet_reaches_firmware] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME).with_max_frame_len(32); 
+
+    for n in [10, 6, 3, 2] {
+        comm.execute(&Request::from(&Command::Magnet { cells: _cells(n) }))
+            .expect("[chunk_test::test_chunked_magnet_reaches_firmware] Cannot write MAGNET"); 
+        let res = comm.read(&ReadSpec { mode: ReadMode::Exact(1), timeout: None })
+            .expect("[chunk_test::test_chunked_magnet_reaches_firmware] No ACK to last frame"); 
+        assert_eq!(res.decoded, Decoded::Ack, "[ERROR] Last frame not acknowledged"); 
+        assert_eq!(comm.board_state().magnets, _cells(n), "[ERROR] Incorrect mirrored state of {n} cells"); 
+
+        comm.sync_state().expect("[chunk_test::test_chunked_magnet_reaches_firmware] Cannot sync state"); 
+        assert_eq!(comm.board_state().magnets, _cells(n), "[ERROR] Incorrect firmware state of {n} cells"); 
+    }
+}
@@ -0,0 +1,121 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serial_communicator::{ReadMode, ReadSpec, Request}; 
+use serial_communicator::color::{self, ColorCorrection}; 
+use serial_communicator::command::{Command, TypedRequest}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::simulator; 
+
+const ACK: ReadSpec = ReadSpec { mode: ReadMode::Exact(1), timeout: None }; 
+
+/// Whether `a` and `b` differ by at most 1 in each channel, i.e. up to rounding.
+fn _near(a: u32, b: u32) -> bool {
+    let (a, b) = (color::to_rgb(a), color::to_rgb(b)); 
+    a.0.abs_diff(b.0) <= 1 && a.1.abs_diff(b.1) <= 1 && a.2.abs_diff(b.2) <= 1
+}
+
+#[test]
+fn test_parse_forms() {
+    for (s, rgb) in [
+        ("#FF8000", 0xFF8000), 
+        ("0xff8000", 0xFF8000), 
+        ("16744448", 0xFF8000), 
+        ("Orange", 0xFFA500), 
+        ("rebeccapurple", 0x663399), 
+        ("rgb(255, 128, 0)", 0xFF8000), 
+        ("rgb(100%,50%,0%)", 0xFF8000), 
+        ("hsv(0, 1, 1)", 0xFF0000), 
+        ("hsv(120, 100%, 50%)", 0x008000), 
+        ("hsv(360, 0, 1)", 0xFFFFFF), 
+    ] {
+        assert_eq!(color::parse(s), Ok(rgb), "[ERROR] Incorrect colour for {s}"); 
+    }
+
+    for s in [
+        "16777216", 
+        "#FF80", 
+        "0x1FF8000", 
+        "#GG8000", 
+        "rgb(256, 0, 0)", 
+        "rgb(-1, 0, 0)", 
+        "rgb(1.5, 0, 0)", 
+        "rgb(0, 0)", 
+        "hsv(361, 1, 1)", 
+        "hsv(0, 1.5, 1)", 
+        "hsv(0, 1, 101%)", 
+        "blurple", 
+    ] {
+        assert!(color::parse(s).is_err(), "[ERROR] Invalid colour {s} accepted"); 
+    }
+}
+
+#[test]
+fn test_text_grammar() {
+    let text = Request::try_from("WRITE LED #ff0000 lime rgb(0, 0, 255) 255")
+        .expect("[color_test::test_text_grammar] Cannot parse LED colours"); 
+    let typed = Command::Led { colors: vec![0xFF0000, 0x00FF00, 0x0000FF, 0x0000FF] }; 
+    assert!(Request::from(&typed) == text, "[ERROR] LED colours encoded differently"); 
+
+    let text = Request::try_from("WRITE LED_DELTA 2 hsv(240, 1, 1) 0 white")
+        .expect("[color_test::test_text_grammar] Cannot parse LED_DELTA colours"); 
+    let typed = Command::LedDelta { colors: vec![(2, 0x0000FF), (0, 0xFFFFFF)] }; 
+    assert!(Request::from(&typed) == text, "[ERROR] LED_DELTA colours encoded differently"); 
+
+    for line in ["WRITE LED 16777216", "WRITE LED red nope", "WRITE LED rgb(0, 0, 0", "WRITE LED_DELTA 0 #1234567"] {
+        assert!(Request::try_from(line).is_err(), "[ERROR] Malformed {line} accepted"); 
+    }
+}
+
+#[test]
+fn test_typed_colors() {
+    let req: TypedRequest = serde_json::from_str(r##"{"op":"WRITE","cmd":"LED","colors":["#ff8000",255,"navy"]}"##)
+        .expect("[color_test::test_typed_colors] Cannot deserialize LED colours"); 
+    assert_eq!(req, TypedRequest::Write(Command::Led { colors: vec![0xFF8000, 0xFF, 0x000080] }), "[ERROR] Incorrect colours"); 
+
+    for json in [
+        r#"{"op":"WRITE","cmd":"LED","colors":[16777216]}"#, 
+        r#"{"op":"WRITE","cmd":"LED_DELTA","colors":[[0,"rgb(0,0,300)"]]}"#, 
+    ] {
+        assert!(serde_json::from_str::<TypedRequest>(json).is_err(), "[ERROR] Invalid colour accepted in {json}"); 
+    }
+}
+
+#[test]
+fn test_correction() {
+    assert!(ColorCorrection::default().is_identity(), "[ERROR] Default correction not identity"); 
+    assert!(ColorCorrection::new(1.5, 1.0).is_err() && ColorCorrection::new(1.0, 0.0).is_err(), "[ERROR] Invalid correction accepted"); 
+
+    let half = ColorCorrection::new(0.5, 1.0).unwrap(); 
+    assert_eq!(half.apply(0xFF8000), 0x804000, "[ERROR] Incorrect brightness"); 
+    let gamma = ColorCorrection::new(1.0, 2.0).unwrap(); 
+    assert_eq!(gamma.apply(0xFF8000), 0xFF4000, "[ERROR] Incorrect gamma"); 
+    for rgb in [0xFF8000, 0x123456, 0x000000] {
+        assert!(_near(half.invert(half.apply(rgb)), rgb), "[ERROR] Inverse off by more than rounding for {rgb:#08x}"); 
+    }
+
+    let instr = Command::LedDelta { colors: vec![(7, 0xFF8000)] }.encode(); 
+    assert_eq!(half.apply_instruction(&instr), Command::LedDelta { colors: vec![(7, 0x804000)] }.encode(), "[ERROR] Incorrect LED_DELTA correction"); 
+    let magnet = Command::Magnet { cells: Vec::new() }.encode(); 
+    assert_eq!(half.apply_instruction(&magnet), magnet, "[ERROR] MAGNET corrected"); 
+}
+
+#[test]
+fn test_communicator_corrects_colors() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[color_test::test_communicator_corrects_colors] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME)
+        .with_color_correction(ColorCorrection::new(0.5, 1.0).unwrap()); 
+
+    comm.execute(&Request::try_from("WRITE LED #ff8000 #000000").unwrap())
+        .expect("[color_test::test_communicator_corrects_colors] Cannot write LED"); 
+    comm.read(&ACK).expect("[color_test::test_communicator_corrects_colors] No ACK"); 
+    assert_eq!(comm.board_state().leds, vec![0xFF8000, 0], "[ERROR] Mirror not in requested colours"); 
+
+    comm.sync_state().expect("[color_test::test_communicator_corrects_colors] Cannot sync state"); 
+    let leds = comm.board_state().leds.clone(); 
+    assert!(leds.len() == 2 && _near(leds[0], 0xFF8000) && leds[1] == 0, "[ERROR] Firmware colours not corrected: {leds:x?}"); 
+}
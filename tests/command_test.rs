@@ -0,0 +1,98 @@
+extern crate serial_communicator;
+
+use std::time::Duration;
+
+use serial_communicator::{ReadMode, ReadSpec, Request};
+use serial_communicator::command::{Command, MagnetCell, TypedRequest};
+
+#[test]
+fn test_typed_magnet_matches_text_grammar() {
+    let typed = Command::Magnet {
+        cells: vec![
+            MagnetCell { x: 1.0, y: 2.0, on: true },
+            MagnetCell { x: -0.5, y: 3.25, on: false },
+        ]
+    };
+    let text = Request::try_from("WRITE MAGNET 1.0 2.0 true -0.5 3.25 false")
+        .expect("[command_test] Cannot parse MAGNET text request");
+    assert!(
+        Request::from(&typed) == text,
+        "[ERROR] Typed MAGNET command encoded differently from text grammar"
+    );
+}
+
+#[test]
+fn test_typed_led_matches_text_grammar() {
+    let typed = Command::Led { colors: vec![0x00ff_8000, 0x0000_00ff] };
+    let text = Request::try_from("WRITE LED 16744448 255")
+        .expect("[command_test] Cannot parse LED text request");
+    assert!(
+        Request::from(&typed) == text,
+        "[ERROR] Typed LED command encoded differently from text grammar"
+    );
+}
+
+#[test]
+fn test_typed_request_from_json() {
+    let req: TypedRequest = serde_json::from_str(
+        r#"{"op":"WRITE","cmd":"MAGNET","cells":[{"x":1.0,"y":2.0,"on":true}]}"#
+    ).expect("[command_test] Cannot deserialize WRITE request");
+    assert_eq!(
+        req,
+        TypedRequest::Write(Command::Magnet { cells: vec![MagnetCell { x: 1.0, y: 2.0, on: true }] })
+    );
+
+    let req: TypedRequest = serde_json::from_str(r#"{"op":"READ"}"#)
+        .expect("[command_test] Cannot deserialize READ request");
+    assert_eq!(req, TypedRequest::READ);
+}
+
+#[test]
+fn test_read_variants_from_text_grammar() {
+    let cases = [
+        ("READ", ReadSpec::default()), 
+        ("READ 16", ReadSpec { mode: ReadMode::Exact(16), timeout: None }), 
+        ("READ UNTIL 0x0a", ReadSpec { mode: ReadMode::Until(b'\n'), timeout: None }), 
+        ("READ UNTIL 10", ReadSpec { mode: ReadMode::Until(b'\n'), timeout: None }), 
+        ("READ FRAME", ReadSpec { mode: ReadMode::Frame, timeout: None }), 
+        (
+            "READ TIMEOUT 250", 
+            ReadSpec { mode: ReadMode::Available, timeout: Some(Duration::from_millis(250)) }
+        ), 
+        (
+            "READ 4 TIMEOUT 5", 
+            ReadSpec { mode: ReadMode::Exact(4), timeout: Some(Duration::from_millis(5)) }
+        ), 
+    ]; 
+    for (text, spec) in cases {
+        match Request::try_from(text) {
+            Ok(Request::Read(s)) => assert_eq!(s, spec, "[ERROR] Incorrect READ spec for \"{text}\""), 
+            _ => panic!("[command_test] Cannot parse \"{text}\" as READ"), 
+        }
+    }
+
+    for text in ["READ UNTIL", "READ UNTIL 256", "READ -1", "READ TIMEOUT", "READ FRAME 4"] {
+        assert!(Request::try_from(text).is_err(), "[ERROR] Accepted malformed \"{text}\""); 
+    }
+}
+
+#[test]
+fn test_non_finite_magnet_rejected() {
+    for text in [
+        "WRITE MAGNET inf NaN true",
+        "WRITE MAGNET 1.0 2.0 true -inf 0.0 false",
+        "WRITE MAGNET_DELTA 0 NaN 1.0 true",
+    ] {
+        assert!(Request::try_from(text).is_err(), "[ERROR] Non-finite coordinates accepted in {text:?}");
+    }
+
+    let cell = MagnetCell { x: f32::NAN, y: 1.0, on: true };
+    for cmd in [Command::Magnet { cells: vec![cell] }, Command::MagnetDelta { cells: vec![(0, cell)] }] {
+        assert!(
+            Request::try_from(&TypedRequest::Write(cmd)).is_err(),
+            "[ERROR] Non-finite typed MAGNET accepted"
+        );
+    }
+    let cell = MagnetCell { x: 1.0, y: 2.0, on: true };
+    assert!(Request::try_from(&TypedRequest::Write(Command::Magnet { cells: vec![cell] })).is_ok());
+}
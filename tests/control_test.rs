@@ -0,0 +1,66 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::sync::mpsc; 
+use std::thread; 
+use std::time::Duration; 
+
+use serial_communicator::{ReadMode, ReadSpec}; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::control::{CommandQueue, ControlLoop}; 
+use serial_communicator::format::Format; 
+use serial_communicator::shutdown::Interrupt; 
+use serial_communicator::simulator; 
+
+fn _magnet(x: f32) -> Vec<u8> {
+    Command::Magnet { cells: vec![MagnetCell { x, y: 0.0, on: true }] }.encode()
+}
+
+#[test]
+fn test_queue_coalesces_by_opcode() {
+    let mut queue = CommandQueue::default(); 
+    assert!(!queue.push(_magnet(1.0)), "[ERROR] Nothing to supersede yet"); 
+    assert!(!queue.push(Command::Led { colors: vec![0xff0000] }.encode()), "[ERROR] LED superseded MAGNET"); 
+    assert!(queue.push(_magnet(2.0)), "[ERROR] Later MAGNET did not supersede earlier one"); 
+    assert_eq!(
+        queue.drain(), 
+        vec![_magnet(2.0), Command::Led { colors: vec![0xff0000] }.encode()], 
+        "[ERROR] Incorrect queue after coalescing"
+    ); 
+    assert!(queue.is_empty(), "[ERROR] Queue not drained"); 
+}
+
+#[test]
+fn test_loop_runs_at_rate() {
+    let (port, _) = simulator::spawn(Duration::from_secs(1))
+        .expect("[control_test::test_loop_runs_at_rate] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+    let (tx, rx) = mpsc::channel(); 
+    tx.send(_magnet(1.0)).unwrap(); 
+    tx.send(_magnet(5.0)).unwrap(); 
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(500)); 
+        drop(tx); 
+    }); 
+
+    let mut out: Vec<u8> = Vec::new(); 
+    let mut ctrl = ControlLoop::new(&mut comm, Duration::from_millis(20))
+        .with_sensor_reply(ReadSpec { mode: ReadMode::Exact(13), timeout: Some(Duration::from_millis(20)) }); 
+    ctrl.run(&rx, Format::Jsonl, &mut out, &Interrupt::default())
+        .expect("[control_test::test_loop_runs_at_rate] Control loop failed"); 
+
+    let stats = ctrl.stats().clone(); 
+    assert_eq!(stats.coalesced, 1, "[ERROR] Superseded MAGNET not coalesced"); 
+    assert_eq!(stats.errors, 0, "[ERROR] Ticks failed"); 
+    assert!((20..=30).contains(&stats.ticks), "[ERROR] Expected ~25 ticks in 500 ms, got {}", stats.ticks); 
+
+    let last_reading = String::from_utf8(out).unwrap()
+        .lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .filter(|v| v["decoded"]["kind"] == "SENSOR")
+        .last()
+        .expect("[control_test::test_loop_runs_at_rate] No SENSOR reading published"); 
+    assert_eq!(last_reading["decoded"]["readings"][1], 5.0, "[ERROR] Latest MAGNET not applied"); 
+}
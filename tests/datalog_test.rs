@@ -0,0 +1,70 @@
+extern crate serial_communicator; 
+
+use std::fs; 
+use std::path::PathBuf; 
+
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::datalog::{LogFormat, Rotation, SensorLog}; 
+use serial_communicator::response::Response; 
+
+fn _sensor(readings: &[f32]) -> Response {
+    let mut raw = vec![Command::Sensor.encode()[0]]; 
+    for r in readings { raw.extend_from_slice(&r.to_le_bytes()); }
+    Response::new("arduino", raw)
+}
+
+fn _log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("serial-communicator-{name}-{}", std::process::id())); 
+    let _ = fs::remove_dir_all(&dir); 
+    fs::create_dir_all(&dir).expect("[datalog_test::log_dir] Cannot create log directory"); 
+    dir
+}
+
+#[test]
+fn test_csv_log_with_commands_and_rotation() {
+    let dir = _log_dir("csv"); 
+    let mut log = SensorLog::new(&dir.join("run.csv"), LogFormat::Csv)
+        .with_rotation(Rotation { max_rows: Some(2), max_age: None }); 
+    let magnet = Command::Magnet { cells: vec![MagnetCell { x: 1.5, y: 2.0, on: true }] }; 
+    log.record_response(&_sensor(&[0.0, 0.0])).unwrap(); 
+    log.record_command(&magnet.encode()); 
+    log.record_command(&Command::Led { colors: vec![255] }.encode()); 
+    log.record_response(&_sensor(&[1.0, 1.5])).unwrap(); 
+    log.record_command(&Command::Quit.encode()); 
+    log.record_response(&_sensor(&[0.0, 0.0])).unwrap(); 
+    log.close().unwrap(); 
+
+    let first = fs::read_to_string(dir.join("run.csv")).unwrap(); 
+    let lines: Vec<Vec<&str>> = first.lines().map(|l| l.split(',').collect()).collect(); 
+    assert_eq!(lines[0], ["timestamp", "device", "reading_0", "reading_1", "magnet", "led"], "[ERROR] Incorrect header"); 
+    assert_eq!(lines[1][1..], ["arduino", "0", "0", "", ""], "[ERROR] Incorrect row before commands"); 
+    assert_eq!(lines[2][1..], ["arduino", "1", "1.5", "1.5 2 true", "255"], "[ERROR] Commands in effect not logged"); 
+    assert_eq!(lines.len(), 3, "[ERROR] Log not rotated after 2 rows"); 
+
+    let second = fs::read_to_string(dir.join("run.1.csv")).unwrap(); 
+    assert_eq!(second.lines().count(), 2, "[ERROR] Incorrect rotated log:\n{second}"); 
+    assert!(second.lines().nth(1).unwrap().ends_with(",0,0,,"), "[ERROR] QUIT did not clear commands in effect"); 
+    let _ = fs::remove_dir_all(&dir); 
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_parquet_log() {
+    use parquet::file::reader::{FileReader, SerializedFileReader}; 
+
+    let dir = _log_dir("parquet"); 
+    let path = dir.join("run.parquet"); 
+    let mut log = SensorLog::new(&path, LogFormat::Parquet); 
+    for i in 0..3 {
+        #[allow(clippy::cast_precision_loss)]
+        log.record_response(&_sensor(&[i as f32])).unwrap(); 
+    }
+    log.close().unwrap(); 
+
+    let reader = SerializedFileReader::new(fs::File::open(&path).unwrap())
+        .expect("[datalog_test::test_parquet_log] Cannot read Parquet log"); 
+    let meta = reader.metadata().file_metadata(); 
+    assert_eq!(meta.num_rows(), 3, "[ERROR] Incorrect number of rows"); 
+    assert_eq!(meta.schema_descr().num_columns(), 5, "[ERROR] Incorrect number of columns"); 
+    let _ = fs::remove_dir_all(&dir); 
+}
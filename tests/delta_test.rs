@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serial_communicator::board::BoardState; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::control::ControlLoop; 
+use serial_communicator::delta::DeltaEncoder; 
+use serial_communicator::simulator; 
+
+fn _cells(n: usize) -> Vec<MagnetCell> {
+    #[allow(clippy::cast_precision_loss)]
+    (0..n).map(|i| MagnetCell { x: i as f32, y: 0.0, on: false }).collect()
+}
+
+fn _synced(cells: Vec<MagnetCell>) -> BoardState {
+    BoardState { magnets: cells, leds: vec![0; 4], synced: true }
+}
+
+/// Encoder whose first full send is behind it, so that deltas apply.
+fn _encoder(acked: &BoardState) -> DeltaEncoder {
+    let mut encoder = DeltaEncoder::new().with_full_refresh(Duration::from_secs(60)); 
+    encoder.encode(acked, &Command::Magnet { cells: acked.magnets.clone() }.encode()); 
+    encoder.encode(acked, &Command::Led { colors: acked.leds.clone() }.encode()); 
+    encoder
+}
+
+#[test]
+fn test_only_changes_sent() {
+    let acked = _synced(_cells(8)); 
+    let mut encoder = _encoder(&acked); 
+
+    let unchanged = Command::Magnet { cells: _cells(8) }.encode(); 
+    assert!(encoder.encode(&acked, &unchanged).is_empty(), "[ERROR] Unchanged MAGNET sent"); 
+
+    let mut cells = _cells(8); 
+    cells[5].on = true; 
+    assert_eq!(
+        encoder.encode(&acked, &Command::Magnet { cells: cells.clone() }.encode()), 
+        vec![Command::MagnetDelta { cells: vec![(5, cells[5])] }.encode()], 
+        "[ERROR] Incorrect MAGNET delta"
+    ); 
+    assert_eq!(
+        encoder.encode(&acked, &Command::Led { colors: vec![0, 0, 0xff00ff, 0] }.encode()), 
+        vec![Command::LedDelta { colors: vec![(2, 0xff00ff)] }.encode()], 
+        "[ERROR] Incorrect LED delta"
+    ); 
+}
+
+#[test]
+fn test_large_delta_split_across_frames() {
+    let acked = _synced(_cells(40)); 
+    let mut encoder = _encoder(&acked).with_max_frame_len(32); 
+    let cells: Vec<MagnetCell> = _cells(40).into_iter().map(|c| MagnetCell { on: true, ..c }).collect(); 
+    let frames = encoder.encode(&acked, &Command::Magnet { cells }.encode()); 
+
+    // => 3 cells of 10 bytes per 32-byte frame
+    assert_eq!(frames.len(), 14, "[ERROR] Incorrect number of frames"); 
+    assert!(frames.iter().all(|f| f.len() <= 32), "[ERROR] Frame too long"); 
+    let mut state = acked.clone(); 
+    for f in &frames { state.apply(f); }
+    assert!(state.magnets.iter().all(|c| c.on), "[ERROR] Frames do not add up to the update"); 
+}
+
+#[test]
+fn test_full_sent_when_deltas_do_not_apply() {
+    let mut unsynced = _synced(_cells(4)); 
+    unsynced.synced = false; 
+    let full = Command::Magnet { cells: _cells(4) }.encode(); 
+    let mut encoder = _encoder(&_synced(_cells(4))); 
+    assert_eq!(encoder.encode(&unsynced, &full), vec![full.clone()], "[ERROR] Delta sent while not synced"); 
+
+    let resized = Command::Magnet { cells: _cells(5) }.encode(); 
+    assert_eq!(encoder.encode(&_synced(_cells(4)), &resized), vec![resized.clone()], "[ERROR] Delta sent on resize"); 
+
+    let mut encoder = DeltaEncoder::new().with_full_refresh(Duration::ZERO); 
+    assert_eq!(encoder.encode(&_synced(_cells(4)), &full), vec![full.clone()], "[ERROR] Refresh not sent in full"); 
+    assert_eq!(encoder.take_due_refreshes(), vec![full], "[ERROR] Due refresh not taken"); 
+}
+
+#[test]
+fn test_loop_sends_deltas() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[delta_test::test_loop_sends_deltas] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+    comm.sync_state().expect("[delta_test::test_loop_sends_deltas] Cannot sync state"); 
+
+    let mut cells = _cells(20); 
+    let mut ctrl = ControlLoop::new(&mut comm, Duration::from_millis(200)).with_delta(DeltaEncoder::new()); 
+    for i in [3, 17] {
+        cells[i].on = true; 
+        ctrl.push(Command::Magnet { cells: cells.clone() }.encode()); 
+        ctrl.tick().expect("[delta_test::test_loop_sends_deltas] Tick failed"); 
+    }
+    drop(ctrl); 
+
+    assert_eq!(comm.board_state().magnets, cells, "[ERROR] Incorrect mirrored state"); 
+    comm.sync_state().expect("[delta_test::test_loop_sends_deltas] Cannot resync state"); 
+    assert_eq!(comm.board_state().magnets, cells, "[ERROR] Firmware state differs from mirror"); 
+}
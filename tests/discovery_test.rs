@@ -0,0 +1,51 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::io::{self, Write}; 
+use std::time::{Duration, Instant}; 
+
+use serialport::TTYPort; 
+use serial_communicator::discovery::wait_until_ready; 
+use serial_communicator::simulator; 
+
+#[test]
+fn test_ready_on_handshake_reply() {
+    let (mut port, _) = simulator::spawn(Duration::from_secs(1))
+        .expect("[discovery_test::test_ready_on_handshake_reply] Cannot spawn simulator"); 
+    let start = Instant::now(); 
+    wait_until_ready(port.as_mut(), None, Duration::from_secs(2))
+        .expect("[discovery_test::test_ready_on_handshake_reply] Simulator not ready"); 
+    assert!(start.elapsed() < Duration::from_secs(1), "[ERROR] Readiness detection slower than a fixed sleep"); 
+}
+
+#[test]
+fn test_ready_on_boot_banner() {
+    let (mut host, mut device) = TTYPort::pair()
+        .expect("[discovery_test::test_ready_on_boot_banner] Cannot open pty pair"); 
+    device.write_all(b"booting...\r\nREADY\r\n")
+        .expect("[discovery_test::test_ready_on_boot_banner] Cannot write banner"); 
+    wait_until_ready(&mut host, Some(b"READY"), Duration::from_secs(2))
+        .expect("[discovery_test::test_ready_on_boot_banner] Banner not detected"); 
+}
+
+#[test]
+fn test_not_ready_times_out() {
+    let (mut host, _device) = TTYPort::pair()
+        .expect("[discovery_test::test_not_ready_times_out] Cannot open pty pair"); 
+    let err = wait_until_ready(&mut host, Some(b"READY"), Duration::from_millis(600))
+        .expect_err("[discovery_test::test_not_ready_times_out] Silent device reported ready"); 
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut, "[ERROR] Expected time-out"); 
+}
+
+#[test]
+fn test_handshake_amid_noise_not_ready() {
+    let (mut host, mut device) = TTYPort::pair()
+        .expect("[discovery_test::test_handshake_amid_noise_not_ready] Cannot open pty pair"); 
+    // => HANDSHAKE byte followed by more noise, and no reply
+    device.write_all(&[0x00, 0x10, 0x7f, 0x3a])
+        .expect("[discovery_test::test_handshake_amid_noise_not_ready] Cannot write noise"); 
+    let err = wait_until_ready(&mut host, None, Duration::from_millis(600))
+        .expect_err("[discovery_test::test_handshake_amid_noise_not_ready] Noise taken for handshake reply"); 
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut, "[ERROR] Expected time-out"); 
+}
@@ -0,0 +1,46 @@
+extern crate serial_communicator; 
+
+use serial_communicator::format::{Format, Status}; 
+use serial_communicator::response::{Decoded, Response}; 
+
+const TEST_DEVICE: &str = "/dev/ttyACM0"; 
+const TEST_TIMESTAMP: u64 = 0x0123_4567_89ab_cdef; 
+
+#[test]
+fn test_framed_response_layout() {
+    let res = Response {
+        device: TEST_DEVICE.to_owned(), 
+        timestamp: TEST_TIMESTAMP, 
+        raw: vec![0x20, 0xca, 0xfe], 
+        decoded: Decoded::Ack, 
+    }; 
+    let mut out: Vec<u8> = Vec::new(); 
+    Format::Framed.write_response(&mut out, &res)
+        .expect("[format_test::test_framed_response_layout] Cannot write frame"); 
+
+    assert_eq!(&out[0..4], &3_u32.to_le_bytes(), "[ERROR] Incorrect payload length"); 
+    assert_eq!(out[4], Status::Ok as u8, "[ERROR] Incorrect status"); 
+    assert_eq!(out[5] as usize, TEST_DEVICE.len(), "[ERROR] Incorrect device name length"); 
+    assert_eq!(&out[6..14], &TEST_TIMESTAMP.to_le_bytes(), "[ERROR] Incorrect timestamp"); 
+    assert_eq!(&out[14..14 + TEST_DEVICE.len()], TEST_DEVICE.as_bytes(), "[ERROR] Incorrect device name"); 
+    assert_eq!(&out[14 + TEST_DEVICE.len()..], &res.raw, "[ERROR] Incorrect payload"); 
+}
+
+#[test]
+fn test_framed_timeout_status() {
+    let mut out: Vec<u8> = Vec::new(); 
+    Format::Framed.write_error(&mut out, Status::Timeout, TEST_DEVICE, "timed out")
+        .expect("[format_test::test_framed_timeout_status] Cannot write frame"); 
+
+    assert_eq!(&out[0..4], &9_u32.to_le_bytes(), "[ERROR] Incorrect payload length"); 
+    assert_eq!(out[4], Status::Timeout as u8, "[ERROR] Incorrect status"); 
+    assert_eq!(&out[out.len() - 9..], b"timed out", "[ERROR] Incorrect payload"); 
+}
+
+#[test]
+fn test_text_timeout_line() {
+    let mut out: Vec<u8> = Vec::new(); 
+    Format::Text.write_error(&mut out, Status::Timeout, TEST_DEVICE, "timed out")
+        .expect("[format_test::test_text_timeout_line] Cannot write error"); 
+    assert_eq!(out, b"TIMEOUT: timed out\n", "[ERROR] Incorrect error line"); 
+}
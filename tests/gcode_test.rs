@@ -0,0 +1,101 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::{Duration, Instant}; 
+
+use serial_communicator::command::{Command, MagnetCell, TypedRequest}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::gcode::{GcodeOptions, Program}; 
+use serial_communicator::shutdown::Interrupt; 
+use serial_communicator::simulator; 
+
+fn _parse(src: &str) -> Program {
+    Program::parse(src, GcodeOptions { leds: 3, ..GcodeOptions::default() })
+        .expect("[gcode_test::parse] Cannot parse program")
+}
+
+fn _magnet(x: f32, y: f32, on: bool) -> TypedRequest {
+    TypedRequest::Write(Command::Magnet { cells: vec![MagnetCell { x, y, on }] })
+}
+
+#[test]
+fn test_parse_moves() {
+    let program = _parse("\
+        ; square corner\n\
+        N10 G21 G90 (mm, absolute)\n\
+        G0 X10 Y0\n\
+        M3\n\
+        G1 X10 Y60 F1200 *42\n\
+        G4 P250\n\
+        g1x40\n\
+        M5\n\
+        M30\n\
+        G1 X0 Y0\n"); 
+    let requests: Vec<_> = program.steps.iter().map(|s| (s.at_ms, s.line, &s.request)).collect(); 
+    assert_eq!(requests.len(), 4, "[ERROR] Incorrect number of steps: {requests:?}"); 
+    assert_eq!((requests[0].0, requests[0].1, requests[0].2), (0, 4, &_magnet(10.0, 0.0, true)), "[ERROR] Magnet not on at rapid target"); 
+
+    // => 60 mm at 20 mm/s, then 30 mm at the same feed rate after dwelling
+    let TypedRequest::Move(first) = requests[1].2 else { panic!("[ERROR] G1 not parsed into MOVE") }; 
+    assert_eq!((first.waypoints.clone(), first.duration_ms), (vec![(10.0, 0.0), (10.0, 60.0)], 3000), "[ERROR] Incorrect first move"); 
+    let TypedRequest::Move(second) = requests[2].2 else { panic!("[ERROR] Modal G1 not parsed into MOVE") }; 
+    assert_eq!((requests[2].0, second.waypoints.clone(), second.duration_ms), (3250, vec![(10.0, 60.0), (40.0, 60.0)], 1500), "[ERROR] Incorrect second move"); 
+    assert_eq!((requests[3].0, requests[3].2), (4750, &_magnet(40.0, 60.0, false)), "[ERROR] Magnet not off at end"); 
+    assert_eq!(program.duration, Duration::from_millis(4750), "[ERROR] Blocks after M30 not ignored"); 
+}
+
+#[test]
+fn test_units_and_relative() {
+    let program = _parse("G20 G91\nG0 X1 Y2\nG0 X1\nM3\nG21\nG1 X-10 F600\n"); 
+    let TypedRequest::Move(t) = &program.steps[1].request else { panic!("[ERROR] G1 not parsed into MOVE") }; 
+    assert_eq!(t.waypoints, vec![(50.8, 50.8), (40.8, 50.8)], "[ERROR] Incorrect inch and relative coordinates"); 
+    assert_eq!(t.duration_ms, 1000, "[ERROR] Incorrect duration"); 
+}
+
+#[test]
+fn test_leds_and_errors() {
+    let program = _parse("M150 R255 U10\nM150 B7 I2\n"); 
+    assert_eq!(program.steps[0].request, TypedRequest::Write(Command::Led { colors: vec![0xFF_0A00; 3] }), "[ERROR] Incorrect LED"); 
+    assert_eq!(program.steps[1].request, TypedRequest::Write(Command::LedDelta { colors: vec![(2, 7)] }), "[ERROR] Incorrect LED_DELTA"); 
+
+    for (src, line) in [
+        ("G0 X1\nM150 R256\n", 2), 
+        ("G1 X1 F0\n", 1), 
+        ("\n\nG28\n", 3), 
+        ("M106 S255\n", 1), 
+        ("G0 X1 (unclosed\n", 1), 
+        ("G4\n", 1), 
+        ("G0 X1e\n", 1), 
+    ] {
+        let err = Program::parse(src, GcodeOptions::default()).expect_err(&format!("[ERROR] Malformed {src:?} accepted")); 
+        assert_eq!(err.line, line, "[ERROR] Incorrect line for {src:?}: {err}"); 
+    }
+}
+
+#[test]
+fn test_preview() {
+    let program = _parse("M3\nG4 S0.5\nM150 R1 I0\n"); 
+    let mut out = Vec::new(); 
+    program.write_preview(&mut out).expect("[gcode_test::test_preview] Cannot write preview"); 
+    let lines: Vec<_> = String::from_utf8(out).unwrap().lines().map(String::from).collect(); 
+    assert_eq!(lines.len(), 2, "[ERROR] Incorrect number of lines"); 
+    assert_eq!(lines[1], r#"{"at_ms":500,"line":3,"op":"WRITE","cmd":"LED_DELTA","colors":[[0,65536]]}"#, "[ERROR] Incorrect preview"); 
+    let step: serde_json::Value = serde_json::from_str(&lines[0]).unwrap(); 
+    assert_eq!(step["cells"][0]["on"], true, "[ERROR] Incorrect preview of MAGNET"); 
+}
+
+#[test]
+fn test_run_on_simulator() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[gcode_test::test_run_on_simulator] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    let program = _parse("G0 X5 Y5\nM3\nG1 X15 F3000\nG4 P100\nM150 R9\n"); 
+    let start = Instant::now(); 
+    program.run(&mut comm, &Interrupt::default()).expect("[gcode_test::test_run_on_simulator] Cannot run program"); 
+    assert!(start.elapsed() >= Duration::from_millis(300), "[ERROR] Steps not timed"); 
+    let state = comm.board_state(); 
+    assert_eq!(state.magnets, vec![MagnetCell { x: 15.0, y: 5.0, on: true }], "[ERROR] Magnet not at end of program"); 
+    assert_eq!(state.leds, vec![9 << 16; 3], "[ERROR] LEDs not set"); 
+}
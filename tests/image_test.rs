@@ -0,0 +1,195 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::io::Cursor; 
+use std::time::Duration; 
+
+use ::image::{ImageFormat, RgbaImage}; 
+
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::image::{self, Image, LedLayout, Order, Origin, Sequence}; 
+use serial_communicator::shutdown::Interrupt; 
+use serial_communicator::simulator; 
+
+const RED: [u8; 4] = [255, 0, 0, 255]; 
+const GREEN: [u8; 4] = [0, 255, 0, 255]; 
+const BLUE: [u8; 4] = [0, 0, 255, 255]; 
+
+/// BMP of `bits` per pixel with a BITMAPINFOHEADER, followed by `palette` and `data`.
+fn _bmp(width: i32, height: i32, bits: u16, palette: &[u8], data: &[u8]) -> Vec<u8> {
+    let offset = 54 + palette.len() as u32; 
+    let mut bmp = b"BM".to_vec(); 
+    bmp.extend_from_slice(&(offset + data.len() as u32).to_le_bytes()); 
+    bmp.extend_from_slice(&[0; 4]); 
+    bmp.extend_from_slice(&offset.to_le_bytes()); 
+    bmp.extend_from_slice(&40_u32.to_le_bytes()); 
+    bmp.extend_from_slice(&width.to_le_bytes()); 
+    bmp.extend_from_slice(&height.to_le_bytes()); 
+    bmp.extend_from_slice(&1_u16.to_le_bytes()); 
+    bmp.extend_from_slice(&bits.to_le_bytes()); 
+    bmp.extend_from_slice(&[0; 16]); 
+    bmp.extend_from_slice(&(palette.len() as u32 / 4).to_le_bytes()); 
+    bmp.extend_from_slice(&[0; 4]); 
+    bmp.extend_from_slice(palette); 
+    bmp.extend_from_slice(data); 
+    bmp
+}
+
+/// PNG of `width` by `height` RGBA `pixels`.
+fn _png(width: u32, height: u32, pixels: &[[u8; 4]]) -> Vec<u8> {
+    let rgba = RgbaImage::from_raw(width, height, pixels.concat()).unwrap(); 
+    let mut png = Cursor::new(Vec::new()); 
+    rgba.write_to(&mut png, ImageFormat::Png).expect("[image_test::png] Cannot encode PNG"); 
+    png.into_inner()
+}
+
+/// GIF image data of 3-bit `codes`, for a 2-bit palette.
+fn _lzw(codes: &[u8]) -> Vec<u8> {
+    let (mut data, mut acc, mut bits) = (Vec::new(), 0_u32, 0); 
+    for &code in codes {
+        acc |= u32::from(code) << bits; 
+        bits += 3; 
+        while bits >= 8 {
+            data.push(acc as u8); 
+            (acc, bits) = (acc >> 8, bits - 8); 
+        }
+    }
+    if bits > 0 { data.push(acc as u8); }
+    data
+}
+
+/// GIF of `width` by 1 pixels with a palette of black, red, green and blue, and one frame per
+/// graphic control byte, delay in hundredths and LZW codes of `frames`.
+fn _gif(width: u8, frames: &[(u8, u8, &[u8])]) -> Vec<u8> {
+    let mut gif = b"GIF89a".to_vec(); 
+    gif.extend_from_slice(&[width, 0, 1, 0, 0x81, 0, 0]); 
+    gif.extend_from_slice(&[0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255]); 
+    for &(control, delay, codes) in frames {
+        gif.extend_from_slice(&[0x21, 0xF9, 4, control, delay, 0, 0, 0]); 
+        gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, width, 0, 1, 0, 0, 2]); 
+        let data = _lzw(codes); 
+        gif.push(data.len() as u8); 
+        gif.extend_from_slice(&data); 
+        gif.push(0); 
+    }
+    gif.push(0x3B); 
+    gif
+}
+
+fn _decode_one(bytes: &[u8]) -> Image {
+    let mut frames = image::decode(bytes).expect("[image_test::decode_one] Cannot decode image"); 
+    assert_eq!(frames.len(), 1, "[ERROR] Still image decoded into several frames"); 
+    assert_eq!(frames[0].delay, Duration::ZERO, "[ERROR] Still image has a delay"); 
+    frames.remove(0).image
+}
+
+#[test]
+fn test_layout() {
+    let layout = LedLayout::new(3, 2); 
+    assert_eq!((0..2).flat_map(|y| (0..3).map(move |x| layout.index(x, y))).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5], "[ERROR] Incorrect row-major order"); 
+    let layout = layout.with_order(Order::Serpentine); 
+    assert_eq!((0..2).flat_map(|y| (0..3).map(move |x| layout.index(x, y))).collect::<Vec<_>>(), vec![0, 1, 2, 5, 4, 3], "[ERROR] Incorrect serpentine order"); 
+    let layout = layout.with_origin(Origin::BottomRight); 
+    assert_eq!((0..2).flat_map(|y| (0..3).map(move |x| layout.index(x, y))).collect::<Vec<_>>(), vec![3, 4, 5, 2, 1, 0], "[ERROR] Incorrect serpentine order from bottom right"); 
+
+    let parsed: LedLayout = serde_json::from_str(r#"{"width":3,"height":2,"order":"serpentine","origin":"bottom_right"}"#).unwrap(); 
+    assert_eq!(parsed, layout, "[ERROR] Incorrect layout parsed"); 
+    let parsed: LedLayout = serde_json::from_str(r#"{"width":3,"height":2}"#).unwrap(); 
+    assert_eq!(parsed, LedLayout::new(3, 2), "[ERROR] Incorrect default layout"); 
+    for (width, height) in [(0, 4), (16, 16), (usize::MAX, 2)] {
+        assert!(LedLayout::new(width, height).validate().is_err(), "[ERROR] Invalid layout {width}x{height} accepted"); 
+    }
+}
+
+#[test]
+fn test_decode_bmp() {
+    // => Bottom-up rows, each padded to 4 bytes
+    let bmp = _bmp(2, 2, 24, &[], &[0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 10, 20, 30, 0, 0]); 
+    let img = _decode_one(&bmp); 
+    assert_eq!((img.width, img.height), (2, 2), "[ERROR] Incorrect size"); 
+    assert_eq!(img.pixels, vec![BLUE, [30, 20, 10, 255], RED, GREEN], "[ERROR] Incorrect 24-bit pixels"); 
+
+    // => Top-down, with a palette of 2
+    let bmp = _bmp(3, -1, 8, &[255, 0, 0, 0, 0, 0, 255, 0], &[1, 0, 1, 0]); 
+    assert_eq!(_decode_one(&bmp).pixels, vec![RED, BLUE, RED], "[ERROR] Incorrect 8-bit pixels"); 
+
+    assert!(image::decode(&bmp[..40]).is_err(), "[ERROR] Truncated BMP accepted"); 
+}
+
+#[test]
+fn test_decode_png() {
+    let pixels = [[10, 20, 30, 255], [20, 40, 60, 128], [11, 21, 31, 255], [25, 45, 65, 0]]; 
+    let img = _decode_one(&_png(2, 2, &pixels)); 
+    assert_eq!((img.width, img.height, img.pixels.clone()), (2, 2, pixels.to_vec()), "[ERROR] Incorrect RGBA pixels"); 
+    assert_eq!(img.rgb(1, 0), 0x0A_141E, "[ERROR] Translucent pixel not over black"); 
+    assert_eq!(img.rgb(1, 1), 0, "[ERROR] Transparent pixel not black"); 
+
+    let png = _png(2, 2, &pixels); 
+    assert!(image::decode(&png[..png.len() - 20]).is_err(), "[ERROR] Truncated PNG accepted"); 
+    assert!(image::decode(b"JFIF").is_err(), "[ERROR] Unsupported format accepted"); 
+}
+
+#[test]
+fn test_decode_gif() {
+    // => Codes cleared every other one to keep them 3 bits wide
+    let gif = _gif(2, &[(0x00, 5, &[4, 1, 2, 5]), (0x01, 0, &[4, 3, 0, 5])]); 
+    let frames = image::decode(&gif).expect("[image_test::test_decode_gif] Cannot decode GIF"); 
+    assert_eq!(frames.len(), 2, "[ERROR] Incorrect number of frames"); 
+    assert_eq!((frames[0].image.pixels.clone(), frames[0].delay), (vec![RED, GREEN], Duration::from_millis(50)), "[ERROR] Incorrect first frame"); 
+    // => Transparent index keeps the previous frame, and a missing delay is the default
+    assert_eq!((frames[1].image.pixels.clone(), frames[1].delay), (vec![BLUE, GREEN], image::DEFAULT_GIF_DELAY), "[ERROR] Incorrect second frame"); 
+
+    let truncated = &gif[..gif.len() - 8]; 
+    assert!(image::decode(truncated).is_err(), "[ERROR] Truncated GIF accepted"); 
+}
+
+#[test]
+fn test_decode_gif_overlong_data() {
+    // => 4 indices for 3 pixels, the last word of 2 from the dictionary
+    let gif = _gif(3, &[(0x00, 0, &[4, 0, 1, 6, 5])]); 
+    if let Ok(frames) = image::decode(&gif) {
+        assert_eq!(frames[0].image.pixels.len(), 3, "[ERROR] Incorrect number of pixels"); 
+    }
+}
+
+#[test]
+fn test_resample_and_preview() {
+    // => Left half red, right half blue at half opacity
+    let pixels = (0..16).map(|i| if i % 4 < 2 { RED } else { [0, 0, 255, 128] }).collect(); 
+    let img = Image { width: 4, height: 4, pixels }; 
+    let small = img.resample(2, 1); 
+    assert_eq!(small.pixels, vec![RED, [0, 0, 128, 255]], "[ERROR] Incorrect downsampling"); 
+    assert_eq!(img.resample(8, 8).rgb(7, 7), 0x00_0080, "[ERROR] Incorrect upsampling"); 
+
+    let frames = [
+        image::Frame { image: img.clone(), delay: Duration::from_millis(40) }, 
+        image::Frame { image: img, delay: Duration::from_millis(40) }, 
+    ]; 
+    let sequence = Sequence::new(&frames, LedLayout::new(2, 1).with_origin(Origin::TopRight)); 
+    assert_eq!(sequence.frames[0].0, vec![0x00_0080, 0xFF_0000], "[ERROR] Incorrect LED colours"); 
+
+    let mut out = Vec::new(); 
+    sequence.write_preview(&mut out).expect("[image_test::test_resample_and_preview] Cannot write preview"); 
+    let lines: Vec<_> = String::from_utf8(out).unwrap().lines().map(String::from).collect(); 
+    assert_eq!(lines[1], r#"{"at_ms":40,"op":"WRITE","cmd":"LED","colors":[128,16711680]}"#, "[ERROR] Incorrect preview"); 
+
+    let mut out = Vec::new(); 
+    sequence.render(&mut out).expect("[image_test::test_resample_and_preview] Cannot render preview"); 
+    let out = String::from_utf8(out).unwrap(); 
+    assert!(out.starts_with("Frame 1/2, 40 ms\n"), "[ERROR] Missing frame header: {out:?}"); 
+    assert!(out.contains("\x1b[38;2;255;0;0m"), "[ERROR] Red LED not drawn: {out:?}"); 
+}
+
+#[test]
+fn test_run_on_simulator() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[image_test::test_run_on_simulator] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    let bmp = _bmp(2, -2, 24, &[], &[0, 0, 255, 255, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0]); 
+    let frames = image::decode(&bmp).expect("[image_test::test_run_on_simulator] Cannot decode image"); 
+    let sequence = Sequence::new(&frames, LedLayout::new(2, 2).with_order(Order::Serpentine)); 
+    sequence.run(&mut comm, &Interrupt::default(), true).expect("[image_test::test_run_on_simulator] Cannot show image"); 
+    assert_eq!(comm.board_state().leds, vec![0xFF_0000, 0x00_00FF, 0, 0x00_FF00], "[ERROR] LEDs not set in serpentine order"); 
+}
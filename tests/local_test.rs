@@ -2,6 +2,7 @@
 
 extern crate serial_communicator; 
 
+use std::io::{ErrorKind, Write};
 use std::time::Duration;
 use serialport::{SerialPort, TTYPort, Result}; 
 
@@ -191,4 +192,38 @@ fn test_ttyport_pairs() {
     // String and &str
     _write_str_raw_read(&mut tx, &mut rx); 
     _write_str_append_read(&mut tx, &mut rx); 
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_deadline_reads() {
+    let (mut tx, mut rx) = _set_up()
+        .expect("[local_test::set_up] Cannot create pseudo TTY ports");
+    let timeout = Duration::from_millis(50); 
+    let mut buf: Vec<u8> = Vec::new(); 
+
+    // Exact
+    write_str_raw(&mut tx, TEST_STR_LF)
+        .expect("[local_test::test_deadline_reads] Cannot write to `tx`"); 
+    read_exact_into(&mut rx, &mut buf, 4, timeout)
+        .expect("[local_test::test_deadline_reads] Cannot read exact at `rx`"); 
+    assert_eq!(&buf, &TEST_STR_LF.as_bytes()[..4], "[ERROR] `read_exact_into` read incorrect bytes"); 
+
+    // Until, leaving nothing behind
+    read_until_byte_into(&mut rx, &mut buf, NEWLINE, timeout)
+        .expect("[local_test::test_deadline_reads] Cannot read until byte at `rx`"); 
+    assert_eq!(&buf, &TEST_STR_LF.as_bytes()[4..], "[ERROR] `read_until_byte_into` read incorrect bytes"); 
+
+    // Frame
+    tx.write_all(&[3, 0xca, 0xfe, 0xba, 0xbe])
+        .expect("[local_test::test_deadline_reads] Cannot write to `tx`"); 
+    read_frame_into(&mut rx, &mut buf, timeout)
+        .expect("[local_test::test_deadline_reads] Cannot read frame at `rx`"); 
+    assert_eq!(&buf, &[0xca, 0xfe, 0xba], "[ERROR] `read_frame_into` read incorrect payload"); 
+
+    // Time-out on short read
+    match read_exact_into(&mut rx, &mut buf, 2, timeout) {
+        Err(e) => assert_eq!(e.kind(), ErrorKind::TimedOut, "[ERROR] Expected time-out on short read"), 
+        Ok(_)  => panic!("[local_test::test_deadline_reads] Short read did not time out"), 
+    }
+    assert_eq!(&buf, &[0xbe], "[ERROR] Short read did not keep bytes read so far"); 
+}
@@ -0,0 +1,129 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::io; 
+use std::thread::sleep; 
+use std::time::Duration; 
+
+use serial_communicator::Request; 
+use serial_communicator::board::BoardState; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::safety::{DutyBudget, SafetyGuard, SafetyLimits, SafetyMode, Violation}; 
+use serial_communicator::simulator; 
+
+fn _cell(x: f32, y: f32, on: bool) -> MagnetCell {
+    MagnetCell { x, y, on }
+}
+
+fn _bounded(mode: SafetyMode) -> SafetyLimits {
+    SafetyLimits { x: Some((0.0, 100.0)), y: Some((0.0, 50.0)), mode, ..SafetyLimits::default() }
+}
+
+#[test]
+fn test_out_of_bounds() {
+    let mut guard = SafetyGuard::new(_bounded(SafetyMode::Reject)); 
+    let mut cells = vec![_cell(10.0, 10.0, true), _cell(10.0, 60.0, false)]; 
+    let err = guard.check(&mut cells).unwrap_err(); 
+    assert_eq!(err.rule(), "y", "[ERROR] Incorrect rule reported"); 
+    assert!(matches!(err, Violation::OutOfBounds { cell: 1, .. }), "[ERROR] Incorrect cell reported"); 
+
+    let mut guard = SafetyGuard::new(_bounded(SafetyMode::Clamp)); 
+    let mut cells = vec![_cell(-5.0, 10.0, true), _cell(10.0, 60.0, false)]; 
+    let clamped = guard.check(&mut cells).expect("[safety_test::test_out_of_bounds] Clamp rejected"); 
+    assert_eq!(clamped.len(), 2, "[ERROR] Incorrect number of clamped violations"); 
+    assert_eq!(cells, vec![_cell(0.0, 10.0, true), _cell(10.0, 50.0, false)], "[ERROR] Incorrect clamped cells"); 
+}
+
+#[test]
+fn test_non_finite_always_rejected() {
+    for v in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+        let mut guard = SafetyGuard::new(_bounded(SafetyMode::Clamp)); 
+        let mut cells = vec![_cell(1.0, 1.0, false), _cell(v, 1.0, false)]; 
+        assert_eq!(guard.check(&mut cells), Err(Violation::NonFinite { cell: 1 }), "[ERROR] {v} accepted"); 
+    }
+    let mut guard = SafetyGuard::new(SafetyLimits::default()); 
+    assert!(guard.check(&mut [_cell(f32::NAN, 0.0, false)]).is_err(), "[ERROR] NaN accepted without limits"); 
+}
+
+#[test]
+fn test_max_on() {
+    let limits = SafetyLimits { max_on: Some(2), ..SafetyLimits::default() }; 
+    let mut cells: Vec<MagnetCell> = (0..4).map(|i| _cell(i as f32, 0.0, true)).collect(); 
+    let err = SafetyGuard::new(limits.clone()).check(&mut cells.clone()).unwrap_err(); 
+    assert_eq!(err, Violation::TooManyOn { on: 4, max: 2 }, "[ERROR] Incorrect violation"); 
+
+    let mut guard = SafetyGuard::new(SafetyLimits { mode: SafetyMode::Clamp, ..limits }); 
+    guard.check(&mut cells).expect("[safety_test::test_max_on] Clamp rejected"); 
+    let on: Vec<bool> = cells.iter().map(|c| c.on).collect(); 
+    assert_eq!(on, vec![true, true, false, false], "[ERROR] Incorrect magnets turned off"); 
+}
+
+#[test]
+fn test_duty_budget() {
+    let limits = SafetyLimits {
+        duty: Some(DutyBudget { max_on_ms: 100, duty_cycle: 0.5 }), 
+        ..SafetyLimits::default()
+    }; 
+    let mut guard = SafetyGuard::new(limits); 
+    let magnet = _cell(3.0, 4.0, true); 
+    guard.check(&mut [magnet]).expect("[safety_test::test_duty_budget] Cold magnet rejected"); 
+    sleep(Duration::from_millis(150)); 
+    let err = guard.check(&mut [magnet, _cell(5.0, 5.0, true)]).unwrap_err(); 
+    assert!(matches!(err, Violation::DutyExceeded { cell: 0, .. }), "[ERROR] Hot magnet accepted: {err:?}"); 
+
+    // => Cools down once off
+    guard.check(&mut [_cell(3.0, 4.0, false)]).expect("[safety_test::test_duty_budget] Turning off rejected"); 
+    sleep(Duration::from_millis(200)); 
+    assert!(guard.heat_ms(&magnet) < 100.0, "[ERROR] Magnet not cooled down"); 
+    guard.check(&mut [magnet]).expect("[safety_test::test_duty_budget] Cooled magnet rejected"); 
+}
+
+#[test]
+fn test_delta_checked_against_board() {
+    let limits = SafetyLimits { max_on: Some(1), mode: SafetyMode::Clamp, ..SafetyLimits::default() }; 
+    let board = |on: [bool; 2]| BoardState {
+        magnets: vec![_cell(0.0, 0.0, on[0]), _cell(1.0, 0.0, on[1])], 
+        leds: Vec::new(), 
+        synced: true, 
+    }; 
+
+    let mut guard = SafetyGuard::new(limits.clone()); 
+    let delta = Command::MagnetDelta { cells: vec![(1, _cell(1.0, 0.0, true))] }.encode(); 
+    let (instr, clamped) = guard.guard(&delta, &board([true, false]))
+        .expect("[safety_test::test_delta_checked_against_board] Clamp rejected"); 
+    assert_eq!(clamped.len(), 1, "[ERROR] Excess magnet not reported"); 
+    assert_eq!(
+        instr, 
+        Command::MagnetDelta { cells: vec![(1, _cell(1.0, 0.0, false))] }.encode(), 
+        "[ERROR] Incorrect clamped delta"
+    ); 
+
+    // => Magnet to turn off lies outside the delta
+    let mut guard = SafetyGuard::new(limits); 
+    let delta = Command::MagnetDelta { cells: vec![(0, _cell(0.0, 0.0, true))] }.encode(); 
+    let (instr, _) = guard.guard(&delta, &board([false, true]))
+        .expect("[safety_test::test_delta_checked_against_board] Clamp rejected"); 
+    assert_eq!(
+        instr, 
+        Command::Magnet { cells: vec![_cell(0.0, 0.0, true), _cell(1.0, 0.0, false)] }.encode(), 
+        "[ERROR] Clamped delta not sent in full"
+    ); 
+}
+
+#[test]
+fn test_communicator_rejects() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[safety_test::test_communicator_rejects] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME)
+        .with_safety(SafetyGuard::new(_bounded(SafetyMode::Reject))); 
+
+    let cells = vec![_cell(10.0, 10.0, true), _cell(200.0, 10.0, true)]; 
+    let err = comm.execute(&Request::from(&Command::Magnet { cells })).unwrap_err(); 
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "[ERROR] Incorrect error kind"); 
+    assert!(err.to_string().contains("`x`"), "[ERROR] Rule not named in {err}"); 
+
+    comm.execute(&Request::from(&Command::Magnet { cells: vec![_cell(10.0, 10.0, true)] }))
+        .expect("[safety_test::test_communicator_rejects] Valid MAGNET rejected"); 
+}
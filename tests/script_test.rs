@@ -0,0 +1,99 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::io::Write;
+use std::time::Duration; 
+
+use serialport::TTYPort; 
+
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::format::Format; 
+use serial_communicator::script::{Runner, Script, ScriptErrorKind}; 
+use serial_communicator::simulator; 
+
+const TEST_SCRIPT: &str = "
+# Turn on a magnet, then check sensor reply
+SET X 1.5
+REPEAT 2 {
+    WRITE MAGNET $X ${ITER} true   # trailing comment
+    SLEEP 1
+}
+READ 5 TIMEOUT 50
+EXPECT SENSOR 1.0 WITHIN 0.01
+EXPECT 0x010000803f
+READ TIMEOUT 10
+EXPECT TIMEOUT
+"; 
+
+fn _run(src: &str, reply: &[u8]) -> Result<(), serial_communicator::script::ScriptError> {
+    let (tx, mut rx) = TTYPort::pair()
+        .expect("[script_test::run] Cannot create pseudo TTY ports"); 
+    let script = Script::parse(src)?; 
+    rx.write_all(reply)
+        .expect("[script_test::run] Cannot write reply to `rx`"); 
+
+    let mut comm = Communicator::new(Box::new(tx)); 
+    let mut out: Vec<u8> = Vec::new(); 
+    Runner::new(&mut comm, Format::Text, &mut out).run(&script)
+}
+
+#[test]
+fn test_script_passes() {
+    _run(TEST_SCRIPT, &[0x01, 0x00, 0x00, 0x80, 0x3f])
+        .expect("[script_test::test_script_passes] Script failed"); 
+}
+
+#[test]
+fn test_script_reports_failing_line() {
+    let e = _run(TEST_SCRIPT, &[0x20, 0x00, 0x00, 0x80, 0x3f])
+        .expect_err("[script_test::test_script_reports_failing_line] Script passed on wrong reply"); 
+    assert_eq!(e.kind, ScriptErrorKind::Expectation, "[ERROR] Incorrect error kind"); 
+    assert_eq!(e.line, 9, "[ERROR] Incorrect failing line"); 
+}
+
+#[test]
+fn test_script_syntax_errors() {
+    for (src, line) in [
+        ("READ\nREPEAT 2 {\nREAD\n", 2), 
+        ("READ\n}\n", 2), 
+        ("SLEEP\n", 1), 
+        ("WRITE NOTHING\n", 1), 
+        ("FROB 1\n", 1), 
+        ("READ\nSTATE now\n", 2), 
+        ("STOP\nANIMATE\n", 2), 
+    ] {
+        let e = Script::parse(src)
+            .expect_err("[script_test::test_script_syntax_errors] Accepted malformed script"); 
+        assert_eq!(e.kind, ScriptErrorKind::Syntax, "[ERROR] Incorrect error kind for {src:?}"); 
+        assert_eq!(e.line, line, "[ERROR] Incorrect failing line for {src:?}"); 
+    }
+}
+
+#[test]
+fn test_script_state() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[script_test::test_script_state] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+    let script = Script::parse("WRITE MAGNET 1.5 2 true\nREAD 1\nEXPECT ACK\nSTATE\nSTOP\n")
+        .expect("[script_test::test_script_state] Cannot parse script"); 
+    let mut out: Vec<u8> = Vec::new(); 
+    Runner::new(&mut comm, Format::Text, &mut out).run(&script)
+        .expect("[script_test::test_script_state] Script failed"); 
+    let out = String::from_utf8(out).unwrap(); 
+    let state = out.lines().last().unwrap_or_default(); 
+    assert!(state.contains(r#""magnets":[{"x":1.5,"y":2.0,"on":true}]"#), "[ERROR] Incorrect state written: {out:?}"); 
+}
+
+#[test]
+fn test_script_hex_colours_not_comments() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[script_test::test_script_hex_colours_not_comments] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+    let script = Script::parse("# LEDs\nWRITE LED #ff0000 #00FF00 # red, green\nREAD 1\nEXPECT ACK\n")
+        .expect("[script_test::test_script_hex_colours_not_comments] Cannot parse script"); 
+    let mut out: Vec<u8> = Vec::new(); 
+    Runner::new(&mut comm, Format::Text, &mut out).run(&script)
+        .expect("[script_test::test_script_hex_colours_not_comments] Script failed"); 
+    assert_eq!(comm.board_state().leds, vec![0xFF_0000, 0x00_FF00], "[ERROR] Colours taken for comments"); 
+}
@@ -0,0 +1,199 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serialport::TTYPort; 
+use serial_communicator::{ReadSpec, Request}; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::{
+    Communicator, Connector, HeartbeatPolicy, LinkEvent, Outcome, PendingPolicy, ReconnectPolicy
+}; 
+use serial_communicator::response::Decoded; 
+use serial_communicator::safety::{SafetyGuard, SafetyLimits}; 
+use serial_communicator::shutdown::SafeState; 
+use serial_communicator::simulator::{self, Firmware}; 
+
+#[test]
+fn test_firmware_tracks_magnets() {
+    let mut firmware = Firmware::default(); 
+    let cells = vec![
+        MagnetCell { x: 1.0, y: 2.0, on: true }, 
+        MagnetCell { x: 3.0, y: 6.0, on: true }, 
+        MagnetCell { x: 9.0, y: 9.0, on: false }, 
+    ]; 
+    let reply = firmware.handle(&Command::Magnet { cells: cells.clone() }.encode()); 
+    assert_eq!(reply.map(|r| Decoded::from_bytes(&r)), Some(Decoded::Ack), "[ERROR] MAGNET not acknowledged"); 
+    assert_eq!(firmware.magnets, cells, "[ERROR] Incorrect magnet state after MAGNET"); 
+
+    let reply = firmware.handle(&Command::Sensor.encode())
+        .expect("[simulator_test::test_firmware_tracks_magnets] No reply to SENSOR"); 
+    assert_eq!(
+        Decoded::from_bytes(&reply), 
+        Decoded::Sensor { readings: vec![2.0, 2.0, 4.0] }, 
+        "[ERROR] Incorrect SENSOR readings"
+    ); 
+
+    firmware.handle(&Command::Quit.encode()); 
+    assert_eq!(firmware, Firmware::default(), "[ERROR] QUIT did not reset firmware state"); 
+}
+
+fn _unplugged_communicator(pending: PendingPolicy) -> Communicator {
+    // Writing to a pty whose device side is closed fails, as after a USB glitch
+    let (host, device) = TTYPort::pair().expect("[simulator_test::unplugged_communicator] Cannot open pty pair"); 
+    drop(device); 
+    let connector: Connector = Box::new(|| Ok(simulator::spawn(Duration::from_secs(1))?.0)); 
+    let policy = ReconnectPolicy {
+        pending, 
+        give_up_after: Duration::from_secs(2), 
+        retry_interval: Duration::from_millis(50), 
+    }; 
+    Communicator::with_device_name(Box::new(host), simulator::DEVICE_NAME).with_reconnect(connector, policy)
+}
+
+#[test]
+fn test_reconnect_queues_pending_request() {
+    let mut comm = _unplugged_communicator(PendingPolicy::Queue); 
+    comm.execute(&Request::from(&Command::Sensor))
+        .expect("[simulator_test::test_reconnect_queues_pending_request] Request not run after reconnection"); 
+    let events = comm.take_events(); 
+    assert!(matches!(events[..], [LinkEvent::Disconnected { .. }, LinkEvent::Connected { .. }]), "[ERROR] Incorrect link events {events:?}"); 
+
+    match comm.execute(&Request::Read(ReadSpec::default())) {
+        Ok(Outcome::Received(res)) => assert!(matches!(res.decoded, Decoded::Sensor { .. }), "[ERROR] Expected SENSOR reply"), 
+        _ => panic!("[simulator_test::test_reconnect_queues_pending_request] No reply after reconnection"), 
+    }
+}
+
+#[test]
+fn test_rejected_request_keeps_link() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[simulator_test::test_rejected_request_keeps_link] Cannot spawn simulator"); 
+    let connector: Connector = Box::new(|| Ok(simulator::spawn(Duration::from_secs(1))?.0)); 
+    let policy = ReconnectPolicy { pending: PendingPolicy::Queue, ..ReconnectPolicy::default() }; 
+    let limits = SafetyLimits { x: Some((0.0, 10.0)), ..SafetyLimits::default() }; 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME)
+        .with_reconnect(connector, policy)
+        .with_safety(SafetyGuard::new(limits)); 
+
+    let cell = MagnetCell { x: 50.0, y: 50.0, on: true }; 
+    let err = comm.execute(&Request::from(&Command::Magnet { cells: vec![cell] }))
+        .expect_err("[simulator_test::test_rejected_request_keeps_link] Unsafe MAGNET accepted"); 
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "[ERROR] Incorrect error kind"); 
+    assert!(comm.is_connected(), "[ERROR] Disconnected by rejected request"); 
+    let events = comm.take_events(); 
+    assert!(events.is_empty(), "[ERROR] Link events on rejected request: {events:?}"); 
+}
+
+#[test]
+fn test_reconnect_fails_pending_request() {
+    let mut comm = _unplugged_communicator(PendingPolicy::Fail); 
+    assert!(comm.execute(&Request::from(&Command::Sensor)).is_err(), "[ERROR] Pending request not failed"); 
+    assert!(!comm.is_connected(), "[ERROR] Still connected after failure"); 
+
+    // Next request reconnects
+    comm.execute(&Request::from(&Command::Sensor))
+        .expect("[simulator_test::test_reconnect_fails_pending_request] Cannot reconnect on next request"); 
+    assert_eq!(comm.take_events().len(), 2, "[ERROR] Expected disconnect and connect events"); 
+}
+
+#[test]
+fn test_safe_state_acknowledged() {
+    let (port, _) = simulator::spawn(Duration::from_secs(1))
+        .expect("[simulator_test::test_safe_state_acknowledged] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME)
+        .with_safe_state(SafeState::default()); 
+    comm.enter_safe_state()
+        .expect("[simulator_test::test_safe_state_acknowledged] Safe state not acknowledged"); 
+    // Already run, so a no-op
+    comm.enter_safe_state()
+        .expect("[simulator_test::test_safe_state_acknowledged] Safe state run twice"); 
+}
+
+#[test]
+fn test_safe_state_unacknowledged() {
+    let (port, _) = simulator::spawn(Duration::from_secs(1))
+        .expect("[simulator_test::test_safe_state_unacknowledged] Cannot spawn simulator"); 
+    // Simulator does not reply to SENSOR with ACK
+    let safe_state = SafeState { sequence: vec![Command::Sensor.encode()], ack_timeout: Duration::from_millis(200) }; 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME).with_safe_state(safe_state); 
+    assert!(comm.enter_safe_state().is_err(), "[ERROR] Non-ACK reply accepted"); 
+}
+
+fn _active_magnets(comm: &mut Communicator) -> f32 {
+    comm.execute(&Request::from(&Command::Sensor))
+        .expect("[simulator_test::active_magnets] Cannot write SENSOR"); 
+    match comm.execute(&Request::Read(ReadSpec::default())) {
+        Ok(Outcome::Received(res)) => match res.decoded {
+            Decoded::Sensor { readings } => readings[0], 
+            d => panic!("[simulator_test::active_magnets] Expected SENSOR reply, got {d:?}"), 
+        }, 
+        _ => panic!("[simulator_test::active_magnets] No reply to SENSOR"), 
+    }
+}
+
+fn _magnet_on_simulator(heartbeat: HeartbeatPolicy) -> Communicator {
+    let (port, _) = simulator::spawn(Duration::from_secs(1))
+        .expect("[simulator_test::magnet_on_simulator] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME).with_heartbeat(heartbeat); 
+    comm.execute(&Request::from(&Command::Magnet { cells: vec![MagnetCell { x: 1.0, y: 1.0, on: true }] }))
+        .expect("[simulator_test::magnet_on_simulator] Cannot write MAGNET"); 
+    comm.execute(&Request::Read(ReadSpec::default()))
+        .expect("[simulator_test::magnet_on_simulator] MAGNET not acknowledged"); 
+    comm
+}
+
+#[test]
+fn test_heartbeat_keeps_watchdog_fed() {
+    let mut comm = _magnet_on_simulator(HeartbeatPolicy::default()); 
+    comm.idle_for(simulator::WATCHDOG_TIMEOUT * 3)
+        .expect("[simulator_test::test_heartbeat_keeps_watchdog_fed] Heartbeat failed"); 
+    assert_eq!(_active_magnets(&mut comm), 1.0, "[ERROR] Watchdog tripped despite heartbeats"); 
+    assert!(comm.take_events().is_empty(), "[ERROR] Live link reported stale"); 
+}
+
+#[test]
+fn test_watchdog_trips_without_heartbeat() {
+    let mut comm = _magnet_on_simulator(HeartbeatPolicy::default()); 
+    comm.execute(&Request::from(&Command::Heartbeat))
+        .expect("[simulator_test::test_watchdog_trips_without_heartbeat] Cannot arm watchdog"); 
+    comm.execute(&Request::Read(ReadSpec::default()))
+        .expect("[simulator_test::test_watchdog_trips_without_heartbeat] No HEARTBEAT reply"); 
+    // Host stalls
+    std::thread::sleep(simulator::WATCHDOG_TIMEOUT * 2); 
+    assert_eq!(_active_magnets(&mut comm), 0.0, "[ERROR] Watchdog did not trip"); 
+}
+
+#[test]
+fn test_heartbeat_after_unread_ack() {
+    let mut comm = _magnet_on_simulator(HeartbeatPolicy::default()); 
+    comm.idle_for(Duration::from_millis(300))
+        .expect("[simulator_test::test_heartbeat_after_unread_ack] Cannot arm watchdog"); 
+    // ACK left unread while idle
+    let cell = MagnetCell { x: 2.0, y: 2.0, on: true }; 
+    comm.execute(&Request::from(&Command::Magnet { cells: vec![cell] }))
+        .expect("[simulator_test::test_heartbeat_after_unread_ack] Cannot write MAGNET"); 
+    comm.idle_for(Duration::from_millis(1200))
+        .expect("[simulator_test::test_heartbeat_after_unread_ack] Heartbeat failed"); 
+    assert!(comm.take_events().is_empty(), "[ERROR] Live link reported stale"); 
+    assert_eq!(comm.board_state().magnets, vec![cell], "[ERROR] Held ACK not applied to board state"); 
+
+    match comm.execute(&Request::Read(ReadSpec::default())) {
+        Ok(Outcome::Received(res)) => assert_eq!(res.decoded, Decoded::Ack, "[ERROR] Held ACK not read first"), 
+        res => panic!("[simulator_test::test_heartbeat_after_unread_ack] No held ACK, got {res:?}"), 
+    }
+    assert_eq!(_active_magnets(&mut comm), 1.0, "[ERROR] Watchdog tripped despite heartbeats"); 
+}
+
+#[test]
+fn test_silent_link_reported_stale() {
+    let (host, _device) = TTYPort::pair()
+        .expect("[simulator_test::test_silent_link_reported_stale] Cannot open pty pair"); 
+    let policy = HeartbeatPolicy { stale_after: Duration::from_millis(300), ..HeartbeatPolicy::default() }; 
+    let mut comm = Communicator::with_device_name(Box::new(host), "silent").with_heartbeat(policy); 
+    comm.idle_for(Duration::from_millis(600))
+        .expect("[simulator_test::test_silent_link_reported_stale] Heartbeat failed"); 
+    let events = comm.take_events(); 
+    assert!(matches!(events[..], [LinkEvent::Stale { .. }]), "[ERROR] Incorrect link events {events:?}"); 
+}
@@ -0,0 +1,98 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::Duration; 
+
+use serial_communicator::{ReadMode, ReadSpec, Request}; 
+use serial_communicator::command::{Command, MagnetCell}; 
+use serial_communicator::communicator::Communicator; 
+use serial_communicator::format::Format; 
+use serial_communicator::response::{Decoded, Response}; 
+use serial_communicator::shutdown::Interrupt; 
+use serial_communicator::simulator; 
+use serial_communicator::stream::{Buffer, DropPolicy, Item, SensorStream, Source}; 
+
+fn _reading(n: u8) -> Item {
+    Item::Reading(Response::new(simulator::DEVICE_NAME, vec![n])) 
+}
+
+fn _drain(buffer: &Buffer) -> Vec<u8> {
+    buffer.close(); 
+    std::iter::from_fn(|| buffer.pop())
+        .map(|i| match i {
+            Item::Reading(r) => r.raw[0], 
+            i => panic!("[stream_test::drain] Unexpected item {i:?}"), 
+        })
+        .collect()
+}
+
+#[test]
+fn test_buffer_drop_policies() {
+    let interrupt = Interrupt::default(); 
+    let oldest = Buffer::new(2, DropPolicy::Oldest); 
+    let newest = Buffer::new(2, DropPolicy::Newest); 
+    for n in 1..=3 {
+        assert_eq!(oldest.push(_reading(n), &interrupt), n == 3, "[ERROR] Incorrect drop when pushing {n}"); 
+        assert_eq!(newest.push(_reading(n), &interrupt), n == 3, "[ERROR] Incorrect drop when pushing {n}"); 
+    }
+    assert_eq!(_drain(&oldest), vec![2, 3], "[ERROR] Oldest reading not dropped"); 
+    assert_eq!(_drain(&newest), vec![1, 2], "[ERROR] Newest reading not dropped"); 
+}
+
+fn _stream(source: Source) -> Vec<serde_json::Value> {
+    let (port, _) = simulator::spawn(Duration::from_secs(1))
+        .expect("[stream_test::stream] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+    let cells = vec![MagnetCell { x: 3.0, y: 4.0, on: true }]; 
+    comm.execute(&Request::from(&Command::Magnet { cells }))
+        .expect("[stream_test::stream] Cannot write MAGNET"); 
+    comm.read(&Default::default())
+        .expect("[stream_test::stream] MAGNET not acknowledged"); 
+
+    let mut out: Vec<u8> = Vec::new(); 
+    let mut s = SensorStream::new(&mut comm, source, Duration::from_millis(20))
+        .with_sensor_reply(ReadSpec { mode: ReadMode::Exact(13), timeout: Some(Duration::from_millis(200)) })
+        .with_count(5); 
+    s.run(Format::Jsonl, &mut out, &Interrupt::default())
+        .expect("[stream_test::stream] Stream failed"); 
+    assert_eq!(s.stats().errors, 0, "[ERROR] Readings failed"); 
+    String::from_utf8(out).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("[stream_test::stream] Invalid JSON line"))
+        .collect()
+}
+
+#[test]
+fn test_poll_stream() {
+    let readings = _stream(Source::Poll); 
+    assert_eq!(readings.len(), 5, "[ERROR] Expected 5 readings, got {readings:?}"); 
+    for r in readings {
+        assert!(r["timestamp"].as_u64().is_some(), "[ERROR] Reading not timestamped"); 
+        assert_eq!(r["decoded"], serde_json::json!({"kind": "SENSOR", "readings": [1.0, 3.0, 4.0]})); 
+    }
+}
+
+#[test]
+fn test_push_stream() {
+    let readings = _stream(Source::Push); 
+    assert_eq!(readings.len(), 5, "[ERROR] Expected 5 readings, got {readings:?}"); 
+    for r in readings {
+        assert_eq!(r["decoded"], serde_json::json!({"kind": "SENSOR", "readings": [1.0, 3.0, 4.0]})); 
+    }
+}
+
+#[test]
+fn test_stream_command_matches_text_grammar() {
+    let text = Request::try_from("WRITE STREAM 500")
+        .expect("[stream_test] Cannot parse STREAM text request"); 
+    assert!(
+        Request::from(&Command::Stream { interval_ms: 500 }) == text, 
+        "[ERROR] Typed STREAM command encoded differently from text grammar"
+    ); 
+    assert_eq!(
+        Decoded::from_bytes(&simulator::Firmware::default().handle(&Command::Stream { interval_ms: 0 }.encode()).unwrap()), 
+        Decoded::Ack, 
+        "[ERROR] STREAM not acknowledged"
+    ); 
+}
@@ -0,0 +1,105 @@
+#![cfg(unix)]
+
+extern crate serial_communicator; 
+
+use std::time::{Duration, Instant}; 
+
+use serial_communicator::Request; 
+use serial_communicator::board::decode_cells; 
+use serial_communicator::command::MagnetCell; 
+use serial_communicator::communicator::{Communicator, Outcome}; 
+use serial_communicator::simulator; 
+use serial_communicator::trajectory::{Interpolation, Trajectory}; 
+
+/// Position of the single magnet of each frame.
+fn _positions(trajectory: &Trajectory) -> Vec<(f32, f32)> {
+    trajectory.frames()
+        .iter()
+        .map(|(_, f)| decode_cells(&f[1..]))
+        .map(|cells| (cells[0].x, cells[0].y))
+        .collect()
+}
+
+fn _near(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+}
+
+#[test]
+fn test_parse_move() {
+    let req = Request::try_from("MOVE 0 0 10 5 20 0 OVER 500 SPLINE SPEED 40 EVERY 10")
+        .expect("[trajectory_test::test_parse_move] Cannot parse MOVE"); 
+    let Request::Move(t) = &req else { panic!("[ERROR] MOVE not parsed into Request::Move") }; 
+    assert_eq!(t.waypoints, vec![(0.0, 0.0), (10.0, 5.0), (20.0, 0.0)], "[ERROR] Incorrect waypoints"); 
+    assert_eq!(t.duration_ms, 500, "[ERROR] Incorrect duration"); 
+    assert_eq!(t.interpolation, Interpolation::Spline, "[ERROR] Incorrect interpolation"); 
+    assert_eq!((t.max_speed, t.max_accel, t.frame_ms), (Some(40.0), None, 10), "[ERROR] Incorrect options"); 
+    assert!(matches!(Request::try_from(req.to_string().as_str()), Ok(r) if r == req), "[ERROR] Display does not round-trip"); 
+
+    for line in [
+        "MOVE 0 0 OVER 100", 
+        "MOVE 0 0 1 OVER 100", 
+        "MOVE 0 0 1 1", 
+        "MOVE 0 0 1 1 OVER 100 SPEED -1", 
+        "MOVE 0 0 1 1 OVER 100 FAST", 
+    ] {
+        assert!(Request::try_from(line).is_err(), "[ERROR] Malformed {line} accepted"); 
+    }
+}
+
+#[test]
+fn test_linear_frames() {
+    let trajectory = Trajectory::new(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)], Duration::from_millis(200)); 
+    let frames = trajectory.frames(); 
+    assert_eq!(frames.len(), 11, "[ERROR] Incorrect number of 20 ms frames"); 
+    assert_eq!(frames.last().unwrap().0, Duration::from_millis(200), "[ERROR] Last frame not at end"); 
+
+    let positions = _positions(&trajectory); 
+    assert!(_near(positions[0], (0.0, 0.0)) && _near(positions[10], (10.0, 10.0)), "[ERROR] Incorrect ends"); 
+    // => Constant speed of 2 units per frame, turning at the middle waypoint
+    let turns = [positions[3], positions[5], positions[7]]; 
+    assert!(_near(turns[0], (6.0, 0.0)) && _near(turns[1], (10.0, 0.0)) && _near(turns[2], (10.0, 4.0)), "[ERROR] Incorrect positions {positions:?}"); 
+}
+
+#[test]
+fn test_limits_stretch_duration() {
+    // => Triangular profile of 2 * sqrt(100 / 100) seconds at best
+    let trajectory = Trajectory::new(vec![(0.0, 0.0), (100.0, 0.0)], Duration::from_secs(1)).with_max_accel(100.0); 
+    assert_eq!(trajectory.duration(), Duration::from_secs(2), "[ERROR] Acceleration limit not respected"); 
+    let positions = _positions(&trajectory); 
+    assert!(_near(positions[50], (50.0, 0.0)), "[ERROR] Not halfway at half time: {:?}", positions[50]); 
+    assert!(positions[1].0 < 0.1, "[ERROR] Not accelerating from rest: {:?}", positions[1]); 
+
+    let trajectory = Trajectory::new(vec![(0.0, 0.0), (100.0, 0.0)], Duration::from_secs(1)).with_max_speed(50.0); 
+    assert_eq!(trajectory.duration(), Duration::from_secs(2), "[ERROR] Speed limit not respected"); 
+
+    let trajectory = trajectory.with_max_accel(100.0); 
+    assert_eq!(trajectory.duration(), Duration::from_millis(2500), "[ERROR] Trapezoidal profile not respected"); 
+    let slow = Trajectory::new(vec![(0.0, 0.0), (100.0, 0.0)], Duration::from_secs(5)).with_max_speed(50.0); 
+    assert_eq!(slow.duration(), Duration::from_secs(5), "[ERROR] Duration within limits stretched"); 
+}
+
+#[test]
+fn test_spline_through_waypoints() {
+    let waypoints = vec![(0.0, 0.0), (10.0, 10.0), (20.0, 0.0)]; 
+    let trajectory = Trajectory::new(waypoints, Duration::from_secs(1)).with_interpolation(Interpolation::Spline); 
+    let positions = _positions(&trajectory); 
+    assert!(_near(positions[0], (0.0, 0.0)) && _near(positions[positions.len() - 1], (20.0, 0.0)), "[ERROR] Incorrect ends"); 
+    // => Symmetric path, so the middle waypoint is reached at half time
+    let middle = positions[positions.len() / 2]; 
+    assert!((middle.0 - 10.0).abs() < 0.1 && (middle.1 - 10.0).abs() < 0.1, "[ERROR] Middle waypoint missed: {middle:?}"); 
+    assert!(positions.windows(2).all(|p| p[1].0 >= p[0].0), "[ERROR] Path not smooth"); 
+}
+
+#[test]
+fn test_communicator_runs_move() {
+    let (port, _handle) = simulator::spawn(Duration::from_secs(1))
+        .expect("[trajectory_test::test_communicator_runs_move] Cannot spawn simulator"); 
+    let mut comm = Communicator::with_device_name(port, simulator::DEVICE_NAME); 
+
+    let req = Request::try_from("MOVE 0 0 10 0 OVER 200").unwrap(); 
+    let start = Instant::now(); 
+    let outcome = comm.execute(&req).expect("[trajectory_test::test_communicator_runs_move] Cannot run MOVE"); 
+    assert!(start.elapsed() >= Duration::from_millis(200), "[ERROR] Frames not timed"); 
+    assert!(matches!(outcome, Outcome::Written(_)), "[ERROR] Incorrect outcome"); 
+    assert_eq!(comm.board_state().magnets, vec![MagnetCell { x: 10.0, y: 0.0, on: true }], "[ERROR] Magnet not at end of trajectory"); 
+}